@@ -0,0 +1,67 @@
+//! Bakes build information into the binary, see `src/build_info.rs`. Values which cannot be
+//! determined, e.g. without git, are empty.
+
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    set("BUILD_GIT_SHA", output("git", &["rev-parse", "HEAD"]));
+    set(
+        "BUILD_GIT_TAG",
+        output("git", &["describe", "--tags", "--exact-match"]),
+    );
+    set("BUILD_RUSTC_VERSION", output(&rustc, &["--version"]));
+    set("BUILD_TIMESTAMP", timestamp());
+}
+
+fn set(name: &str, value: String) {
+    println!("cargo:rustc-env={name}={value}");
+}
+
+fn output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// The current time in RFC 3339 format in UTC, or `SOURCE_DATE_EPOCH` for reproducible builds.
+fn timestamp() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default()
+        });
+
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = secs / 86_400 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    let time = secs % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
@@ -0,0 +1,74 @@
+//! Access log: one event with target `access_log` per request, which is JSON with the `json`
+//! logging format, with method, route, i.e. the path template like `/users/:id`, status, latency,
+//! request and response size as far as known, client IP and request ID.
+//!
+//! Requests to routes configured in `sample_rates` are only logged with the respective
+//! probability, except for server errors.
+
+use crate::client_ip::client_ip;
+use crate::request_id::{self, RequestId};
+use crate::settings::AccessLogSettings;
+use axum::body::HttpBody;
+use axum::extract::MatchedPath;
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{info, Instrument};
+
+/// Middleware running the request within the span created by [request_id::make_span] and
+/// logging it afterwards, see [crate::access_log].
+pub async fn log_access<B>(
+    request: Request<B>,
+    next: Next<B>,
+    settings: Arc<AccessLogSettings>,
+) -> Response {
+    let span = request_id::make_span(&request);
+    if !settings.enabled {
+        return next.run(request).instrument(span).await;
+    }
+
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_owned());
+    let request_size = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let client_ip = client_ip(&request).map(|ip| ip.to_string());
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(ToOwned::to_owned);
+
+    let response = next.run(request).instrument(span.clone()).await;
+
+    let status = response.status();
+    let sample_rate = settings.sample_rates.get(&route).copied().unwrap_or(1.0);
+    if status.is_server_error() || sample_rate >= 1.0 || rand::random::<f64>() < sample_rate {
+        let response_size = response.body().size_hint().exact();
+        span.in_scope(|| {
+            info!(
+                target: "access_log",
+                method = method.as_str(),
+                route = route.as_str(),
+                status = status.as_u16(),
+                latency_millis = start.elapsed().as_secs_f64() * 1_000.0,
+                request_size,
+                response_size,
+                client_ip = client_ip.as_deref(),
+                request_id = request_id.as_deref(),
+                "Request completed"
+            )
+        });
+    }
+    response
+}
@@ -0,0 +1,179 @@
+//! Administrative endpoints, protected by basic authentication.
+
+use crate::error::{Error, Result};
+use crate::features::{Features, FlagStatus};
+use crate::maintenance::Maintenance;
+use crate::response_cache::ResponseCache;
+use crate::settings::MaintenanceSettings;
+use crate::state::AppState;
+use crate::telemetry::FilterHandle;
+use crate::webhooks::{Delivery, DeliveryStatus, Webhooks};
+use anyhow::Context;
+use axum::extract::{Extension, Path, Query};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, put};
+use axum::{AddExtensionLayer, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tower_http::auth::RequireAuthorizationLayer;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+/// Routes for `/admin/loglevel`: `GET` responds with the current filter directives and `PUT`
+/// replaces them with the ones from the request body, e.g. `info,bayer_axum=debug`.
+///
+/// Routes for `/admin/maintenance`: `GET` responds with the current [MaintenanceSettings] as JSON
+/// and `PUT` replaces them, e.g. with `{"enabled":true,"message":"Back at 10:00"}`.
+///
+/// Routes for `/admin/features`: `GET` responds with all feature flags and their overrides as
+/// JSON, `PUT /admin/features/:name` overrides the flag with the JSON boolean from the request body
+/// and `DELETE` removes the override.
+///
+/// Route for `/admin/cache`: `DELETE` purges the response cache, optionally only for the route
+/// pattern given as `route` query parameter, and responds with the number of purged responses.
+///
+/// Routes for `/admin/webhooks/deliveries`: `GET` responds with the most recent webhook
+/// deliveries and their attempts, optionally only those with the `status` query parameter, e.g.
+/// `failed`, and `GET /admin/webhooks/deliveries/:id` with a single one.
+pub fn routes(state: &AppState, filter_handle: FilterHandle, password: &str) -> Router {
+    Router::new()
+        .route("/admin/loglevel", get(get_loglevel).put(put_loglevel))
+        .route(
+            "/admin/maintenance",
+            get(get_maintenance).put(put_maintenance),
+        )
+        .route("/admin/features", get(get_features))
+        .route(
+            "/admin/features/:name",
+            put(put_feature).delete(delete_feature),
+        )
+        .route("/admin/cache", delete(delete_cache))
+        .route("/admin/webhooks/deliveries", get(get_deliveries))
+        .route("/admin/webhooks/deliveries/:id", get(get_delivery))
+        .layer(AddExtensionLayer::new(filter_handle))
+        .layer(AddExtensionLayer::new(state.maintenance.clone()))
+        .layer(AddExtensionLayer::new(state.features.clone()))
+        .layer(AddExtensionLayer::new(state.response_cache.clone()))
+        .layer(AddExtensionLayer::new(state.webhooks.clone()))
+        .layer(RequireAuthorizationLayer::basic(
+            &state.settings.admin.username,
+            password,
+        ))
+}
+
+async fn get_loglevel(Extension(filter_handle): Extension<FilterHandle>) -> Result<String> {
+    let filter = filter_handle
+        .with_current(|filter| filter.to_string())
+        .context("Cannot access logging filter")?;
+    Ok(filter)
+}
+
+async fn put_loglevel(
+    Extension(filter_handle): Extension<FilterHandle>,
+    filter: String,
+) -> Result<StatusCode> {
+    let filter = filter.trim();
+    let env_filter = EnvFilter::try_new(filter)
+        .map_err(|e| Error::Validation(format!("Invalid logging filter {filter}: {e}")))?;
+    filter_handle
+        .reload(env_filter)
+        .context("Cannot reload logging filter")?;
+    info!(filter, "Logging filter changed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_maintenance(
+    Extension(maintenance): Extension<Maintenance>,
+) -> Json<MaintenanceSettings> {
+    Json(maintenance.get())
+}
+
+async fn put_maintenance(
+    Extension(maintenance): Extension<Maintenance>,
+    Json(settings): Json<MaintenanceSettings>,
+) -> StatusCode {
+    info!(enabled = settings.enabled, "Maintenance mode changed");
+    maintenance.set(settings);
+    StatusCode::NO_CONTENT
+}
+
+async fn get_features(
+    Extension(features): Extension<Features>,
+) -> Json<BTreeMap<String, FlagStatus>> {
+    Json(features.list())
+}
+
+async fn put_feature(
+    Extension(features): Extension<Features>,
+    Path(name): Path<String>,
+    Json(enabled): Json<bool>,
+) -> Result<StatusCode> {
+    override_feature(&features, &name, Some(enabled))
+}
+
+async fn delete_feature(
+    Extension(features): Extension<Features>,
+    Path(name): Path<String>,
+) -> Result<StatusCode> {
+    override_feature(&features, &name, None)
+}
+
+fn override_feature(features: &Features, name: &str, enabled: Option<bool>) -> Result<StatusCode> {
+    if !features.set_override(name, enabled) {
+        return Err(Error::NotFound(format!("No feature flag {name}")));
+    }
+    info!(name, ?enabled, "Feature flag override changed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct PurgeParams {
+    route: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Purged {
+    purged: usize,
+}
+
+async fn delete_cache(
+    Extension(cache): Extension<ResponseCache>,
+    Query(params): Query<PurgeParams>,
+) -> Json<Purged> {
+    let purged = cache.purge(params.route.as_deref());
+    info!(route = ?params.route, purged, "Response cache purged");
+    Json(Purged { purged })
+}
+
+/// Maximum number of deliveries listed.
+const MAX_DELIVERIES: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct DeliveriesParams {
+    status: Option<DeliveryStatus>,
+}
+
+async fn get_deliveries(
+    Extension(webhooks): Extension<Webhooks>,
+    Query(params): Query<DeliveriesParams>,
+) -> Result<Json<Vec<Delivery>>> {
+    let deliveries = webhooks
+        .store()
+        .list(params.status, MAX_DELIVERIES)
+        .await
+        .context("Cannot list webhook deliveries")?;
+    Ok(Json(deliveries))
+}
+
+async fn get_delivery(
+    Extension(webhooks): Extension<Webhooks>,
+    Path(id): Path<String>,
+) -> Result<Json<Delivery>> {
+    webhooks
+        .store()
+        .get(&id)
+        .await
+        .context("Cannot get webhook delivery")?
+        .map(Json)
+        .ok_or_else(|| Error::NotFound(format!("No webhook delivery {id}")))
+}
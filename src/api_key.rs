@@ -0,0 +1,141 @@
+//! Authentication via API keys in the `x-api-key` header, optionally rate limited per key.
+
+use crate::auth::Claims;
+use crate::error::Error;
+use crate::metrics::increment_counter;
+use crate::rate_limit::RateLimiter;
+use crate::settings::{ApiKeySettings, RateLimitSettings};
+use crate::webhook_signature::VerifiedWebhook;
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+pub const X_API_KEY: &str = "x-api-key";
+
+/// Looks up API keys, e.g. in the configuration or in a database.
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync + 'static {
+    /// The [ApiKey] for the given key value, if it is known.
+    async fn lookup(&self, key: &str) -> Result<Option<ApiKey>>;
+}
+
+/// A known API key; its ID, not the key value, is used in logs and metrics.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub rate_limit: Option<RateLimitSettings>,
+}
+
+/// The ID of the authenticated API key, put into the request extensions for handlers.
+#[derive(Debug, Clone)]
+pub struct ApiKeyId(pub String);
+
+/// [ApiKeyStore] for the API keys from the configuration.
+#[derive(Debug, Default)]
+pub struct StaticApiKeyStore {
+    keys: HashMap<String, ApiKey>,
+}
+
+impl StaticApiKeyStore {
+    pub fn new(api_keys: &BTreeMap<String, ApiKeySettings>) -> Self {
+        let keys = api_keys
+            .iter()
+            .map(|(id, settings)| {
+                let api_key = ApiKey {
+                    id: id.to_owned(),
+                    rate_limit: settings.rate_limit.clone(),
+                };
+                (settings.key.expose().to_owned(), api_key)
+            })
+            .collect();
+        Self { keys }
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for StaticApiKeyStore {
+    async fn lookup(&self, key: &str) -> Result<Option<ApiKey>> {
+        Ok(self.keys.get(key).cloned())
+    }
+}
+
+/// Shared state of the [require_api_key] middleware.
+#[derive(Clone)]
+pub struct ApiKeyAuth {
+    store: Arc<dyn ApiKeyStore>,
+    limiter: Arc<RateLimiter<String>>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(store: impl ApiKeyStore) -> Self {
+        Self {
+            store: Arc::new(store),
+            limiter: Default::default(),
+        }
+    }
+}
+
+/// Middleware rejecting requests without a known API key with 401 and requests exceeding the rate
+/// limit of their API key with 429. Requests are counted as `api_key_requests_total` by key ID and
+/// outcome. Webhooks with a verified signature, see [crate::webhook_signature], and requests with a
+/// valid bearer token, see [crate::auth], are exempt.
+pub async fn require_api_key<B>(
+    mut request: Request<B>,
+    next: Next<B>,
+    auth: ApiKeyAuth,
+) -> Response {
+    let extensions = request.extensions();
+    if extensions.get::<VerifiedWebhook>().is_some() || extensions.get::<Claims>().is_some() {
+        return next.run(request).await;
+    }
+
+    let key = match request
+        .headers()
+        .get(X_API_KEY)
+        .and_then(|key| key.to_str().ok())
+    {
+        Some(key) => key,
+        None => {
+            return Error::Unauthorized(format!("Missing {X_API_KEY} header")).into_response();
+        }
+    };
+
+    let api_key = match auth.store.lookup(key).await {
+        Ok(Some(api_key)) => api_key,
+        Ok(None) => {
+            increment_counter(
+                "api_key_requests_total",
+                &[
+                    ("key_id", "unknown".to_string()),
+                    ("outcome", "rejected".to_string()),
+                ],
+            );
+            return Error::Unauthorized("Unknown API key".to_string()).into_response();
+        }
+        Err(e) => return Error::Internal(e.context("Cannot look up API key")).into_response(),
+    };
+
+    if let Some(rate_limit) = &api_key.rate_limit {
+        if let Err(retry_after) = auth.limiter.check(api_key.id.clone(), rate_limit) {
+            increment_counter(
+                "api_key_requests_total",
+                &[("key_id", api_key.id), ("outcome", "limited".to_string())],
+            );
+            return Error::TooManyRequests(retry_after).into_response();
+        }
+    }
+
+    increment_counter(
+        "api_key_requests_total",
+        &[
+            ("key_id", api_key.id.clone()),
+            ("outcome", "accepted".to_string()),
+        ],
+    );
+    request.extensions_mut().insert(ApiKeyId(api_key.id));
+    next.run(request).await
+}
@@ -0,0 +1,292 @@
+//! Audit logging of who did what when: handlers record actions via the [Audit] extractor, which
+//! knows the actor, i.e. the API key unless set explicitly, as well as request and tenant ID and
+//! the client address.
+//!
+//! Records are written to an [AuditSink], by default as JSON lines to the file configured as
+//! `audit.path` or else as events with target `audit` to the regular log. They are tamper-evident:
+//! sequence numbers are consecutive and each record contains the SHA-256 `hash` of itself with an
+//! empty `hash` and the `previous_hash`, such that removed or changed records break the chain.
+
+use crate::api_key::ApiKeyId;
+use crate::client_ip::ClientIp;
+use crate::error::Error;
+use crate::hmac::{sha256, to_hex};
+use crate::request_id::RequestId;
+use crate::state::AppState;
+use crate::tenancy::TenantId;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+use tracing::info;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuditRecord {
+    pub sequence: u64,
+    /// Unix time in milliseconds.
+    pub time: u64,
+    pub actor: String,
+    pub action: String,
+    pub resource: String,
+    pub outcome: AuditOutcome,
+    pub request_id: Option<String>,
+    pub tenant_id: Option<String>,
+    /// Omitted if unknown, keeping the hashes of records from before it was recorded valid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+/// Persists [AuditRecord]s, e.g. in a file or a database table.
+#[async_trait]
+pub trait AuditSink: Debug + Send + Sync + 'static {
+    async fn append(&self, record: &AuditRecord) -> Result<()>;
+
+    /// The last appended record, if any, to continue its chain after a restart.
+    async fn last(&self) -> Result<Option<AuditRecord>> {
+        Ok(None)
+    }
+}
+
+/// [AuditSink] logging records as events with target `audit`; as it cannot read the last record,
+/// its chain restarts with every process.
+#[derive(Debug, Default)]
+pub struct LogAuditSink;
+
+#[async_trait]
+impl AuditSink for LogAuditSink {
+    async fn append(&self, record: &AuditRecord) -> Result<()> {
+        let record = serde_json::to_string(record).context("Cannot serialize audit record")?;
+        info!(target: "audit", record = record.as_str());
+        Ok(())
+    }
+}
+
+/// [AuditSink] appending records as JSON lines to a file.
+#[derive(Debug)]
+pub struct FileAuditSink {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+impl FileAuditSink {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            file: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for FileAuditSink {
+    async fn append(&self, record: &AuditRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record).context("Cannot serialize audit record")?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        if file.is_none() {
+            let opened = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await
+                .with_context(|| format!("Cannot open audit log {}", self.path.display()))?;
+            *file = Some(opened);
+        }
+        let file = file.as_mut().expect("audit log is open");
+        file.write_all(&line)
+            .await
+            .context("Cannot write audit log")?;
+        file.flush().await.context("Cannot flush audit log")
+    }
+
+    async fn last(&self) -> Result<Option<AuditRecord>> {
+        let file = match File::open(&self.path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(anyhow!(e).context("Cannot open audit log")),
+        };
+        let mut lines = BufReader::new(file).lines();
+        let mut last = None;
+        while let Some(line) = lines.next_line().await.context("Cannot read audit log")? {
+            if !line.trim().is_empty() {
+                last = Some(line);
+            }
+        }
+        last.map(|line| serde_json::from_str(&line).context("Invalid last audit record"))
+            .transpose()
+    }
+}
+
+/// Cheap to clone, all clones share the [AuditSink] and the chain of records.
+#[derive(Debug, Clone)]
+pub struct Auditor {
+    sink: Arc<dyn AuditSink>,
+    /// Sequence number and hash of the last record, once initialized from the sink.
+    last: Arc<Mutex<Option<(u64, String)>>>,
+}
+
+impl Auditor {
+    pub fn new(sink: Arc<dyn AuditSink>) -> Self {
+        Self {
+            sink,
+            last: Default::default(),
+        }
+    }
+
+    /// Append a record for the given action to the chain.
+    pub async fn record(
+        &self,
+        actor: &str,
+        action: &str,
+        resource: &str,
+        outcome: AuditOutcome,
+        context: AuditContext,
+    ) -> Result<AuditRecord> {
+        // Holding the lock while appending keeps the records in sequence.
+        let mut last = self.last.lock().await;
+        if last.is_none() {
+            let record = self.sink.last().await?;
+            *last =
+                Some(record.map_or((0, String::new()), |record| (record.sequence, record.hash)));
+        }
+        let (sequence, previous_hash) = last.clone().expect("last record is initialized");
+
+        let mut record = AuditRecord {
+            sequence: sequence + 1,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_millis() as u64),
+            actor: actor.to_owned(),
+            action: action.to_owned(),
+            resource: resource.to_owned(),
+            outcome,
+            request_id: context.request_id,
+            tenant_id: context.tenant_id,
+            client_ip: context.client_ip,
+            previous_hash,
+            hash: String::new(),
+        };
+        record.hash = hash(&record)?;
+        self.sink.append(&record).await?;
+        *last = Some((record.sequence, record.hash.clone()));
+        Ok(record)
+    }
+}
+
+/// Where an action has been performed, e.g. in which request.
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub request_id: Option<String>,
+    pub tenant_id: Option<String>,
+    pub client_ip: Option<String>,
+}
+
+/// The SHA-256 of the JSON serialized record with an empty `hash`.
+pub fn hash(record: &AuditRecord) -> Result<String> {
+    let record = AuditRecord {
+        hash: String::new(),
+        ..record.clone()
+    };
+    let json = serde_json::to_vec(&record).context("Cannot serialize audit record")?;
+    Ok(to_hex(&sha256(&json)))
+}
+
+/// Extractor for recording audit records for the current request.
+///
+/// ```ignore
+/// async fn delete_user(audit: Audit, Path(id): Path<String>) -> Result<StatusCode> {
+///     delete(&id).await?;
+///     audit.success("user.delete", &format!("users/{id}")).await?;
+///     Ok(StatusCode::NO_CONTENT)
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Audit {
+    auditor: Auditor,
+    actor: String,
+    context: AuditContext,
+}
+
+impl Audit {
+    /// Use the given actor, e.g. the user of the session, instead of the API key.
+    pub fn with_actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = actor.into();
+        self
+    }
+
+    pub async fn success(&self, action: &str, resource: &str) -> Result<AuditRecord> {
+        self.record(action, resource, AuditOutcome::Success).await
+    }
+
+    pub async fn failure(&self, action: &str, resource: &str) -> Result<AuditRecord> {
+        self.record(action, resource, AuditOutcome::Failure).await
+    }
+
+    pub async fn record(
+        &self,
+        action: &str,
+        resource: &str,
+        outcome: AuditOutcome,
+    ) -> Result<AuditRecord> {
+        self.auditor
+            .record(&self.actor, action, resource, outcome, self.context.clone())
+            .await
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Audit
+where
+    B: Send,
+{
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let extensions = request
+            .extensions()
+            .ok_or_else(|| anyhow!("Extensions already taken"))?;
+        let auditor = extensions
+            .get::<AppState>()
+            .map(|state| state.auditor.clone())
+            .ok_or_else(|| anyhow!("AppState missing"))?;
+        let actor = extensions
+            .get::<ApiKeyId>()
+            .map_or_else(|| "anonymous".to_string(), |id| format!("api_key:{}", id.0));
+        let request_id = extensions
+            .get::<RequestId>()
+            .and_then(|id| id.header_value().to_str().ok())
+            .map(ToOwned::to_owned);
+        let tenant_id = extensions
+            .get::<TenantId>()
+            .map(|id| id.as_str().to_owned());
+        let client_ip = extensions
+            .get::<ClientIp>()
+            .map(|ClientIp(ip)| ip.to_string());
+        Ok(Self {
+            auditor,
+            actor,
+            context: AuditContext {
+                request_id,
+                tenant_id,
+                client_ip,
+            },
+        })
+    }
+}
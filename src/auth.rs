@@ -0,0 +1,377 @@
+//! Authentication via JWT bearer tokens (RFC 7519) in the `Authorization` header, signed with
+//! HS256 by one of the keys from the `auth` settings.
+//!
+//! RS256 and JWKS documents are not supported: verifying RSA signatures would have to be
+//! hand-rolled, because no crypto crate is available, and JWKS documents could only be fetched in
+//! cleartext, because the HTTP client has no TLS support, which would allow an on-path attacker
+//! to substitute the keys.
+//!
+//! The [authenticate] middleware rejects requests with invalid tokens with 401 and puts the
+//! [Claims] of valid ones into the request extensions. Requests without a token pass, handlers
+//! requiring one extract [Claims]. Tokens must have an `exp` claim and must match the configured
+//! issuer and audience, if any. Requests with tokens are counted as `bearer_tokens_total` by
+//! outcome.
+
+use crate::error::Error;
+use crate::hmac::{constant_time_eq, hmac_sha256};
+use crate::metrics::increment_counter;
+use crate::secret::Secret;
+use crate::settings::AuthSettings;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+const HS256: &str = "HS256";
+
+/// The claims of a valid token, put into the request extensions by the [authenticate]
+/// middleware. As an extractor it rejects requests without a token with 401.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Claims {
+    pub sub: Option<String>,
+    pub iss: Option<String>,
+    /// A single audience is given as a list with one element.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub aud: Vec<String>,
+    pub exp: u64,
+    pub nbf: Option<u64>,
+    pub iat: Option<u64>,
+    /// All other claims, e.g. `scope`.
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Claims
+where
+    B: Send,
+{
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        request
+            .extensions()
+            .ok_or_else(|| anyhow!("Extensions already taken"))?
+            .get::<Claims>()
+            .cloned()
+            .ok_or_else(|| Error::Unauthorized("Missing bearer token".to_string()))
+    }
+}
+
+/// Shared state of the [authenticate] middleware.
+#[derive(Clone)]
+pub struct JwtAuth {
+    keys: Arc<Vec<VerificationKey>>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: Duration,
+}
+
+impl JwtAuth {
+    /// `None` if no keys are configured.
+    pub fn new(settings: &AuthSettings) -> Option<Self> {
+        let keys = settings
+            .keys
+            .iter()
+            .filter_map(|(kid, key)| {
+                key.secret.as_ref().map(|secret| VerificationKey {
+                    kid: kid.to_owned(),
+                    secret: Secret::new(secret.expose().as_bytes().to_vec()),
+                })
+            })
+            .collect::<Vec<_>>();
+        (!keys.is_empty()).then(|| Self {
+            keys: Arc::new(keys),
+            issuer: settings.issuer.clone(),
+            audience: settings.audience.clone(),
+            leeway: settings.leeway,
+        })
+    }
+
+    /// The claims of the given token or the reason why it is invalid.
+    fn verify(&self, token: &str) -> Result<Claims, &'static str> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature)) if parts.next().is_none() => {
+                (header, payload, signature)
+            }
+            _ => return Err("malformed"),
+        };
+        let header = decode_json::<Header>(header).ok_or("malformed header")?;
+        if header.alg != HS256 {
+            return Err("unsupported algorithm");
+        }
+        // No extensions are supported, hence tokens marking any as critical must be rejected.
+        if header.crit.is_some() {
+            return Err("unsupported critical header");
+        }
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| "malformed signature")?;
+
+        // Without a key ID all keys are tried, otherwise only the one with it.
+        let message = &token.as_bytes()[..header_and_payload_len(token)];
+        let verified = self
+            .keys
+            .iter()
+            .filter(|key| header.kid.as_ref().map_or(true, |kid| *kid == key.kid))
+            .any(|key| constant_time_eq(&hmac_sha256(key.secret.expose(), message), &signature));
+        if !verified {
+            return Err("invalid signature");
+        }
+
+        let claims = decode_json::<Claims>(payload).ok_or("malformed claims")?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let leeway = self.leeway.as_secs();
+        if now > claims.exp.saturating_add(leeway) {
+            return Err("expired");
+        }
+        if claims
+            .nbf
+            .map_or(false, |nbf| now.saturating_add(leeway) < nbf)
+        {
+            return Err("not yet valid");
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.iss.as_ref() != Some(issuer) {
+                return Err("wrong issuer");
+            }
+        }
+        if let Some(audience) = &self.audience {
+            if !claims.aud.contains(audience) {
+                return Err("wrong audience");
+            }
+        }
+        Ok(claims)
+    }
+}
+
+/// Middleware rejecting requests with an invalid bearer token with 401 and putting the [Claims]
+/// of valid ones into the request extensions.
+pub async fn authenticate<B>(mut request: Request<B>, next: Next<B>, auth: JwtAuth) -> Response {
+    let token = match bearer_token(request.headers()) {
+        Some(token) => token.to_owned(),
+        None => return next.run(request).await,
+    };
+
+    match auth.verify(&token) {
+        Ok(claims) => {
+            increment_counter(
+                "bearer_tokens_total",
+                &[("outcome", "accepted".to_string())],
+            );
+            request.extensions_mut().insert(claims);
+            next.run(request).await
+        }
+        Err(reason) => {
+            increment_counter(
+                "bearer_tokens_total",
+                &[("outcome", "rejected".to_string())],
+            );
+            debug!(reason, "Rejecting bearer token");
+            Error::Unauthorized(format!("Invalid bearer token: {reason}")).into_response()
+        }
+    }
+}
+
+/// The JOSE header of a token.
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+    crit: Option<Value>,
+}
+
+struct VerificationKey {
+    kid: String,
+    secret: Secret<Vec<u8>>,
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let (scheme, token) = headers.get(AUTHORIZATION)?.to_str().ok()?.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then(|| token)
+}
+
+/// The signature is computed over the encoded header and payload, i.e. up to the last dot.
+fn header_and_payload_len(token: &str) -> usize {
+    token.rfind('.').unwrap_or_default()
+}
+
+fn decode_json<T>(part: &str) -> Option<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let json = base64::decode_config(part, base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(aud) => vec![aud],
+        OneOrMany::Many(aud) => aud,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::JwtKeySettings;
+    use serde_json::json;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn auth() -> JwtAuth {
+        let mut settings = AuthSettings {
+            issuer: Some("https://id.example.com".to_string()),
+            audience: Some("api".to_string()),
+            ..Default::default()
+        };
+        let key = JwtKeySettings {
+            secret: Some(Secret::new(SECRET.to_string())),
+        };
+        settings.keys.insert("k1".to_string(), key);
+        JwtAuth::new(&settings).unwrap()
+    }
+
+    fn token(header: Value, claims: Value, secret: &str) -> String {
+        let encode =
+            |value: Value| base64::encode_config(value.to_string(), base64::URL_SAFE_NO_PAD);
+        let message = format!("{}.{}", encode(header), encode(claims));
+        let signature = hmac_sha256(secret.as_bytes(), message.as_bytes());
+        format!(
+            "{message}.{}",
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    fn claims(exp_offset: i64) -> Value {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        json!({
+            "sub": "alice",
+            "iss": "https://id.example.com",
+            "aud": "api",
+            "exp": now + exp_offset,
+            "scope": "read",
+        })
+    }
+
+    fn rejection(token: &str) -> &'static str {
+        match auth().verify(token) {
+            Err(reason) => reason,
+            Ok(claims) => panic!("accepted: {claims:?}"),
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let header = json!({"alg": "HS256", "kid": "k1"});
+        let verified = auth().verify(&token(header, claims(60), SECRET)).unwrap();
+        assert_eq!(verified.sub.as_deref(), Some("alice"));
+        assert_eq!(verified.aud, vec!["api"]);
+        assert_eq!(verified.other["scope"], "read");
+
+        // Without a key ID all keys of the algorithm are tried.
+        let header = json!({"alg": "HS256"});
+        assert!(auth().verify(&token(header, claims(60), SECRET)).is_ok());
+
+        // Expired within the leeway.
+        let header = json!({"alg": "HS256", "kid": "k1"});
+        assert!(auth().verify(&token(header, claims(-30), SECRET)).is_ok());
+    }
+
+    #[test]
+    fn test_verify_invalid() {
+        let header = json!({"alg": "HS256", "kid": "k1"});
+        let valid = token(header.clone(), claims(60), SECRET);
+
+        assert_eq!(rejection("a.b"), "malformed");
+        assert_eq!(rejection(&format!("{valid}.")), "malformed");
+        assert_eq!(rejection(&format!("{valid}x")), "invalid signature");
+        let other_secret = "fedcba9876543210fedcba9876543210";
+        assert_eq!(
+            rejection(&token(header.clone(), claims(60), other_secret)),
+            "invalid signature"
+        );
+        let other_kid = json!({"alg": "HS256", "kid": "k2"});
+        assert_eq!(
+            rejection(&token(other_kid, claims(60), SECRET)),
+            "invalid signature"
+        );
+        let rs256 = json!({"alg": "RS256", "kid": "k1"});
+        assert_eq!(
+            rejection(&token(rs256, claims(60), SECRET)),
+            "unsupported algorithm"
+        );
+        let none = json!({"alg": "none"});
+        assert_eq!(
+            rejection(&token(none, claims(60), SECRET)),
+            "unsupported algorithm"
+        );
+        let crit = json!({"alg": "HS256", "kid": "k1", "crit": ["b64"]});
+        assert_eq!(
+            rejection(&token(crit, claims(60), SECRET)),
+            "unsupported critical header"
+        );
+        assert_eq!(
+            rejection(&token(header.clone(), claims(-120), SECRET)),
+            "expired"
+        );
+
+        let mut no_exp = claims(60);
+        no_exp.as_object_mut().unwrap().remove("exp");
+        assert_eq!(
+            rejection(&token(header.clone(), no_exp, SECRET)),
+            "malformed claims"
+        );
+        let mut other_audience = claims(60);
+        other_audience["aud"] = json!(["web"]);
+        assert_eq!(
+            rejection(&token(header.clone(), other_audience, SECRET)),
+            "wrong audience"
+        );
+        let mut other_issuer = claims(60);
+        other_issuer["iss"] = json!("https://evil.example.com");
+        assert_eq!(
+            rejection(&token(header, other_issuer, SECRET)),
+            "wrong issuer"
+        );
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+        headers.insert(AUTHORIZATION, "Bearer abc".parse().unwrap());
+        assert_eq!(bearer_token(&headers), Some("abc"));
+        headers.insert(AUTHORIZATION, "bearer  abc ".parse().unwrap());
+        assert_eq!(bearer_token(&headers), Some("abc"));
+        headers.insert(AUTHORIZATION, "Basic abc".parse().unwrap());
+        assert_eq!(bearer_token(&headers), None);
+        headers.insert(AUTHORIZATION, "Bearer ".parse().unwrap());
+        assert_eq!(bearer_token(&headers), None);
+    }
+}
@@ -0,0 +1,315 @@
+//! Settings values from AWS Secrets Manager or SSM Parameter Store, merged after the
+//! configuration files and before the environment variables, see [load].
+//!
+//! The names of the secrets or parameters below the configured prefix are the keys with `/` as
+//! separator, e.g. `/bayer-axum/prod/admin/password` for `admin.password` with the prefix
+//! `/bayer-axum/prod`. Secrets holding JSON objects, e.g. key/value pairs created in the AWS
+//! console, are merged below their key, hence a secret named like the prefix can hold any
+//! settings.
+//!
+//! As the HTTP client has no TLS support, the secret values are received in cleartext, hence
+//! `aws_secrets.endpoint` must be on the loopback interface, e.g. an egress proxy sidecar
+//! originating TLS towards AWS, such that they never cross the network unencrypted.
+
+use crate::aws::{self, payload_hash, Credentials, CredentialsProvider};
+use crate::has_path_prefix;
+use crate::settings::{AwsSecretsSettings, AwsSecretsSource, AwsSettings};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{body, Body, Client, Request, Uri};
+use serde_json::{json, Map, Value};
+use std::net::IpAddr;
+use std::time::SystemTime;
+use tokio::time::timeout;
+use tracing::warn;
+
+const CONTENT_TYPE_JSON: &str = "application/x-amz-json-1.1";
+
+const X_AMZ_TARGET: &str = "x-amz-target";
+
+/// Read all secrets or parameters below the configured prefix into a JSON object of settings
+/// values; SSM parameters are decrypted.
+pub async fn load(settings: &AwsSecretsSettings, aws_settings: &AwsSettings) -> Result<Value> {
+    let source = settings
+        .source
+        .ok_or_else(|| anyhow!("aws_secrets.source not defined"))?;
+    let endpoint = settings
+        .endpoint
+        .as_deref()
+        .ok_or_else(|| anyhow!("aws_secrets.endpoint not defined"))?;
+    // Checked here as well, because the settings are only validated after being loaded.
+    ensure!(
+        endpoint
+            .parse::<Uri>()
+            .map_or(false, |uri| is_loopback(&uri)),
+        "aws_secrets.endpoint must be on the loopback interface, e.g. http://localhost:4566"
+    );
+    let credentials = CredentialsProvider::new(aws_settings).credentials().await?;
+    let api = Api {
+        client: Client::new(),
+        uri: format!("{}/", endpoint.trim_end_matches('/')),
+        region: aws::region(aws_settings)?,
+        credentials,
+        settings,
+    };
+
+    let mut values = Value::Object(Map::new());
+    match source {
+        AwsSecretsSource::SecretsManager => {
+            for name in api.secret_names().await? {
+                if let Some(path) = path(&name, &settings.prefix) {
+                    let secret = api
+                        .call(
+                            "secretsmanager",
+                            "secretsmanager.GetSecretValue",
+                            json!({ "SecretId": name }),
+                        )
+                        .await?;
+                    let value = match secret["SecretString"].as_str() {
+                        Some(value) => value,
+                        None => {
+                            warn!(name = name.as_str(), "Ignoring binary secret");
+                            continue;
+                        }
+                    };
+                    let value = match serde_json::from_str(value) {
+                        Ok(Value::Object(object)) => Value::Object(object),
+                        _ => Value::String(value.to_owned()),
+                    };
+                    insert(&mut values, &path, value, &name);
+                }
+            }
+        }
+
+        AwsSecretsSource::Ssm => {
+            let mut next_token = None;
+            loop {
+                let mut request = json!({
+                    "Path": settings.prefix,
+                    "Recursive": true,
+                    "WithDecryption": true,
+                });
+                if let Some(next_token) = next_token {
+                    request["NextToken"] = next_token;
+                }
+                let mut response = api
+                    .call("ssm", "AmazonSSM.GetParametersByPath", request)
+                    .await?;
+                let parameters = response["Parameters"].as_array().cloned();
+                for parameter in parameters.unwrap_or_default() {
+                    let (name, value) = match (parameter["Name"].as_str(), &parameter["Value"]) {
+                        (Some(name), Value::String(value)) => (name, value),
+                        _ => bail!("Invalid parameter in response from SSM"),
+                    };
+                    if let Some(path) = path(name, &settings.prefix) {
+                        insert(&mut values, &path, Value::String(value.to_owned()), name);
+                    }
+                }
+                next_token = response.get_mut("NextToken").map(Value::take);
+                if next_token.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+struct Api<'a> {
+    client: Client<HttpConnector>,
+    uri: String,
+    region: String,
+    credentials: Credentials,
+    settings: &'a AwsSecretsSettings,
+}
+
+impl Api<'_> {
+    /// The names of all secrets starting with the prefix.
+    async fn secret_names(&self) -> Result<Vec<String>> {
+        let mut names = vec![];
+        let mut next_token = None;
+        loop {
+            let mut request = json!({
+                "Filters": [{ "Key": "name", "Values": [self.settings.prefix] }],
+                "MaxResults": 100,
+            });
+            if let Some(next_token) = next_token {
+                request["NextToken"] = next_token;
+            }
+            let mut response = self
+                .call("secretsmanager", "secretsmanager.ListSecrets", request)
+                .await?;
+            let secrets = response["SecretList"].as_array().cloned();
+            names.extend(
+                secrets
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|secret| secret["Name"].as_str().map(str::to_owned)),
+            );
+            next_token = response.get_mut("NextToken").map(Value::take);
+            if next_token.is_none() {
+                return Ok(names);
+            }
+        }
+    }
+
+    /// Call the given action of the JSON protocol of AWS.
+    async fn call(&self, service: &str, target: &str, body: Value) -> Result<Value> {
+        let body = body.to_string();
+        let payload_hash = payload_hash(body.as_bytes());
+        let mut request = Request::post(&self.uri)
+            .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+            .header(X_AMZ_TARGET, target)
+            .body(Body::from(body))
+            .context("Cannot create request")?;
+        aws::sign(
+            &mut request,
+            &self.credentials,
+            &self.region,
+            service,
+            &payload_hash,
+            SystemTime::now(),
+        )?;
+
+        let response = timeout(self.settings.timeout, self.client.request(request))
+            .await
+            .map_err(|_| anyhow!("Timeout calling {target}"))?
+            .with_context(|| format!("Cannot call {target}"))?;
+        let status = response.status();
+        let body = body::to_bytes(response.into_body()).await?;
+        if !status.is_success() {
+            // Error responses only have a type and a message, e.g. `AccessDeniedException`.
+            bail!(
+                "{target} responded with status {status}: {}",
+                String::from_utf8_lossy(&body)
+            );
+        }
+        serde_json::from_slice(&body).with_context(|| format!("Cannot parse response of {target}"))
+    }
+}
+
+/// Whether the given URI is plain HTTP to the loopback interface, e.g. `http://localhost:4566`
+/// or `http://[::1]:4566`.
+pub(crate) fn is_loopback(uri: &Uri) -> bool {
+    let host = match uri.host() {
+        Some(host) if uri.scheme_str() == Some("http") => host,
+        _ => return false,
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map_or(false, |ip| ip.is_loopback())
+}
+
+/// The segments of the given name below the given prefix, if it is below the prefix.
+fn path<'a>(name: &'a str, prefix: &str) -> Option<Vec<&'a str>> {
+    if !has_path_prefix(name, prefix) {
+        return None;
+    }
+    let rest = &name[prefix.len()..];
+    Some(
+        rest.split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect(),
+    )
+}
+
+/// Insert the given value at the given path, merging objects.
+fn insert(values: &mut Value, path: &[&str], value: Value, name: &str) {
+    if path.is_empty() && !value.is_object() {
+        warn!(
+            name,
+            "Ignoring value without key, the secret must hold a JSON object"
+        );
+        return;
+    }
+    let value = path
+        .iter()
+        .rev()
+        .fold(value, |value, segment| json!({ *segment: value }));
+    merge(values, value);
+}
+
+fn merge(target: &mut Value, value: Value) {
+    match (target, value) {
+        (Value::Object(target), Value::Object(value)) => {
+            for (name, value) in value {
+                merge(target.entry(name).or_insert(Value::Null), value);
+            }
+        }
+        (target, value) => *target = value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path() {
+        assert_eq!(
+            path("/app/prod/admin/password", "/app/prod"),
+            Some(vec!["admin", "password"])
+        );
+        assert_eq!(
+            path("/app/prod/admin/password", "/app/prod/"),
+            Some(vec!["admin", "password"])
+        );
+        assert_eq!(path("app/prod", "app/prod"), Some(vec![]));
+        assert_eq!(path("app/production/admin", "app/prod"), None);
+        assert_eq!(path("other/admin", "app/prod"), None);
+    }
+
+    #[test]
+    fn test_is_loopback() {
+        for uri in [
+            "http://localhost:4566",
+            "http://LOCALHOST",
+            "http://127.0.0.1:4566/",
+            "http://127.1.2.3",
+            "http://[::1]:4566",
+        ] {
+            assert!(is_loopback(&uri.parse().unwrap()), "{uri}");
+        }
+        for uri in [
+            "https://localhost:4566",
+            "http://localstack:4566",
+            "http://secretsmanager.eu-central-1.amazonaws.com",
+            "http://10.0.0.1",
+            "http://localhost.evil.example",
+            "/localhost",
+        ] {
+            assert!(!is_loopback(&uri.parse().unwrap()), "{uri}");
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut values = json!({});
+        insert(&mut values, &[], json!({ "admin": { "user": "a" } }), "app");
+        insert(
+            &mut values,
+            &["admin", "password"],
+            json!("secret"),
+            "app/admin/password",
+        );
+        insert(
+            &mut values,
+            &["server", "port"],
+            json!("8080"),
+            "app/server/port",
+        );
+        insert(&mut values, &[], json!("ignored"), "app");
+        assert_eq!(
+            values,
+            json!({
+                "admin": { "user": "a", "password": "secret" },
+                "server": { "port": "8080" },
+            })
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Pluggable storage for uploaded files, see [BlobStore], with [LocalBlobStore] storing them in
+//! a local directory and [S3BlobStore](s3::S3BlobStore) in an S3 bucket.
+
+pub mod s3;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use hyper::Uri;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+/// Storage for blobs, which are written in chunks such that they need not be held in memory.
+#[async_trait]
+pub trait BlobStore: Debug + Send + Sync + 'static {
+    /// Start writing the blob with the given key, which must only become visible once the
+    /// returned [BlobWriter] is completed.
+    async fn create(&self, key: &str) -> Result<Box<dyn BlobWriter>>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// A URL for clients to download the blob with the given key directly, valid for the given
+    /// duration, if the store supports it.
+    async fn download_url(&self, _key: &str, _expires_in: Duration) -> Result<Option<Uri>> {
+        Ok(None)
+    }
+
+    /// A URL for clients to upload the blob with the given key directly via `PUT`, valid for the
+    /// given duration, if the store supports it.
+    async fn upload_url(&self, _key: &str, _expires_in: Duration) -> Result<Option<Uri>> {
+        Ok(None)
+    }
+}
+
+#[async_trait]
+pub trait BlobWriter: Send {
+    async fn write(&mut self, chunk: &[u8]) -> Result<()>;
+
+    async fn complete(self: Box<Self>) -> Result<()>;
+
+    /// Discard what has been written so far.
+    async fn abort(self: Box<Self>) -> Result<()>;
+}
+
+/// Stores blobs as files named after their keys in the given directory, which is created if
+/// needed. Files are first written with a `.part` suffix and renamed when completed.
+#[derive(Debug, Clone)]
+pub struct LocalBlobStore {
+    dir: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn create(&self, key: &str) -> Result<Box<dyn BlobWriter>> {
+        check_key(key)?;
+        fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Cannot create directory {}", self.dir.display()))?;
+
+        let path = self.dir.join(key);
+        let part_path = self.dir.join(format!("{key}.part"));
+        let file = File::create(&part_path)
+            .await
+            .with_context(|| format!("Cannot create file {}", part_path.display()))?;
+        Ok(Box::new(LocalBlobWriter {
+            file,
+            path,
+            part_path,
+            finished: false,
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        let path = self.dir.join(key);
+        fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Cannot remove file {}", path.display()))
+    }
+}
+
+/// Keys must be plain file names, e.g. no paths like `../etc/passwd`.
+pub(crate) fn check_key(key: &str) -> Result<()> {
+    anyhow::ensure!(
+        !key.is_empty() && Path::new(key).file_name() == Some(key.as_ref()),
+        "Invalid blob key {key}"
+    );
+    Ok(())
+}
+
+/// Removes the `.part` file if dropped before being completed or aborted, e.g. because the
+/// client has disconnected.
+struct LocalBlobWriter {
+    file: File,
+    path: PathBuf,
+    part_path: PathBuf,
+    finished: bool,
+}
+
+#[async_trait]
+impl BlobWriter for LocalBlobWriter {
+    async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.file
+            .write_all(chunk)
+            .await
+            .with_context(|| format!("Cannot write file {}", self.part_path.display()))
+    }
+
+    async fn complete(mut self: Box<Self>) -> Result<()> {
+        self.file
+            .sync_all()
+            .await
+            .with_context(|| format!("Cannot write file {}", self.part_path.display()))?;
+        fs::rename(&self.part_path, &self.path)
+            .await
+            .with_context(|| format!("Cannot rename file {}", self.part_path.display()))?;
+        self.finished = true;
+        Ok(())
+    }
+
+    async fn abort(mut self: Box<Self>) -> Result<()> {
+        fs::remove_file(&self.part_path)
+            .await
+            .with_context(|| format!("Cannot remove file {}", self.part_path.display()))?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Drop for LocalBlobWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = std::fs::remove_file(&self.part_path);
+        }
+    }
+}
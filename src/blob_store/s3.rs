@@ -0,0 +1,372 @@
+//! [BlobStore] storing blobs as objects in an S3 bucket, configured via the `storage.s3` and
+//! `aws` settings, which can also hand out presigned URLs for clients to download or upload
+//! objects directly.
+//!
+//! Blobs up to the part size are uploaded at once, larger ones via a multipart upload, which is
+//! aborted if the [BlobWriter] is aborted or dropped before being completed. As this cannot be
+//! guaranteed, e.g. if the process is killed, the bucket should have a lifecycle rule removing
+//! incomplete multipart uploads.
+//!
+//! As the HTTP client has no TLS support, objects are sent in cleartext to `storage.s3.endpoint`,
+//! which must be an egress proxy originating TLS towards AWS or a store within a trusted network,
+//! see [crate::aws].
+
+use super::{check_key, BlobStore, BlobWriter};
+use crate::aws::{self, CredentialsProvider};
+use crate::http_client::HttpClient;
+use crate::log_error_chain;
+use crate::settings::{AwsSettings, S3Settings};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use async_trait::async_trait;
+use hyper::header::ETAG;
+use hyper::{body, Body, Method, Request, Response, Uri};
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::runtime::Handle;
+
+const SERVICE: &str = "s3";
+
+const X_AMZ_CONTENT_SHA256: &str = "x-amz-content-sha256";
+
+/// Presigned URLs cannot be valid for longer than seven days.
+const MAX_EXPIRES_IN: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Cheap to clone, all components are shared.
+#[derive(Debug, Clone)]
+pub struct S3BlobStore {
+    settings: Arc<S3Settings>,
+    region: Option<String>,
+    credentials: Arc<CredentialsProvider>,
+    http_client: HttpClient,
+}
+
+impl S3BlobStore {
+    pub fn new(settings: &S3Settings, aws_settings: &AwsSettings, http_client: HttpClient) -> Self {
+        Self {
+            settings: Arc::new(settings.clone()),
+            region: aws::region(aws_settings).ok(),
+            credentials: Arc::new(CredentialsProvider::new(aws_settings)),
+            http_client,
+        }
+    }
+
+    async fn presign(&self, method: &str, key: &str, expires_in: Duration) -> Result<Uri> {
+        check_key(key)?;
+        ensure!(
+            expires_in <= MAX_EXPIRES_IN,
+            "Presigned URLs cannot be valid for longer than seven days"
+        );
+        let credentials = self.credentials.credentials().await?;
+        aws::presign(
+            method,
+            &self.uri(key, "")?,
+            &credentials,
+            self.region()?,
+            SERVICE,
+            expires_in,
+            SystemTime::now(),
+        )
+    }
+
+    /// Send a signed request for the object with the given key, failing unless it succeeds.
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &str,
+        body: Vec<u8>,
+    ) -> Result<Response<Body>> {
+        let payload_hash = aws::payload_hash(&body);
+        let mut request = Request::builder()
+            .method(&method)
+            .uri(self.uri(key, query)?)
+            .header(X_AMZ_CONTENT_SHA256, &payload_hash)
+            .body(Body::from(body))
+            .context("Cannot create request")?;
+        let credentials = self.credentials.credentials().await?;
+        aws::sign(
+            &mut request,
+            &credentials,
+            self.region()?,
+            SERVICE,
+            &payload_hash,
+            SystemTime::now(),
+        )?;
+
+        let response = self.http_client.request(request).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = body::to_bytes(response.into_body()).await?;
+            let code = element(&String::from_utf8_lossy(&body), "Code")
+                .unwrap_or_default()
+                .to_owned();
+            bail!("S3 responded to {method} {key} with status {status} {code}");
+        }
+        Ok(response)
+    }
+
+    /// The URI of the object with the given key with the given, already encoded, query.
+    fn uri(&self, key: &str, query: &str) -> Result<Uri> {
+        let endpoint = self.settings.endpoint.trim_end_matches('/');
+        let object = format!("{}{key}", self.settings.prefix)
+            .split('/')
+            .map(aws::encode)
+            .collect::<Vec<_>>()
+            .join("/");
+        let uri = if self.settings.path_style {
+            format!("{endpoint}/{}/{object}", aws::encode(&self.settings.bucket))
+        } else {
+            let (scheme, host) = endpoint
+                .split_once("://")
+                .ok_or_else(|| anyhow!("Invalid S3 endpoint {endpoint}"))?;
+            format!("{scheme}://{}.{host}/{object}", self.settings.bucket)
+        };
+        let uri = if query.is_empty() {
+            uri
+        } else {
+            format!("{uri}?{query}")
+        };
+        uri.parse().context("Cannot create S3 URI")
+    }
+
+    fn region(&self) -> Result<&str> {
+        self.region
+            .as_deref()
+            .ok_or_else(|| anyhow!("Neither aws.region nor AWS_REGION defined"))
+    }
+
+    async fn abort_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        let query = format!("uploadId={}", aws::encode(upload_id));
+        self.send(Method::DELETE, key, &query, vec![]).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn create(&self, key: &str) -> Result<Box<dyn BlobWriter>> {
+        check_key(key)?;
+        Ok(Box::new(S3BlobWriter {
+            store: self.clone(),
+            key: key.to_owned(),
+            buffer: Vec::new(),
+            upload: None,
+            finished: false,
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        self.send(Method::DELETE, key, "", vec![]).await?;
+        Ok(())
+    }
+
+    async fn download_url(&self, key: &str, expires_in: Duration) -> Result<Option<Uri>> {
+        self.presign("GET", key, expires_in).await.map(Some)
+    }
+
+    async fn upload_url(&self, key: &str, expires_in: Duration) -> Result<Option<Uri>> {
+        self.presign("PUT", key, expires_in).await.map(Some)
+    }
+}
+
+/// Buffers up to the part size; the multipart upload is only created once it is exceeded.
+struct S3BlobWriter {
+    store: S3BlobStore,
+    key: String,
+    buffer: Vec<u8>,
+    upload: Option<MultipartUpload>,
+    finished: bool,
+}
+
+struct MultipartUpload {
+    id: String,
+    etags: Vec<String>,
+}
+
+impl S3BlobWriter {
+    async fn upload_part(&mut self, part: Vec<u8>) -> Result<()> {
+        if self.upload.is_none() {
+            let response = self
+                .store
+                .send(Method::POST, &self.key, "uploads", vec![])
+                .await?;
+            let body = body::to_bytes(response.into_body()).await?;
+            let id = element(&String::from_utf8_lossy(&body), "UploadId")
+                .ok_or_else(|| anyhow!("No upload ID in response from S3"))?
+                .to_owned();
+            self.upload = Some(MultipartUpload { id, etags: vec![] });
+        }
+        let upload = self.upload.as_mut().expect("upload is created");
+
+        let query = format!(
+            "partNumber={}&uploadId={}",
+            upload.etags.len() + 1,
+            aws::encode(&upload.id)
+        );
+        let response = self
+            .store
+            .send(Method::PUT, &self.key, &query, part)
+            .await?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .ok_or_else(|| anyhow!("No ETag in response from S3"))?;
+        upload.etags.push(etag.to_owned());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlobWriter for S3BlobWriter {
+    async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.buffer.extend_from_slice(chunk);
+        let part_size = self.store.settings.part_size;
+        while self.buffer.len() >= part_size {
+            let rest = self.buffer.split_off(part_size);
+            let part = mem::replace(&mut self.buffer, rest);
+            self.upload_part(part).await?;
+        }
+        Ok(())
+    }
+
+    async fn complete(mut self: Box<Self>) -> Result<()> {
+        let buffer = mem::take(&mut self.buffer);
+        if self.upload.is_none() {
+            self.store.send(Method::PUT, &self.key, "", buffer).await?;
+            self.finished = true;
+            return Ok(());
+        }
+
+        if !buffer.is_empty() {
+            self.upload_part(buffer).await?;
+        }
+        let upload = self.upload.as_ref().expect("upload is created");
+        let parts = upload
+            .etags
+            .iter()
+            .enumerate()
+            .map(|(n, etag)| {
+                format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>{etag}</ETag></Part>",
+                    n + 1
+                )
+            })
+            .collect::<String>();
+        let body = format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>");
+        let query = format!("uploadId={}", aws::encode(&upload.id));
+        let response = self
+            .store
+            .send(Method::POST, &self.key, &query, body.into_bytes())
+            .await?;
+        // Failures may be reported with status 200 and an error document.
+        let body = body::to_bytes(response.into_body()).await?;
+        if let Some(code) = element(&String::from_utf8_lossy(&body), "Code") {
+            bail!("Cannot complete multipart upload of {}: {code}", self.key);
+        }
+        self.finished = true;
+        Ok(())
+    }
+
+    async fn abort(mut self: Box<Self>) -> Result<()> {
+        self.finished = true;
+        match self.upload.take() {
+            Some(upload) => self.store.abort_upload(&self.key, &upload.id).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for S3BlobWriter {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let (Some(upload), Ok(handle)) = (self.upload.take(), Handle::try_current()) {
+            let store = self.store.clone();
+            let key = mem::take(&mut self.key);
+            handle.spawn(async move {
+                if let Err(e) = store.abort_upload(&key, &upload.id).await {
+                    log_error_chain("Cannot abort multipart upload", e.as_ref());
+                }
+            });
+        }
+    }
+}
+
+/// The text of the first element with the given name in the given XML document, which suffices
+/// for the simple responses of S3.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{name}>"))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{name}>"))?;
+    Some(&xml[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(path_style: bool) -> S3BlobStore {
+        let settings = S3Settings {
+            bucket: "uploads".to_string(),
+            endpoint: "http://minio:9000/".to_string(),
+            path_style,
+            prefix: "files/".to_string(),
+            ..Default::default()
+        };
+        let aws_settings = AwsSettings {
+            region: Some("eu-central-1".to_string()),
+            access_key_id: Some("AKID".to_string()),
+            secret_access_key: Some("secret".to_string().into()),
+            ..Default::default()
+        };
+        let http_client = HttpClient::new(&Default::default());
+        S3BlobStore::new(&settings, &aws_settings, http_client)
+    }
+
+    #[test]
+    fn test_uri() {
+        assert_eq!(
+            store(true).uri("a b.txt", "").unwrap(),
+            "http://minio:9000/uploads/files/a%20b.txt"
+        );
+        assert_eq!(
+            store(false).uri("a.txt", "uploads").unwrap(),
+            "http://uploads.minio:9000/files/a.txt?uploads"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_presign() {
+        let store = store(true);
+        let url = store
+            .download_url("a.txt", Duration::from_secs(60))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(url.path(), "/uploads/files/a.txt");
+        let query = url.query().unwrap();
+        assert!(query.contains("X-Amz-Expires=60&"));
+        assert!(query.contains("X-Amz-Credential=AKID%2F"));
+        assert!(query.contains("%2Feu-central-1%2Fs3%2Faws4_request&"));
+
+        assert!(store
+            .upload_url("../a.txt", Duration::from_secs(60))
+            .await
+            .is_err());
+        assert!(store
+            .upload_url("a.txt", MAX_EXPIRES_IN + Duration::from_secs(1))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_element() {
+        let xml = "<InitiateMultipartUploadResult><Bucket>b</Bucket>\
+                   <UploadId>VXBsb2FkIElE</UploadId></InitiateMultipartUploadResult>";
+        assert_eq!(element(xml, "UploadId"), Some("VXBsb2FkIElE"));
+        assert_eq!(element(xml, "Code"), None);
+    }
+}
@@ -0,0 +1,196 @@
+//! Logging of request and response bodies for debugging, e.g. integrations with partners, for the
+//! routes configured in `logging.body_capture.routes`, but only in the configured environments,
+//! by default only `dev`. Each request gets one event with target `body_capture` with method,
+//! route, status, headers and bodies, the latter truncated to `max_size`.
+//!
+//! Configured headers as well as fields of JSON and form bodies are logged as `[redacted]`.
+//! Streamed response bodies of unknown size are not captured.
+
+use crate::error::Error;
+use crate::settings::BodyCaptureSettings;
+use axum::body::{boxed, Body, Full, HttpBody};
+use axum::extract::MatchedPath;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tracing::info;
+
+const REDACTED: &str = "[redacted]";
+
+/// Shared state of the [capture_bodies] middleware.
+#[derive(Debug, Clone)]
+pub struct BodyCapture {
+    routes: Arc<BTreeSet<String>>,
+    max_size: usize,
+    /// Lowercase.
+    redact_headers: Arc<BTreeSet<String>>,
+    /// Lowercase.
+    redact_fields: Arc<BTreeSet<String>>,
+}
+
+impl BodyCapture {
+    /// The configured body capture, if any routes are configured and the given environment is one
+    /// of the configured ones.
+    pub fn new(settings: &BodyCaptureSettings, environment: Option<&str>) -> Option<Self> {
+        let enabled = !settings.routes.is_empty()
+            && environment.map_or(false, |environment| {
+                settings.environments.iter().any(|e| e == environment)
+            });
+        enabled.then(|| Self {
+            routes: Arc::new(settings.routes.iter().cloned().collect()),
+            max_size: settings.max_size,
+            redact_headers: Arc::new(lowercase(&settings.redact_headers)),
+            redact_fields: Arc::new(lowercase(&settings.redact_fields)),
+        })
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> String {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if self.redact_headers.contains(name.as_str()) {
+                    REDACTED
+                } else {
+                    value.to_str().unwrap_or("[binary]")
+                };
+                format!("{name}: {value}")
+            })
+            .collect::<Vec<_>>();
+        headers.join("\n")
+    }
+
+    fn body(&self, headers: &HeaderMap, body: &[u8]) -> String {
+        if body.is_empty() {
+            return String::new();
+        }
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        let body = if content_type.contains("json") {
+            match serde_json::from_slice::<Value>(body) {
+                Ok(mut json) => {
+                    self.redact_json(&mut json);
+                    json.to_string()
+                }
+                Err(_) => String::from_utf8_lossy(body).into_owned(),
+            }
+        } else if content_type.starts_with("application/x-www-form-urlencoded") {
+            self.redact_form(&String::from_utf8_lossy(body))
+        } else if content_type.starts_with("text/") || std::str::from_utf8(body).is_ok() {
+            String::from_utf8_lossy(body).into_owned()
+        } else {
+            return format!("[{} bytes binary]", body.len());
+        };
+        truncate(body, self.max_size)
+    }
+
+    fn redact_json(&self, json: &mut Value) {
+        match json {
+            Value::Object(fields) => {
+                for (name, value) in fields {
+                    if self.redact_fields.contains(&name.to_lowercase()) {
+                        *value = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_json(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact_json(value)),
+            _ => {}
+        }
+    }
+
+    fn redact_form(&self, form: &str) -> String {
+        let pairs = form
+            .split('&')
+            .map(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                let decoded = percent_decode_str(&name.replace('+', " "))
+                    .decode_utf8_lossy()
+                    .to_lowercase();
+                if self.redact_fields.contains(&decoded) {
+                    format!("{name}={REDACTED}")
+                } else {
+                    pair.to_owned()
+                }
+            })
+            .collect::<Vec<_>>();
+        pairs.join("&")
+    }
+}
+
+/// Middleware logging the bodies of requests to the configured routes, see
+/// [crate::body_capture].
+pub async fn capture_bodies(
+    request: Request<Body>,
+    next: Next<Body>,
+    capture: BodyCapture,
+) -> Response {
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(route) if capture.routes.contains(route.as_str()) => route.as_str().to_owned(),
+        _ => return next.run(request).await,
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            return Error::Validation(format!("Cannot read request body: {e}")).into_response()
+        }
+    };
+    let method = parts.method.to_string();
+    let request_headers = capture.headers(&parts.headers);
+    let request_body = capture.body(&parts.headers, &body);
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let (parts, body) = response.into_parts();
+    let (response_body, body) = if body.size_hint().exact().is_some() {
+        match hyper::body::to_bytes(body).await {
+            Ok(body) => (capture.body(&parts.headers, &body), boxed(Full::from(body))),
+            Err(e) => {
+                let e = anyhow::anyhow!(e).context("Cannot read response body");
+                return Error::Internal(e).into_response();
+            }
+        }
+    } else {
+        ("[streamed]".to_string(), body)
+    };
+
+    info!(
+        target: "body_capture",
+        method = method.as_str(),
+        route = route.as_str(),
+        status = parts.status.as_u16(),
+        request_headers = request_headers.as_str(),
+        request_body = request_body.as_str(),
+        response_headers = capture.headers(&parts.headers).as_str(),
+        response_body = response_body.as_str(),
+        "Bodies captured"
+    );
+    Response::from_parts(parts, body)
+}
+
+fn lowercase(values: &[String]) -> BTreeSet<String> {
+    values.iter().map(|value| value.to_lowercase()).collect()
+}
+
+fn truncate(mut s: String, max_size: usize) -> String {
+    if s.len() > max_size {
+        let mut end = max_size;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        let size = s.len();
+        s.truncate(end);
+        s.push_str(&format!("... [{size} bytes]"));
+    }
+    s
+}
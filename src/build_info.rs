@@ -0,0 +1,37 @@
+//! Information about the build, baked into the binary by `build.rs`, served at `/version`.
+
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: Option<&'static str>,
+    /// The tag of the commit, if it is tagged.
+    pub git_tag: Option<&'static str>,
+    /// In RFC 3339 format in UTC.
+    pub build_timestamp: &'static str,
+    pub rustc_version: Option<&'static str>,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_sha: non_empty(env!("BUILD_GIT_SHA")),
+    git_tag: non_empty(env!("BUILD_GIT_TAG")),
+    build_timestamp: env!("BUILD_TIMESTAMP"),
+    rustc_version: non_empty(env!("BUILD_RUSTC_VERSION")),
+};
+
+/// Route for `/version`.
+pub fn routes() -> Router {
+    Router::new().route("/version", get(|| async { Json(BUILD_INFO) }))
+}
+
+const fn non_empty(s: &'static str) -> Option<&'static str> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
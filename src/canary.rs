@@ -0,0 +1,157 @@
+//! Canary releases within the service: requests to the configured paths are assigned a
+//! [Variant], the canary if the configured header or cookie has the configured value, the stable
+//! one if it has another value, or else the canary for the configured fraction of client
+//! addresses, such that clients consistently get the same variant.
+//!
+//! Handlers select the implementation by extracting the [Variant]; alternatively canary requests
+//! are forwarded to the configured upstream, yet only after authentication, rate limiting and
+//! maintenance mode have accepted them. Requests are counted as `canary_requests_total`
+//! labeled by variant and status code.
+
+use crate::cidr::Cidr;
+use crate::client_ip::client_ip;
+use crate::has_path_prefix;
+use crate::hmac::sha256;
+use crate::http_client::HttpClient;
+use crate::metrics::increment_counter;
+use crate::proxy::Proxy;
+use crate::sessions::cookie;
+use crate::settings::{CanarySettings, ProxySettings};
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::extract::{FromRequest, RequestParts};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+/// Extractor for the variant of the current request, the stable one unless assigned otherwise,
+/// see [crate::canary].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Stable,
+    Canary,
+}
+
+impl Variant {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Variant::Stable => "stable",
+            Variant::Canary => "canary",
+        }
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Variant
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let variant = request
+            .extensions()
+            .and_then(|extensions| extensions.get::<Variant>())
+            .copied()
+            .unwrap_or(Variant::Stable);
+        Ok(variant)
+    }
+}
+
+/// Shared state of the [route_canary] middleware.
+#[derive(Debug, Clone)]
+pub struct Canary {
+    settings: Arc<CanarySettings>,
+    upstream: Option<Arc<Proxy>>,
+}
+
+impl Canary {
+    /// The configured canary routing, if any paths are configured.
+    pub fn new(
+        settings: &CanarySettings,
+        trusted_proxies: &[Cidr],
+        http_client: HttpClient,
+    ) -> Option<Self> {
+        if settings.paths.is_empty() {
+            return None;
+        }
+        let upstream = settings.upstream.as_ref().and_then(|upstream| {
+            let proxy = ProxySettings {
+                upstream: upstream.to_owned(),
+                ..Default::default()
+            };
+            Proxy::new(&proxy, Arc::new(trusted_proxies.to_vec()), http_client).map(Arc::new)
+        });
+        Some(Self {
+            settings: Arc::new(settings.clone()),
+            upstream,
+        })
+    }
+
+    fn variant<B>(&self, request: &Request<B>) -> Variant {
+        let settings = &self.settings;
+        let selected = request
+            .headers()
+            .get(&settings.header)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned)
+            .or_else(|| cookie(request.headers(), &settings.cookie));
+        let canary = match selected {
+            Some(value) => value.trim() == settings.value,
+            None => bucket(request) < settings.fraction,
+        };
+        if canary {
+            Variant::Canary
+        } else {
+            Variant::Stable
+        }
+    }
+}
+
+/// Middleware assigning the [Variant] to requests to the configured paths and forwarding canary
+/// requests if an upstream is configured, see [crate::canary].
+pub async fn route_canary(
+    mut request: Request<Body>,
+    next: Next<Body>,
+    canary: Canary,
+) -> Response {
+    let path = request.uri().path();
+    if !canary
+        .settings
+        .paths
+        .iter()
+        .any(|prefix| has_path_prefix(path, prefix))
+    {
+        return next.run(request).await;
+    }
+
+    let variant = canary.variant(&request);
+    request.extensions_mut().insert(variant);
+    let response = match (variant, &canary.upstream) {
+        (Variant::Canary, Some(upstream)) => upstream.forward(request).await,
+        _ => next.run(request).await,
+    };
+
+    increment_counter(
+        "canary_requests_total",
+        &[
+            ("variant", variant.as_str().to_string()),
+            ("status", response.status().as_u16().to_string()),
+        ],
+    );
+    response
+}
+
+/// A number in `[0, 1)` derived from the client address, random if unknown.
+fn bucket<B>(request: &Request<B>) -> f64 {
+    match client_ip(request) {
+        Some(ip) => {
+            let hash = sha256(ip.to_string().as_bytes());
+            u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) as f64
+                / (u32::MAX as f64 + 1.0)
+        }
+        None => rand::random(),
+    }
+}
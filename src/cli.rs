@@ -0,0 +1,124 @@
+//! Command line arguments, overriding values loaded by [Settings::load](crate::Settings::load).
+
+use crate::settings::{LogFormat, DEFAULT_CONFIG_DIR};
+use anyhow::{anyhow, bail, Context, Result};
+use std::env;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Usage: bayer-axum [OPTIONS] [COMMAND]
+
+Commands:
+  check-config  Load and validate the configuration, print it with secrets redacted and exit
+
+Options:
+      --config-dir <DIR>     Directory with the configuration files [default: config]
+      --addr <ADDR>          Address to bind to
+      --port <PORT>          Port to bind to
+      --log-format <FORMAT>  Log format [possible values: json, pretty, compact]
+  -h, --help                 Print help information";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Cli {
+    pub config_dir: Option<PathBuf>,
+    pub addr: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub log_format: Option<LogFormat>,
+    pub help: bool,
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    CheckConfig,
+}
+
+impl Cli {
+    /// Parse the arguments of this process. On `--help` the usage is printed and the process
+    /// exits successfully, on invalid arguments the error and the usage are printed and the
+    /// process exits with code 2.
+    pub fn parse() -> Self {
+        match Self::try_parse_from(env::args().skip(1)) {
+            Ok(cli) if cli.help => {
+                println!("{USAGE}");
+                process::exit(0)
+            }
+            Ok(cli) => cli,
+            Err(e) => {
+                eprintln!("error: {e:#}\n\n{USAGE}");
+                process::exit(2)
+            }
+        }
+    }
+
+    /// Parse the given arguments, not including the binary name. Values can either be given as
+    /// separate arguments, e.g. `--port 8080`, or inline, e.g. `--port=8080`.
+    pub fn try_parse_from<I, T>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter().map(Into::into);
+
+        while let Some(arg) = args.next() {
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let value = || {
+                inline_value
+                    .or_else(|| args.next())
+                    .ok_or_else(|| anyhow!("Missing value for {name}"))
+            };
+
+            match name.as_str() {
+                "--config-dir" => cli.config_dir = Some(value()?.into()),
+                "--addr" => cli.addr = Some(parse(&name, value()?)?),
+                "--port" => cli.port = Some(parse(&name, value()?)?),
+                "--log-format" => cli.log_format = Some(parse(&name, value()?)?),
+                "-h" | "--help" => cli.help = true,
+                "check-config" if cli.command.is_none() => cli.command = Some(Command::CheckConfig),
+                _ => bail!("Unexpected argument {name}"),
+            }
+        }
+
+        Ok(cli)
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        self.config_dir
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_CONFIG_DIR))
+    }
+
+    /// Settings keys and values to override.
+    pub fn overrides(&self) -> Vec<(&'static str, String)> {
+        let mut overrides = Vec::new();
+        if let Some(addr) = self.addr {
+            overrides.push(("server.addr", addr.to_string()));
+        }
+        if let Some(port) = self.port {
+            overrides.push(("server.port", port.to_string()));
+        }
+        if let Some(log_format) = self.log_format {
+            overrides.push(("logging.format", log_format.as_str().to_string()));
+        }
+        overrides
+    }
+}
+
+fn parse<T>(name: &str, value: String) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .parse()
+        .with_context(|| format!("Invalid value {value} for {name}"))
+}
@@ -0,0 +1,258 @@
+//! The real client address behind reverse proxies and load balancers: if the peer of the
+//! connection is within one of the `server.trusted_proxies`, the `Forwarded` or, if absent, the
+//! `X-Forwarded-For` header is followed from right to left, skipping trusted proxies, up to the
+//! first untrusted address. Otherwise the headers are ignored, because any client could send them.
+//! With `server.tcp.proxy_protocol` enabled, the peer is the source address from the PROXY
+//! protocol header, which is only accepted from trusted proxies.
+//!
+//! The [resolve_client_ip] middleware puts the result as [ClientIp] into the request extensions,
+//! where rate limiting, the access log and audit logging take it from.
+
+use crate::cidr::{canonical, Cidr};
+use crate::error::Error;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use axum::extract::{ConnectInfo, FromRequest, RequestParts};
+use axum::http::{HeaderMap, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Extractor for the real client address, see [crate::client_ip]. Only available if the server
+/// has been started with connect info, i.e. not for Unix domain sockets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<B> FromRequest<B> for ClientIp
+where
+    B: Send,
+{
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        request
+            .extensions()
+            .and_then(|extensions| extensions.get::<ClientIp>())
+            .copied()
+            .ok_or_else(|| anyhow!("Client IP unknown").into())
+    }
+}
+
+/// The client address of the given request, if resolved by [resolve_client_ip].
+pub fn client_ip<B>(request: &Request<B>) -> Option<IpAddr> {
+    request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|ClientIp(ip)| *ip)
+}
+
+/// Middleware resolving the [ClientIp] from the connect info and the forwarding headers sent by
+/// the given trusted proxies.
+pub async fn resolve_client_ip<B>(
+    mut request: Request<B>,
+    next: Next<B>,
+    trusted_proxies: Arc<Vec<Cidr>>,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| canonical(addr.ip()));
+    if let Some(peer) = peer {
+        let ip = resolve(peer, request.headers(), &trusted_proxies);
+        request.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(request).await
+}
+
+fn resolve(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[Cidr]) -> IpAddr {
+    let trusted = |ip: IpAddr| trusted_proxies.iter().any(|cidr| cidr.contains(ip));
+    if !trusted(peer) {
+        return peer;
+    }
+
+    let hops = if headers.contains_key("forwarded") {
+        forwarded(headers)
+    } else {
+        x_forwarded_for(headers)
+    };
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        // Unknown or obfuscated hops end the chain, the last trusted proxy is the best guess.
+        match hop {
+            Some(ip) => client = canonical(*ip),
+            None => break,
+        }
+        if !trusted(client) {
+            break;
+        }
+    }
+    client
+}
+
+/// The `for` addresses of the `Forwarded` header (RFC 7239), e.g. `for=192.0.2.60` or
+/// `for="[2001:db8::1]:4711"`, from the client to the last proxy.
+fn forwarded(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    values(headers, "forwarded")
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value.trim().trim_matches('"')))
+            })
+        })
+        .collect()
+}
+
+/// The addresses of the `X-Forwarded-For` header from the client to the last proxy.
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    values(headers, "x-forwarded-for").map(parse_node).collect()
+}
+
+/// The comma-separated elements of all values of the given header.
+fn values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// An address with an optional port, e.g. `192.0.2.43:47011` or `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.parse().ok().or_else(|| {
+        let (ip, port) = node.rsplit_once(':')?;
+        port.parse::<u16>().ok()?;
+        ip.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    fn trusted_proxies() -> Vec<Cidr> {
+        vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "2001:db8::/32".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_resolve_untrusted_peer() {
+        let headers = headers(&[
+            ("x-forwarded-for", "192.0.2.1"),
+            ("forwarded", "for=192.0.2.1"),
+        ]);
+        assert_eq!(
+            resolve(ip("198.51.100.1"), &headers, &trusted_proxies()),
+            ip("198.51.100.1")
+        );
+        assert_eq!(resolve(ip("10.0.0.1"), &headers, &[]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_resolve_x_forwarded_for() {
+        let trusted_proxies = trusted_proxies();
+        let resolve = |value| {
+            resolve(
+                ip("10.0.0.1"),
+                &headers(&[("x-forwarded-for", value)]),
+                &trusted_proxies,
+            )
+        };
+
+        assert_eq!(resolve("192.0.2.1"), ip("192.0.2.1"));
+        // Addresses left of the first untrusted one may have been sent by the client.
+        assert_eq!(resolve("203.0.113.9, 192.0.2.1"), ip("192.0.2.1"));
+        assert_eq!(resolve("203.0.113.9, 192.0.2.1, 10.0.0.2"), ip("192.0.2.1"));
+        assert_eq!(resolve("192.0.2.1:47011, 10.0.0.2"), ip("192.0.2.1"));
+        assert_eq!(resolve("[2001:db9::1]:4711"), ip("2001:db9::1"));
+        assert_eq!(resolve("2001:db9::1"), ip("2001:db9::1"));
+        assert_eq!(resolve("::ffff:192.0.2.1"), ip("192.0.2.1"));
+        // Only trusted proxies or unknown hops: the last trusted address is the best guess.
+        assert_eq!(resolve("10.0.0.3, 10.0.0.2"), ip("10.0.0.3"));
+        assert_eq!(resolve("192.0.2.1, unknown, 10.0.0.2"), ip("10.0.0.2"));
+        assert_eq!(resolve(""), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_resolve_x_forwarded_for_multiple_headers() {
+        let headers = headers(&[
+            ("x-forwarded-for", "203.0.113.9"),
+            ("x-forwarded-for", "192.0.2.1, 10.0.0.2"),
+        ]);
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted_proxies()),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn test_resolve_forwarded() {
+        let trusted_proxies = trusted_proxies();
+        let resolve = |value| {
+            resolve(
+                ip("10.0.0.1"),
+                &headers(&[("forwarded", value)]),
+                &trusted_proxies,
+            )
+        };
+
+        assert_eq!(
+            resolve("for=192.0.2.60;proto=http;by=10.0.0.1"),
+            ip("192.0.2.60")
+        );
+        assert_eq!(resolve("For=\"[2001:db9::1]:4711\""), ip("2001:db9::1"));
+        assert_eq!(
+            resolve("for=203.0.113.9, for=192.0.2.60, for=10.0.0.2"),
+            ip("192.0.2.60")
+        );
+        assert_eq!(resolve("proto=https;for=192.0.2.60"), ip("192.0.2.60"));
+        assert_eq!(resolve("for=_hidden, for=10.0.0.2"), ip("10.0.0.2"));
+        assert_eq!(resolve("for=\"192.0.2.60:4711\""), ip("192.0.2.60"));
+    }
+
+    #[test]
+    fn test_resolve_forwarded_preferred() {
+        let headers = headers(&[
+            ("x-forwarded-for", "203.0.113.9"),
+            ("forwarded", "for=192.0.2.60"),
+        ]);
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted_proxies()),
+            ip("192.0.2.60")
+        );
+    }
+
+    #[test]
+    fn test_parse_node() {
+        assert_eq!(parse_node("192.0.2.43"), Some(ip("192.0.2.43")));
+        assert_eq!(parse_node("192.0.2.43:47011"), Some(ip("192.0.2.43")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[2001:db8::1]"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[2001:db8::1]:4711"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("192.0.2.43:port"), None);
+        assert_eq!(parse_node("[2001:db8::1"), None);
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! A dedicated pool of threads for CPU-bound work like generating images or reports, which would
+//! otherwise block the worker threads of the runtime and hence starve all other requests:
+//! [Compute::spawn_cpu] runs a closure on the pool and awaits its result.
+//!
+//! Tasks wait in a bounded queue for a free thread; if it is full, the pool is saturated and
+//! further tasks are shed with 503 and `Retry-After` instead of piling up. The number of queued
+//! tasks is exposed as the gauge `compute_queue_depth`, shed ones are counted as
+//! `compute_rejected_total`.
+
+use crate::error::Error;
+use crate::metrics::{increment_counter, set_gauge};
+use crate::settings::ComputeSettings;
+use anyhow::{anyhow, Result};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Handle of the pool, cheap to clone; the threads terminate once all handles are dropped.
+#[derive(Debug, Clone)]
+pub struct Compute {
+    sender: SyncSender<Task>,
+    queued: Arc<AtomicUsize>,
+    retry_after: Duration,
+}
+
+impl Compute {
+    /// Start the threads of the pool, by default one per core; fails if none can be spawned.
+    pub fn new(settings: &ComputeSettings) -> Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Task>(settings.queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let queued = Arc::new(AtomicUsize::new(0));
+        let threads = settings.threads.unwrap_or_else(num_cpus::get);
+        let mut error = None;
+        let mut spawned = 0;
+        for n in 1..=threads {
+            let receiver = receiver.clone();
+            let queued = queued.clone();
+            match thread::Builder::new()
+                .name(format!("compute-{n}"))
+                .spawn(move || run(receiver, queued))
+            {
+                Ok(_) => spawned += 1,
+                Err(e) => {
+                    warn!(
+                        error = e.to_string().as_str(),
+                        "Cannot spawn compute thread"
+                    );
+                    error = Some(e);
+                }
+            }
+        }
+        if spawned == 0 {
+            return Err(match error {
+                Some(e) => anyhow!(e).context("Cannot spawn any compute thread"),
+                None => anyhow!("No compute threads configured"),
+            });
+        }
+        Ok(Self {
+            sender,
+            queued,
+            retry_after: settings.retry_after,
+        })
+    }
+
+    /// Run the given closure on the pool and return its result. Fails with
+    /// [Error::Unavailable] if the pool is saturated and with [Error::Internal] if the closure
+    /// panics.
+    pub async fn spawn_cpu<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let task = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            let _ = result_tx.send(result);
+        });
+
+        // Counted before sending, such that a thread taking the task cannot count it first.
+        set_gauge("compute_queue_depth", &[], increment(&self.queued) as f64);
+        match self.sender.try_send(task) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                set_gauge("compute_queue_depth", &[], decrement(&self.queued) as f64);
+                increment_counter("compute_rejected_total", &[]);
+                return Err(Error::Unavailable(
+                    "Compute capacity exhausted".to_string(),
+                    self.retry_after,
+                ));
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                set_gauge("compute_queue_depth", &[], decrement(&self.queued) as f64);
+                return Err(Error::Internal(anyhow!("Compute pool not running")));
+            }
+        }
+
+        match result_rx.await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(_)) => Err(Error::Internal(anyhow!("Compute task panicked"))),
+            Err(_) => Err(Error::Internal(anyhow!("Compute task dropped"))),
+        }
+    }
+}
+
+fn run(receiver: Arc<Mutex<Receiver<Task>>>, queued: Arc<AtomicUsize>) {
+    loop {
+        // The lock is only held while waiting for a task, not while running it.
+        let task = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match task {
+            Ok(task) => {
+                set_gauge("compute_queue_depth", &[], decrement(&queued) as f64);
+                task();
+            }
+            // All handles have been dropped.
+            Err(_) => return,
+        }
+    }
+}
+
+fn increment(queued: &AtomicUsize) -> usize {
+    queued.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+fn decrement(queued: &AtomicUsize) -> usize {
+    queued.fetch_sub(1, Ordering::Relaxed) - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let settings = ComputeSettings {
+            threads: Some(0),
+            ..Default::default()
+        };
+        assert!(Compute::new(&settings).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_cpu() {
+        let settings = ComputeSettings {
+            threads: Some(1),
+            ..Default::default()
+        };
+        let compute = Compute::new(&settings).unwrap();
+        assert_eq!(compute.spawn_cpu(|| 6 * 7).await.unwrap(), 42);
+        assert!(matches!(
+            compute.spawn_cpu(|| panic!("boom")).await,
+            Err::<(), _>(Error::Internal(_))
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+//! Conditional requests (RFC 7232): `GET` responses get a strong `ETag` computed from the body
+//! unless they have one already, and requests with a matching `If-None-Match` or, for responses
+//! with `Last-Modified`, a not older `If-Modified-Since` are answered with 304.
+
+use crate::error::Error;
+use crate::has_path_prefix;
+use crate::settings::ConditionalRequestsSettings;
+use axum::body::{boxed, Body, Full, HttpBody};
+use axum::http::header::{
+    HeaderName, CACHE_CONTROL, CONTENT_LOCATION, DATE, ETAG, EXPIRES, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, LAST_MODIFIED, VARY,
+};
+use axum::http::{HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+
+/// Headers which 304 responses carry over from the full response.
+const NOT_MODIFIED_HEADERS: [HeaderName; 7] = [
+    CACHE_CONTROL,
+    CONTENT_LOCATION,
+    DATE,
+    ETAG,
+    EXPIRES,
+    LAST_MODIFIED,
+    VARY,
+];
+
+/// Middleware for conditional `GET` and `HEAD` requests to the configured paths. Only bodies of
+/// known size up to `max_body_size` are buffered to compute an `ETag`, streamed ones are not.
+pub async fn conditional<B>(
+    request: Request<B>,
+    next: Next<B>,
+    settings: Arc<ConditionalRequestsSettings>,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path();
+    let applies = (method == Method::GET || method == Method::HEAD)
+        && settings
+            .paths
+            .iter()
+            .any(|prefix| has_path_prefix(path, prefix));
+    if !applies {
+        return next.run(request).await;
+    }
+
+    let if_none_match = header(request.headers(), IF_NONE_MATCH);
+    let if_modified_since = header(request.headers(), IF_MODIFIED_SINCE);
+    let mut response = next.run(request).await;
+    if response.status() != StatusCode::OK || no_store(response.headers()) {
+        return response;
+    }
+
+    if method == Method::GET && !response.headers().contains_key(ETAG) {
+        let size = response.body().size_hint().exact();
+        if size.map_or(false, |size| size <= settings.max_body_size as u64) {
+            let (mut parts, body) = response.into_parts();
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(e) => {
+                    let e = anyhow::anyhow!(e).context("Cannot read response body");
+                    return Error::Internal(e).into_response();
+                }
+            };
+            if let Ok(etag) = HeaderValue::from_str(&format!(r#""{:016x}""#, fnv1a(&body))) {
+                parts.headers.insert(ETAG, etag);
+            }
+            response = Response::from_parts(parts, boxed(Full::from(body)));
+        }
+    }
+
+    let headers = response.headers();
+    let not_modified = match (if_none_match, if_modified_since) {
+        (Some(if_none_match), _) => {
+            header(headers, ETAG).map_or(false, |etag| matches_any(&if_none_match, &etag))
+        }
+        (None, Some(if_modified_since)) => header(headers, LAST_MODIFIED)
+            .and_then(|last_modified| httpdate::parse_http_date(&last_modified).ok())
+            .zip(httpdate::parse_http_date(&if_modified_since).ok())
+            .map_or(false, |(last_modified, since)| last_modified <= since),
+        (None, None) => false,
+    };
+    if !not_modified {
+        return response;
+    }
+
+    let mut not_modified = Response::new(boxed(Body::empty()));
+    *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+    for name in &NOT_MODIFIED_HEADERS {
+        for value in headers.get_all(name) {
+            not_modified.headers_mut().append(name, value.clone());
+        }
+    }
+    not_modified
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned)
+}
+
+fn no_store(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+}
+
+/// Weak comparison as required for `If-None-Match`, i.e. ignoring `W/` prefixes.
+fn matches_any(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .any(|candidate| candidate.trim().trim_start_matches("W/") == etag)
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,77 @@
+//! Reloading of [Settings] on SIGHUP. The logging filter, the rate limits and the feature flags
+//! are applied at runtime, see [apply_filter_changes](crate::telemetry::apply_filter_changes),
+//! [apply_limit_changes](crate::rate_limit::apply_limit_changes) and
+//! [apply_flag_changes](crate::features::apply_flag_changes).
+
+use crate::settings::flatten;
+use crate::{log_error_chain, Settings};
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::info;
+
+/// Spawn a task reloading the [Settings] from the given configuration directory and overrides,
+/// see [Settings::load], whenever SIGHUP is received. Changed settings are published via the
+/// returned receiver and the changed keys are logged; if reloading fails, the current settings
+/// are kept. It is up to the consumers of the receiver which values they apply at runtime.
+pub fn spawn(
+    config_dir: PathBuf,
+    overrides: Vec<(&'static str, String)>,
+    settings: Settings,
+) -> Result<watch::Receiver<Settings>> {
+    let mut sighup = signal(SignalKind::hangup()).context("Cannot install SIGHUP handler")?;
+    let (settings_tx, settings_rx) = watch::channel(settings);
+
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            info!("SIGHUP received, reloading settings");
+            match Settings::load(&config_dir, &overrides).await {
+                Ok(settings) => {
+                    let changed_keys = changed_keys(&settings_tx.borrow(), &settings);
+                    if changed_keys.is_empty() {
+                        info!("Settings unchanged");
+                    } else {
+                        info!(changed_keys = ?changed_keys, "Settings changed");
+                        settings_tx.send_replace(settings);
+                    }
+                }
+                Err(e) => {
+                    log_error_chain("Cannot reload settings, keeping current ones", e.as_ref())
+                }
+            }
+        }
+    });
+
+    Ok(settings_rx)
+}
+
+/// Dotted keys, e.g. `logging.filter`, of all values which differ.
+fn changed_keys(old: &Settings, new: &Settings) -> Vec<String> {
+    let mut old_values = Vec::new();
+    flatten(
+        String::new(),
+        serde_json::to_value(old).unwrap_or_default(),
+        &mut old_values,
+    );
+    let mut new_values = Vec::new();
+    flatten(
+        String::new(),
+        serde_json::to_value(new).unwrap_or_default(),
+        &mut new_values,
+    );
+
+    let mut changed_keys = new_values
+        .iter()
+        .filter(|value| !old_values.contains(value))
+        .chain(
+            old_values
+                .iter()
+                .filter(|value| !new_values.contains(value)),
+        )
+        .map(|(key, _)| key.to_owned())
+        .collect::<Vec<_>>();
+    changed_keys.sort();
+    changed_keys.dedup();
+    changed_keys
+}
@@ -0,0 +1,72 @@
+use crate::settings::CorsSettings;
+use axum::http::header::HeaderName;
+use axum::http::{HeaderValue, Method};
+use std::fmt::Display;
+use std::str::FromStr;
+use tower_http::cors::{Any, CorsLayer, Origin};
+use tracing::warn;
+
+const ANY: &str = "*";
+
+/// Build a [CorsLayer] from the given [CorsSettings], where `*` allows any origin, method or
+/// header. Invalid values are logged and ignored.
+///
+/// If no origins are allowed, there is no layer at all, because [CorsLayer] would reject any
+/// request with an `Origin` header, including same-origin ones.
+pub fn layer(settings: &CorsSettings) -> Option<CorsLayer> {
+    if settings.allowed_origins.is_empty() {
+        return None;
+    }
+
+    let mut layer = CorsLayer::new()
+        .allow_credentials(settings.allow_credentials)
+        .max_age(settings.max_age);
+
+    layer = if is_any(&settings.allowed_origins) {
+        layer.allow_origin(Any)
+    } else {
+        layer.allow_origin(Origin::list(parse_all::<HeaderValue>(
+            "origin",
+            &settings.allowed_origins,
+        )))
+    };
+
+    if !settings.allowed_methods.is_empty() {
+        layer = if is_any(&settings.allowed_methods) {
+            layer.allow_methods(Any)
+        } else {
+            layer.allow_methods(parse_all::<Method>("method", &settings.allowed_methods))
+        };
+    }
+
+    if !settings.allowed_headers.is_empty() {
+        layer = if is_any(&settings.allowed_headers) {
+            layer.allow_headers(Any)
+        } else {
+            layer.allow_headers(parse_all::<HeaderName>("header", &settings.allowed_headers))
+        };
+    }
+
+    Some(layer)
+}
+
+fn is_any(values: &[String]) -> bool {
+    values.iter().any(|value| value == ANY)
+}
+
+fn parse_all<T>(kind: &str, values: &[String]) -> Vec<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    values
+        .iter()
+        .filter_map(|value| match value.parse() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warn!(value = value.as_str(), error = %e, "Ignoring invalid CORS {kind}");
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,250 @@
+//! CSRF protection via double-submit cookies for the route groups configured in [CsrfSettings]:
+//! clients get a random token in a cookie readable by scripts, and state-changing requests of
+//! session authenticated clients, i.e. with the session cookie, must repeat it in the configured
+//! header, which other sites cannot do.
+//!
+//! Requests authenticated with a verified API key or bearer token are not affected, because
+//! browsers do not send these along automatically. Other credentials, e.g. an `Authorization`
+//! header with another scheme, do not exempt requests, because an attacker could add them while
+//! the session cookie still authenticates.
+
+use crate::api_key::ApiKeyId;
+use crate::auth::Claims;
+use crate::error::Error;
+use crate::has_path_prefix;
+use crate::hmac::{constant_time_eq, to_hex};
+use crate::sessions::cookie;
+use crate::settings::{CsrfSettings, SessionSettings};
+use axum::http::header::SET_COOKIE;
+use axum::http::{HeaderValue, Method, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+
+/// The CSRF token of the current request, e.g. for embedding it into rendered pages, available
+/// as `Extension<CsrfToken>`.
+#[derive(Debug, Clone)]
+pub struct CsrfToken(pub String);
+
+/// Shared state of the [protect] middleware.
+#[derive(Debug, Clone)]
+pub struct Csrf {
+    settings: Arc<CsrfSettings>,
+    session_cookie_name: Arc<str>,
+}
+
+impl Csrf {
+    /// The configured CSRF protection, if any paths and sessions are configured.
+    pub fn new(settings: &CsrfSettings, session_settings: &SessionSettings) -> Option<Self> {
+        (!settings.paths.is_empty() && session_settings.secret.is_some()).then(|| Self {
+            settings: Arc::new(settings.clone()),
+            session_cookie_name: session_settings.cookie_name.as_str().into(),
+        })
+    }
+
+    fn cookie(&self, token: &str) -> Option<HeaderValue> {
+        let mut cookie = format!(
+            "{}={token}; Path=/; SameSite=Lax",
+            self.settings.cookie_name
+        );
+        if self.settings.secure {
+            cookie.push_str("; Secure");
+        }
+        HeaderValue::from_str(&cookie).ok()
+    }
+}
+
+/// Middleware issuing CSRF tokens and rejecting state-changing requests without a matching one
+/// with 403, see [crate::csrf].
+pub async fn protect<B>(mut request: Request<B>, next: Next<B>, csrf: Csrf) -> Response {
+    let path = request.uri().path();
+    let applies = csrf
+        .settings
+        .paths
+        .iter()
+        .any(|prefix| has_path_prefix(path, prefix))
+        && request.extensions().get::<ApiKeyId>().is_none()
+        && request.extensions().get::<Claims>().is_none();
+    if !applies {
+        return next.run(request).await;
+    }
+
+    let token =
+        cookie(request.headers(), &csrf.settings.cookie_name).filter(|token| !token.is_empty());
+    let session_authenticated = cookie(request.headers(), &csrf.session_cookie_name).is_some();
+    if session_authenticated && !is_safe(request.method()) {
+        let submitted = request
+            .headers()
+            .get(csrf.settings.header.as_str())
+            .map(HeaderValue::as_bytes);
+        let valid = token
+            .as_ref()
+            .zip(submitted)
+            .map_or(false, |(token, submitted)| {
+                constant_time_eq(token.as_bytes(), submitted)
+            });
+        if !valid {
+            return Error::Forbidden("Missing or invalid CSRF token".to_string()).into_response();
+        }
+    }
+
+    match token {
+        Some(token) => {
+            request.extensions_mut().insert(CsrfToken(token));
+            next.run(request).await
+        }
+
+        None => {
+            let token = to_hex(&rand::random::<[u8; 32]>());
+            request.extensions_mut().insert(CsrfToken(token.clone()));
+            let mut response = next.run(request).await;
+            if let Some(cookie) = csrf.cookie(&token) {
+                response.headers_mut().append(SET_COOKIE, cookie);
+            }
+            response
+        }
+    }
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use axum::body::Body;
+    use axum::extract::Extension;
+    use axum::http::header::COOKIE;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let settings = CsrfSettings {
+            paths: vec!["/app".to_string()],
+            ..Default::default()
+        };
+        let session_settings = SessionSettings {
+            secret: Some(Secret::new("0123456789abcdef0123456789abcdef".to_string())),
+            ..Default::default()
+        };
+        let csrf = Csrf::new(&settings, &session_settings).unwrap();
+        let handler = |token: Option<Extension<CsrfToken>>| async move {
+            token.map(|Extension(token)| token.0).unwrap_or_default()
+        };
+        Router::new()
+            .route("/app", get(handler).post(handler))
+            .route("/api", get(handler).post(handler))
+            .layer(middleware::from_fn(move |request, next| {
+                protect(request, next, csrf.clone())
+            }))
+    }
+
+    fn request(method: Method, path: &str, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri(path);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_issue_token() {
+        let response = app()
+            .oneshot(request(Method::GET, "/app", &[]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap().to_owned();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let token = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(token.len(), 64);
+        assert_eq!(
+            set_cookie,
+            format!("csrf_token={token}; Path=/; SameSite=Lax; Secure")
+        );
+
+        // An existing token is kept.
+        let response = app()
+            .oneshot(request(
+                Method::GET,
+                "/app",
+                &[(COOKIE.as_str(), "csrf_token=abc")],
+            ))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(SET_COOKIE));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"abc");
+    }
+
+    #[tokio::test]
+    async fn test_protect() {
+        let cookies = (COOKIE.as_str(), "session=s1; csrf_token=abc");
+        let status = |request| async { app().oneshot(request).await.unwrap().status() };
+
+        let ok = request(Method::POST, "/app", &[cookies, ("x-csrf-token", "abc")]);
+        assert_eq!(status(ok).await, StatusCode::OK);
+
+        let missing = request(Method::POST, "/app", &[cookies]);
+        assert_eq!(status(missing).await, StatusCode::FORBIDDEN);
+
+        let mismatched = request(Method::POST, "/app", &[cookies, ("x-csrf-token", "abd")]);
+        assert_eq!(status(mismatched).await, StatusCode::FORBIDDEN);
+
+        let without_cookie = request(
+            Method::POST,
+            "/app",
+            &[(COOKIE.as_str(), "session=s1"), ("x-csrf-token", "")],
+        );
+        assert_eq!(status(without_cookie).await, StatusCode::FORBIDDEN);
+
+        // Safe methods, requests without session cookie and other paths are not affected.
+        let safe = request(Method::GET, "/app", &[cookies]);
+        assert_eq!(status(safe).await, StatusCode::OK);
+        let no_session = request(Method::POST, "/app", &[(COOKIE.as_str(), "csrf_token=abc")]);
+        assert_eq!(status(no_session).await, StatusCode::OK);
+        let other_path = request(Method::POST, "/api", &[cookies]);
+        assert_eq!(status(other_path).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_exempt() {
+        let cookies = (COOKIE.as_str(), "session=s1; csrf_token=abc");
+
+        let mut with_api_key = request(Method::POST, "/app", &[cookies]);
+        with_api_key
+            .extensions_mut()
+            .insert(ApiKeyId("key1".to_string()));
+        let response = app().oneshot(with_api_key).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut with_claims = request(Method::POST, "/app", &[cookies]);
+        with_claims.extensions_mut().insert(Claims {
+            sub: Some("alice".to_string()),
+            iss: None,
+            aud: vec![],
+            exp: 0,
+            nbf: None,
+            iat: None,
+            other: Default::default(),
+        });
+        let response = app().oneshot(with_claims).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Other credentials do not exempt requests.
+        let with_basic = request(
+            Method::POST,
+            "/app",
+            &[cookies, ("authorization", "Basic YQ==")],
+        );
+        let response = app().oneshot(with_basic).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
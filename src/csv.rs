@@ -0,0 +1,425 @@
+//! CSV export responses, see [Csv].
+
+use crate::log_error_chain;
+use axum::body::StreamBody;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::HeaderValue;
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use futures_util::{Stream, StreamExt};
+use serde::ser::{Error as _, Impossible, SerializeMap, SerializeStruct};
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+const TEXT_CSV: &str = "text/csv; charset=utf-8";
+
+/// Response streaming the rows of the given stream as `text/csv` attachment with the given file
+/// name, e.g. for reports. Iterators can be converted with [futures_util::stream::iter].
+///
+/// Rows must serialize as structs or maps, the header line is taken from the field names of the
+/// first row, hence there is no header if there are no rows. Later rows are written by these
+/// names, fields they lack, e.g. skipped via `skip_serializing_if`, are empty and fields not in
+/// the header are an error. Fields are rendered as their JSON values, strings unquoted and `null`
+/// as empty string, and escaped according to RFC 4180. Strings starting with `=`, `+`, `-`, `@`,
+/// tab or carriage return are prefixed with `'`, such that spreadsheets do not evaluate them as
+/// formulas. Like for [crate::ndjson::Ndjson], errors abort the response.
+#[derive(Debug)]
+pub struct Csv<S> {
+    rows: S,
+    filename: String,
+}
+
+impl<S> Csv<S> {
+    pub fn new(rows: S, filename: impl Into<String>) -> Self {
+        Self {
+            rows,
+            filename: filename.into(),
+        }
+    }
+}
+
+impl<S, T, E> IntoResponse for Csv<S>
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Serialize,
+    E: Into<BoxError>,
+{
+    fn into_response(self) -> Response {
+        let mut header = None;
+        let lines = self.rows.map(move |row| {
+            let lines = row.map_err(Into::into).and_then(|row| {
+                let row = row.serialize(RowSerializer)?;
+                let mut lines = String::new();
+                match &header {
+                    None => {
+                        write_record(&row.names, &mut lines);
+                        write_record(&row.values, &mut lines);
+                        header = Some(row.names);
+                    }
+                    Some(names) => write_record(&row.by_names(names)?, &mut lines),
+                }
+                Ok(lines)
+            });
+            if let Err(e) = &lines {
+                log_error_chain("Cannot stream CSV response", e.as_ref());
+            }
+            lines
+        });
+
+        let mut response = StreamBody::new(lines).into_response();
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(TEXT_CSV));
+        if let Ok(disposition) = HeaderValue::from_str(&format!(
+            r#"attachment; filename="{}""#,
+            sanitize(&self.filename)
+        )) {
+            headers.insert(CONTENT_DISPOSITION, disposition);
+        }
+        response
+    }
+}
+
+fn write_record(fields: &[String], lines: &mut String) {
+    for (n, field) in fields.iter().enumerate() {
+        if n > 0 {
+            lines.push(',');
+        }
+        if field.contains(&[',', '"', '\r', '\n'][..]) {
+            lines.push('"');
+            lines.push_str(&field.replace('"', r#""""#));
+            lines.push('"');
+        } else {
+            lines.push_str(field);
+        }
+    }
+    lines.push_str("\r\n");
+}
+
+/// Replaces all characters but printable ASCII ones and those with a special meaning in quoted
+/// strings, such that the file name can be used in `Content-Disposition`.
+fn sanitize(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii_graphic() || c == ' ' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn field<T>(value: &T) -> Result<String, serde_json::Error>
+where
+    T: Serialize + ?Sized,
+{
+    let field = match serde_json::to_value(value)? {
+        Value::Null => String::new(),
+        Value::String(s) => escape_formula(s),
+        value => value.to_string(),
+    };
+    Ok(field)
+}
+
+/// Prefixes strings which spreadsheets would evaluate as formulas with `'`; numbers, e.g. `-1`,
+/// are not strings, hence unaffected.
+fn escape_formula(s: String) -> String {
+    if s.starts_with(&['=', '+', '-', '@', '\t', '\r'][..]) {
+        format!("'{s}")
+    } else {
+        s
+    }
+}
+
+#[derive(Debug, Default)]
+struct Row {
+    names: Vec<String>,
+    values: Vec<String>,
+}
+
+impl Row {
+    /// The values in the order of the given names, empty for missing ones.
+    fn by_names(mut self, names: &[String]) -> Result<Vec<String>, serde_json::Error> {
+        if self.names == names {
+            return Ok(self.values);
+        }
+        if let Some(name) = self.names.iter().find(|name| !names.contains(name)) {
+            return Err(serde_json::Error::custom(format!(
+                "CSV row has field {name}, which is not in the header"
+            )));
+        }
+        let values = names
+            .iter()
+            .map(|name| match self.names.iter().position(|n| n == name) {
+                Some(n) => std::mem::take(&mut self.values[n]),
+                None => String::new(),
+            })
+            .collect();
+        Ok(values)
+    }
+}
+
+impl SerializeStruct for Row {
+    type Ok = Row;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T>(&mut self, name: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.names.push(name.to_owned());
+        self.values.push(field(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self)
+    }
+}
+
+impl SerializeMap for Row {
+    type Ok = Row;
+    type Error = serde_json::Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.names.push(field(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.values.push(field(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self)
+    }
+}
+
+/// Serializer for rows, only supporting structs and maps.
+struct RowSerializer;
+
+fn unsupported() -> serde_json::Error {
+    serde_json::Error::custom("CSV rows must be structs or maps")
+}
+
+macro_rules! unsupported {
+    ($($method:ident($($ty:ty),*)),* $(,)?) => {
+        $(
+            fn $method(self, $(_: $ty),*) -> Result<Self::Ok, Self::Error> {
+                Err(unsupported())
+            }
+        )*
+    };
+}
+
+impl Serializer for RowSerializer {
+    type Ok = Row;
+    type Error = serde_json::Error;
+    type SerializeSeq = Impossible<Row, Self::Error>;
+    type SerializeTuple = Impossible<Row, Self::Error>;
+    type SerializeTupleStruct = Impossible<Row, Self::Error>;
+    type SerializeTupleVariant = Impossible<Row, Self::Error>;
+    type SerializeMap = Row;
+    type SerializeStruct = Row;
+    type SerializeStructVariant = Impossible<Row, Self::Error>;
+
+    unsupported! {
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(unsupported())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Err(unsupported())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Err(unsupported())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Err(unsupported())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(unsupported())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(Row::default())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(Row::default())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use serde_json::json;
+    use std::convert::Infallible;
+
+    #[derive(Serialize)]
+    struct Order {
+        id: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+        total: f64,
+    }
+
+    async fn body(response: Response) -> String {
+        let body = hyper::body::to_bytes(response.into_body()).await;
+        String::from_utf8(body.unwrap_or_default().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_write_record() {
+        let mut lines = String::new();
+        let fields = ["a", "b,c", r#"say "hi""#, "x\ny", "", "\r"].map(str::to_owned);
+        write_record(&fields, &mut lines);
+        assert_eq!(lines, "a,\"b,c\",\"say \"\"hi\"\"\",\"x\ny\",,\"\r\"\r\n");
+    }
+
+    #[test]
+    fn test_field() {
+        assert_eq!(field("plain").unwrap(), "plain");
+        assert_eq!(field(&json!(null)).unwrap(), "");
+        assert_eq!(field(&-1).unwrap(), "-1");
+        assert_eq!(field(&1.5).unwrap(), "1.5");
+        assert_eq!(field(&json!({ "a": 1 })).unwrap(), r#"{"a":1}"#);
+        for formula in ["=1+1", "+1", "-1", "@SUM(A1)", "\tx", "\rx"] {
+            assert_eq!(field(formula).unwrap(), format!("'{formula}"));
+        }
+        assert_eq!(field("a=1").unwrap(), "a=1");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("report 2024.csv"), "report 2024.csv");
+        assert_eq!(sanitize("a\"b\\c\r\nä.csv"), "a_b_c___.csv");
+    }
+
+    #[tokio::test]
+    async fn test_rows() {
+        let orders = vec![
+            Ok::<_, Infallible>(Order {
+                id: 1,
+                note: Some("=HYPERLINK()".to_string()),
+                total: 2.5,
+            }),
+            Ok(Order {
+                id: 2,
+                note: None,
+                total: -1.0,
+            }),
+        ];
+        let response = Csv::new(stream::iter(orders), "orders.csv").into_response();
+        assert_eq!(response.headers()[CONTENT_TYPE], TEXT_CSV);
+        assert_eq!(
+            response.headers()[CONTENT_DISPOSITION],
+            r#"attachment; filename="orders.csv""#
+        );
+        assert_eq!(
+            body(response).await,
+            "id,note,total\r\n1,'=HYPERLINK(),2.5\r\n2,,-1.0\r\n"
+        );
+
+        let rows = vec![
+            Ok::<_, Infallible>(json!({ "a": 1, "b": 2 })),
+            Ok(json!({ "b": 3, "a": 4 })),
+            Ok(json!({ "b": 5 })),
+        ];
+        let response = Csv::new(stream::iter(rows), "rows.csv").into_response();
+        assert_eq!(body(response).await, "a,b\r\n1,2\r\n4,3\r\n,5\r\n");
+    }
+
+    #[test]
+    fn test_mismatched_row() {
+        let names = ["a", "b"].map(str::to_owned);
+        let row = json!({ "a": 1, "c": 2 }).serialize(RowSerializer).unwrap();
+        let error = row.by_names(&names).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "CSV row has field c, which is not in the header"
+        );
+        assert!(json!(1).serialize(RowSerializer).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! In-process publish/subscribe of typed [DomainEvent]s, e.g. "user created", such that modules
+//! can react to each other's events without depending on each other.
+//!
+//! Each event type has its own broadcast channel. Every [EventHandler] gets all events published
+//! after its registration, in order, in its own task; its errors and panics are logged, but affect
+//! neither the publisher nor other handlers. Handlers more than `events.domain_capacity` events
+//! behind skip the oldest ones. Events are counted as `domain_events_published_total` and
+//! `domain_events_handled_total` by event type and outcome.
+
+use crate::log_error_chain;
+use crate::metrics::increment_counter;
+use crate::panic::panic_message;
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::FutureExt;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+
+/// An event published via [DomainEvents], identified by its `NAME`, e.g. `user_created`.
+pub trait DomainEvent: Clone + Debug + Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+/// Handles the events of one type, registered via [DomainEvents::register].
+#[async_trait]
+pub trait EventHandler<E>: Send + Sync + 'static
+where
+    E: DomainEvent,
+{
+    /// Used in logs and metrics.
+    fn name(&self) -> &'static str;
+
+    async fn handle(&self, event: E) -> Result<()>;
+}
+
+/// Cheap to clone, all clones share the channels.
+#[derive(Clone)]
+pub struct DomainEvents {
+    /// [broadcast::Sender]s by event type.
+    channels: Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send>>>>,
+    capacity: usize,
+}
+
+impl Debug for DomainEvents {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DomainEvents")
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DomainEvents {
+    /// Domain events with channels buffering the given number of events per type.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            channels: Default::default(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Publish the given event to all handlers of its type, returning their number.
+    pub fn publish<E>(&self, event: E) -> usize
+    where
+        E: DomainEvent,
+    {
+        increment_counter(
+            "domain_events_published_total",
+            &[("event", E::NAME.to_string())],
+        );
+        // Without handlers sending fails, which is fine.
+        self.sender::<E>().send(event).unwrap_or_default()
+    }
+
+    /// Handle all events of type `E` published from now on with the given handler in a task of
+    /// its own, running until all clones of these domain events have been dropped.
+    pub fn register<E, H>(&self, handler: H) -> JoinHandle<()>
+    where
+        E: DomainEvent,
+        H: EventHandler<E>,
+    {
+        let mut receiver = self.sender::<E>().subscribe();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => handle(&handler, event).await,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!(
+                            event = E::NAME,
+                            handler = handler.name(),
+                            n,
+                            "Event handler lagging, events skipped"
+                        );
+                        count_handled::<E>(handler.name(), "skipped");
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        })
+    }
+
+    fn sender<E>(&self) -> broadcast::Sender<E>
+    where
+        E: DomainEvent,
+    {
+        let mut channels = self.channels.lock().expect("channels can be locked");
+        channels
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(broadcast::channel::<E>(self.capacity).0))
+            .downcast_ref::<broadcast::Sender<E>>()
+            .expect("channel has sender for event type")
+            .clone()
+    }
+}
+
+async fn handle<E, H>(handler: &H, event: E)
+where
+    E: DomainEvent,
+    H: EventHandler<E>,
+{
+    let outcome = match AssertUnwindSafe(handler.handle(event)).catch_unwind().await {
+        Ok(Ok(())) => "handled",
+        Ok(Err(e)) => {
+            let message = format!("Event handler {} failed for {}", handler.name(), E::NAME);
+            log_error_chain(&message, e.as_ref());
+            "failed"
+        }
+        Err(panic) => {
+            error!(
+                message = "Event handler panicked",
+                event = E::NAME,
+                handler = handler.name(),
+                panic = panic_message(&panic)
+            );
+            "panicked"
+        }
+    };
+    count_handled::<E>(handler.name(), outcome);
+}
+
+fn count_handled<E>(handler: &'static str, outcome: &'static str)
+where
+    E: DomainEvent,
+{
+    increment_counter(
+        "domain_events_handled_total",
+        &[
+            ("event", E::NAME.to_string()),
+            ("handler", handler.to_string()),
+            ("outcome", outcome.to_string()),
+        ],
+    );
+}
@@ -0,0 +1,246 @@
+use crate::log_error_chain;
+use crate::request_id::RequestId;
+use crate::validation::FieldErrors;
+use axum::body::{boxed, Full};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER};
+use axum::http::{HeaderValue, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+const APPLICATION_PROBLEM_JSON: &str = "application/problem+json";
+
+/// Errors handlers can return, rendered as [Problem]s with the respective status code. Details of
+/// internal errors are logged, but only exposed if configured, see [complete_problem].
+#[derive(Debug)]
+pub enum Error {
+    Validation(String),
+    NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Conflict(String),
+    NotAcceptable(String),
+    UnsupportedMediaType(String),
+    PayloadTooLarge(String),
+    /// Rendered with the errors per field, see [crate::validation::ValidatedJson].
+    Unprocessable(FieldErrors),
+    /// Rendered with a `Retry-After` header for the given duration, rounded up to full seconds.
+    TooManyRequests(Duration),
+    /// Rendered with the given detail and a `Retry-After` header like [Error::TooManyRequests].
+    Unavailable(String, Duration),
+    /// An upstream, e.g. of [crate::proxy], has failed.
+    BadGateway(String),
+    Internal(anyhow::Error),
+}
+
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            Error::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Error::Unavailable(_, _) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Validation(message) => write!(f, "Invalid request: {message}"),
+            Error::NotFound(message) => write!(f, "Not found: {message}"),
+            Error::Unauthorized(message) => write!(f, "Unauthorized: {message}"),
+            Error::Forbidden(message) => write!(f, "Forbidden: {message}"),
+            Error::Conflict(message) => write!(f, "Conflict: {message}"),
+            Error::NotAcceptable(message) => write!(f, "Not acceptable: {message}"),
+            Error::UnsupportedMediaType(message) => write!(f, "Unsupported media type: {message}"),
+            Error::PayloadTooLarge(message) => write!(f, "Payload too large: {message}"),
+            Error::Unprocessable(_) => write!(f, "Invalid request body"),
+            Error::TooManyRequests(retry_after) => {
+                write!(f, "Too many requests, retry after {retry_after:?}")
+            }
+            Error::Unavailable(message, _) => write!(f, "Unavailable: {message}"),
+            Error::BadGateway(message) => write!(f, "Bad gateway: {message}"),
+            Error::Internal(_) => write!(f, "Internal error"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Internal(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Error::Internal(e)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let problem = Problem::new(self.status());
+        match self {
+            Error::Validation(detail)
+            | Error::NotFound(detail)
+            | Error::Unauthorized(detail)
+            | Error::Forbidden(detail)
+            | Error::Conflict(detail)
+            | Error::NotAcceptable(detail)
+            | Error::UnsupportedMediaType(detail)
+            | Error::PayloadTooLarge(detail)
+            | Error::BadGateway(detail) => problem.with_detail(detail).into_response(),
+            Error::Unprocessable(errors) => problem
+                .with_detail("Invalid request body")
+                .with_errors(errors.into_inner())
+                .into_response(),
+            Error::TooManyRequests(retry_after) => {
+                let retry_after = retry_after_secs(retry_after);
+                let response = problem
+                    .with_detail(format!("Rate limit exceeded, retry after {retry_after}s"))
+                    .into_response();
+                with_retry_after(response, retry_after)
+            }
+            Error::Unavailable(detail, retry_after) => {
+                let response = problem.with_detail(detail).into_response();
+                with_retry_after(response, retry_after_secs(retry_after))
+            }
+            Error::Internal(e) => {
+                let problem = problem.with_internal_detail(format!("{e:#}"));
+                log_error_chain("Internal error handling request", e.as_ref());
+                problem.into_response()
+            }
+        }
+    }
+}
+
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64
+}
+
+fn with_retry_after(mut response: Response, retry_after_secs: u64) -> Response {
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after_secs));
+    response
+}
+
+/// Problem details according to RFC 7807, rendered as `application/problem+json`.
+///
+/// The rendered [Response] carries the [Problem] in its extensions, such that [complete_problem]
+/// can add the request ID as `instance` and optionally internal details.
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Validation errors by field name, see [Error::Unprocessable].
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<String, Vec<String>>,
+    #[serde(skip)]
+    internal_detail: Option<String>,
+}
+
+impl Problem {
+    /// A problem without a specific type, i.e. `about:blank`, titled after the status code.
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            type_uri: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: None,
+            instance: None,
+            errors: BTreeMap::new(),
+            internal_detail: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_errors(mut self, errors: BTreeMap<String, Vec<String>>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// Details only exposed in the `detail` field if configured, see [complete_problem].
+    pub fn with_internal_detail(mut self, internal_detail: impl Into<String>) -> Self {
+        self.internal_detail = Some(internal_detail.into());
+        self
+    }
+
+    fn body(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Problem can be serialized")
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(boxed(Full::from(self.body())));
+        *response.status_mut() =
+            StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(APPLICATION_PROBLEM_JSON),
+        );
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// Middleware completing [Problem] responses with the [RequestId] as `instance` and, if
+/// `expose_internal_details` is true, which should only be the case in development, with
+/// internal details as `detail`.
+pub async fn complete_problem<B>(
+    request: Request<B>,
+    next: Next<B>,
+    expose_internal_details: bool,
+) -> Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(ToOwned::to_owned);
+
+    let response = next.run(request).await;
+    let mut problem = match response.extensions().get::<Problem>() {
+        Some(problem) => problem.clone(),
+        None => return response,
+    };
+
+    problem.instance = request_id;
+    if expose_internal_details && problem.detail.is_none() {
+        problem.detail = problem.internal_detail.take();
+    }
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.extensions.insert(problem.clone());
+    Response::from_parts(parts, boxed(Full::from(problem.body())))
+}
@@ -0,0 +1,133 @@
+//! Server-sent events from an application level [EventBus], served at `/events`.
+
+use crate::state::AppState;
+use axum::extract::Extension;
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use axum::Router;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+const LAST_EVENT_ID: &str = "last-event-id";
+
+/// A published event; IDs are assigned in ascending order.
+#[derive(Debug, Clone)]
+pub struct AppEvent {
+    pub id: u64,
+    pub event: &'static str,
+    /// JSON serialized data.
+    pub data: String,
+}
+
+/// Broadcasts published events to all subscribers and keeps the most recent ones, such that
+/// clients can resume after reconnecting.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<AppEvent>,
+    recent: Arc<Mutex<Recent>>,
+}
+
+#[derive(Debug)]
+struct Recent {
+    events: VecDeque<AppEvent>,
+    capacity: usize,
+    next_id: u64,
+}
+
+impl EventBus {
+    /// An event bus keeping the given number of most recent events for resuming.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        let recent = Recent {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            next_id: 1,
+        };
+        Self {
+            sender,
+            recent: Arc::new(Mutex::new(recent)),
+        }
+    }
+
+    /// Publish an event of the given type with the given data serialized as JSON and return its
+    /// ID.
+    pub fn publish(&self, event: &'static str, data: impl Serialize) -> serde_json::Result<u64> {
+        let data = serde_json::to_string(&data)?;
+
+        // Sending while holding the lock makes subscribing and taking the recent events atomic,
+        // see [EventBus::subscribe].
+        let mut recent = self.recent.lock().expect("recent events can be locked");
+        let id = recent.next_id;
+        recent.next_id += 1;
+        let event = AppEvent { id, event, data };
+        if recent.capacity > 0 {
+            if recent.events.len() == recent.capacity {
+                recent.events.pop_front();
+            }
+            recent.events.push_back(event.clone());
+        }
+        // Without subscribers sending fails, which is fine.
+        let _ = self.sender.send(event);
+
+        Ok(id)
+    }
+
+    /// Events published after the one with the given ID, as far as they are still kept, followed
+    /// by all events published from now on.
+    pub fn subscribe(&self, last_event_id: Option<u64>) -> impl Stream<Item = AppEvent> {
+        let recent = self.recent.lock().expect("recent events can be locked");
+        let receiver = self.sender.subscribe();
+        let missed = match last_event_id {
+            Some(last_event_id) => recent
+                .events
+                .iter()
+                .filter(|event| event.id > last_event_id)
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        drop(recent);
+
+        let published = stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(n)) => warn!(n, "Subscriber lagging, events skipped"),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        stream::iter(missed).chain(published)
+    }
+}
+
+/// Routes for `/events`, streaming the events of the [AppState]'s [EventBus], sending a heartbeat
+/// comment at the configured interval and resuming after the event with the ID from the
+/// `Last-Event-ID` header, if given.
+pub fn routes() -> Router {
+    Router::new().route("/events", get(events))
+}
+
+async fn events(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let heartbeat_interval = state.settings.events.heartbeat_interval;
+    let last_event_id = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok());
+    let events = state.event_bus.subscribe(last_event_id).map(|event| {
+        Ok(Event::default()
+            .id(event.id.to_string())
+            .event(event.event)
+            .data(event.data))
+    });
+    Sse::new(events).keep_alive(KeepAlive::new().interval(heartbeat_interval))
+}
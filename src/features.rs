@@ -0,0 +1,478 @@
+//! Feature flags defined in the settings, see [FlagSettings], evaluated per request via the
+//! [Flags] extractor and overridable at runtime via `/admin/features`. The flags are reloaded on
+//! SIGHUP, see [apply_flag_changes].
+
+use crate::api_key::ApiKeyId;
+use crate::auth::Claims;
+use crate::error::Error;
+use crate::sessions::{self, Session};
+use crate::settings::{FlagSettings, Settings};
+use crate::state::AppState;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use axum::http::HeaderMap;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::watch;
+use tracing::info;
+
+/// Header for enabling or disabling flags which allow it, e.g. `new_checkout,-dark_mode`.
+pub const X_FEATURES: &str = "x-features";
+
+/// Header identifying the user for percentage rollouts, only trusted for requests which are
+/// neither authenticated nor carry a session with a user, see [Caller::identity].
+pub const X_USER_ID: &str = "x-user-id";
+
+/// The configured flags and their runtime overrides, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Features {
+    flags: Arc<RwLock<BTreeMap<String, FlagSettings>>>,
+    overrides: Arc<Mutex<BTreeMap<String, bool>>>,
+}
+
+/// A configured flag and its runtime override, if any.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlagStatus {
+    #[serde(flatten)]
+    pub settings: FlagSettings,
+    #[serde(rename = "override")]
+    pub override_: Option<bool>,
+}
+
+impl Features {
+    pub fn new(flags: &BTreeMap<String, FlagSettings>) -> Self {
+        Self {
+            flags: Arc::new(RwLock::new(flags.clone())),
+            overrides: Default::default(),
+        }
+    }
+
+    /// Replace the flags, removing the overrides of flags which no longer exist.
+    pub fn set_flags(&self, flags: &BTreeMap<String, FlagSettings>) {
+        *self.flags.write().expect("flags can be locked") = flags.clone();
+        self.overrides().retain(|name, _| flags.contains_key(name));
+    }
+
+    pub fn list(&self) -> BTreeMap<String, FlagStatus> {
+        let flags = self.flags();
+        let overrides = self.overrides();
+        flags
+            .iter()
+            .map(|(name, settings)| {
+                let status = FlagStatus {
+                    settings: settings.clone(),
+                    override_: overrides.get(name).copied(),
+                };
+                (name.to_owned(), status)
+            })
+            .collect()
+    }
+
+    /// Override the given flag for all requests or, if `None`, remove its override; returns false
+    /// if there is no such flag.
+    pub fn set_override(&self, name: &str, enabled: Option<bool>) -> bool {
+        let flags = self.flags();
+        if !flags.contains_key(name) {
+            return false;
+        }
+        let mut overrides = self.overrides();
+        match enabled {
+            Some(enabled) => overrides.insert(name.to_owned(), enabled),
+            None => overrides.remove(name),
+        };
+        true
+    }
+
+    /// Evaluate all flags for a request with the given headers by the given caller. For each flag,
+    /// the first of these applies: its runtime override, the `x-features` header if the flag
+    /// allows it, its API keys, its claims, its percentage rollout and finally whether it is
+    /// enabled.
+    pub fn evaluate(&self, headers: &HeaderMap, caller: &Caller<'_>) -> Flags {
+        let flags = self.flags();
+        let overrides = self.overrides();
+        let requested = headers
+            .get(X_FEATURES)
+            .and_then(|value| value.to_str().ok())
+            .map(parse_requested)
+            .unwrap_or_default();
+        let identity = caller.identity(headers);
+
+        let enabled = flags
+            .iter()
+            .filter(|(name, settings)| {
+                overrides
+                    .get(*name)
+                    .copied()
+                    .or_else(|| {
+                        requested
+                            .get(name.as_str())
+                            .copied()
+                            .filter(|_| settings.header_override)
+                    })
+                    .unwrap_or_else(|| {
+                        let by_api_key = caller
+                            .api_key_id
+                            .map(|id| settings.api_keys.iter().any(|api_key| api_key == id))
+                            .unwrap_or_default();
+                        let by_claims = caller
+                            .claims
+                            .map(|claims| has_any_claim(claims, &settings.claims))
+                            .unwrap_or_default();
+                        let by_rollout = identity
+                            .map(|identity| bucket(name, identity) < settings.rollout_percent)
+                            .unwrap_or_default();
+                        settings.enabled || by_api_key || by_claims || by_rollout
+                    })
+            })
+            .map(|(name, _)| name.to_owned())
+            .collect();
+        Flags(enabled)
+    }
+
+    /// Locked before the overrides where both are needed.
+    fn flags(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<String, FlagSettings>> {
+        self.flags.read().expect("flags can be locked")
+    }
+
+    fn overrides(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, bool>> {
+        self.overrides.lock().expect("overrides can be locked")
+    }
+}
+
+/// Apply changed `features` settings received via the given receiver, see
+/// [crate::config_watcher].
+pub async fn apply_flag_changes(mut settings_rx: watch::Receiver<Settings>, features: Features) {
+    loop {
+        let changed = settings_rx.borrow_and_update().features.clone();
+        if changed != *features.flags() {
+            info!("Applying changed feature flags");
+            features.set_flags(&changed);
+        }
+        if settings_rx.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Who made a request, as far as relevant for evaluating the flags.
+#[derive(Debug, Clone, Default)]
+pub struct Caller<'a> {
+    pub api_key_id: Option<&'a str>,
+    pub claims: Option<&'a Claims>,
+    /// The user of the session, see [sessions::USER_ID].
+    pub session_user: Option<String>,
+}
+
+impl Caller<'_> {
+    /// The identity for percentage rollouts: the `sub` claim, the API key ID or the session user,
+    /// hence `x-user-id` is only used for unauthenticated requests without a session user, for
+    /// which it cannot be verified anyway.
+    fn identity<'b>(&'b self, headers: &'b HeaderMap) -> Option<&'b str> {
+        if self.api_key_id.is_some() || self.claims.is_some() || self.session_user.is_some() {
+            self.claims
+                .and_then(|claims| claims.sub.as_deref())
+                .or(self.api_key_id)
+                .or(self.session_user.as_deref())
+        } else {
+            headers.get(X_USER_ID).and_then(|value| value.to_str().ok())
+        }
+    }
+}
+
+/// Extractor for the flags evaluated for the current request, see [Features::evaluate].
+#[derive(Debug, Clone, Default)]
+pub struct Flags(BTreeSet<String>);
+
+impl Flags {
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Flags
+where
+    B: Send,
+{
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let extensions = request
+            .extensions()
+            .ok_or_else(|| anyhow!("Extensions already taken"))?;
+        let features = &extensions
+            .get::<AppState>()
+            .ok_or_else(|| anyhow!("AppState missing"))?
+            .features;
+        let caller = Caller {
+            api_key_id: extensions.get::<ApiKeyId>().map(|id| id.0.as_str()),
+            claims: extensions.get::<Claims>(),
+            session_user: extensions
+                .get::<Session>()
+                .and_then(|session| session.get(sessions::USER_ID)),
+        };
+        let headers = request
+            .headers()
+            .ok_or_else(|| anyhow!("Headers already taken"))?;
+        Ok(features.evaluate(headers, &caller))
+    }
+}
+
+/// Flags enabled, e.g. `new_checkout`, or disabled, e.g. `-new_checkout`, via `x-features`.
+fn parse_requested(value: &str) -> BTreeMap<&str, bool> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| match name.strip_prefix('-') {
+            Some(name) => (name, false),
+            None => (name, true),
+        })
+        .collect()
+}
+
+/// Whether any of the given claims has one of the given values, where claims with a list of
+/// values, e.g. `aud` or `groups`, match if any of their values does.
+fn has_any_claim(claims: &Claims, values_by_claim: &BTreeMap<String, Vec<String>>) -> bool {
+    values_by_claim.iter().any(|(name, values)| {
+        let actual = match name.as_str() {
+            "sub" => claims.sub.iter().map(String::as_str).collect(),
+            "iss" => claims.iss.iter().map(String::as_str).collect(),
+            "aud" => claims.aud.iter().map(String::as_str).collect(),
+            _ => match claims.other.get(name) {
+                Some(Value::String(value)) => vec![value.as_str()],
+                Some(Value::Array(elements)) => elements.iter().filter_map(Value::as_str).collect(),
+                _ => vec![],
+            },
+        };
+        actual
+            .iter()
+            .any(|actual| values.iter().any(|value| value == actual))
+    })
+}
+
+/// Stable bucket from 0 to 99 for the given flag and identity, using FNV-1a, such that
+/// identities keep their bucket across restarts and flags are rolled out independently.
+fn bucket(name: &str, identity: &str) -> u8 {
+    let hash = name
+        .bytes()
+        .chain([0])
+        .chain(identity.bytes())
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    (hash % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    fn flags(enabled: bool) -> BTreeMap<String, FlagSettings> {
+        let flag = FlagSettings {
+            enabled,
+            ..Default::default()
+        };
+        [("new_checkout".to_string(), flag)].into_iter().collect()
+    }
+
+    #[tokio::test]
+    async fn test_apply_flag_changes() {
+        let settings = Settings {
+            features: flags(false),
+            ..Default::default()
+        };
+        let (settings_tx, settings_rx) = watch::channel(settings.clone());
+        let features = Features::new(&settings.features);
+        tokio::spawn(apply_flag_changes(settings_rx, features.clone()));
+        assert!(!features
+            .evaluate(&HeaderMap::new(), &Caller::default())
+            .is_enabled("new_checkout"));
+
+        settings_tx.send_replace(Settings {
+            features: flags(true),
+            ..settings
+        });
+        for _ in 0..100 {
+            if features
+                .evaluate(&HeaderMap::new(), &Caller::default())
+                .is_enabled("new_checkout")
+            {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert!(features
+            .evaluate(&HeaderMap::new(), &Caller::default())
+            .is_enabled("new_checkout"));
+    }
+
+    #[test]
+    fn test_set_flags() {
+        let features = Features::new(&flags(false));
+        assert!(features.set_override("new_checkout", Some(true)));
+        assert!(!features.set_override("dark_mode", Some(true)));
+
+        features.set_flags(&flags(false));
+        assert_eq!(features.list()["new_checkout"].override_, Some(true));
+
+        // Overrides of removed flags are removed as well.
+        features.set_flags(&BTreeMap::new());
+        features.set_flags(&flags(false));
+        assert_eq!(features.list()["new_checkout"].override_, None);
+    }
+
+    fn claims(sub: &str, other: Value) -> Claims {
+        Claims {
+            sub: Some(sub.to_string()),
+            iss: None,
+            aud: vec!["bayer-axum".to_string()],
+            exp: 0,
+            nbf: None,
+            iat: None,
+            other: serde_json::from_value(other).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(
+            bucket("new_checkout", "alice"),
+            bucket("new_checkout", "alice")
+        );
+        assert!((0..1000).all(|n| bucket("new_checkout", &n.to_string()) < 100));
+
+        // Roughly the rollout percentage of the identities is in the rollout.
+        let in_rollout = (0..10_000)
+            .filter(|n| bucket("new_checkout", &n.to_string()) < 30)
+            .count();
+        assert!((2_700..3_300).contains(&in_rollout), "{in_rollout}");
+
+        // Flags are rolled out independently.
+        let in_both = (0..10_000)
+            .filter(|n| {
+                bucket("new_checkout", &n.to_string()) < 30
+                    && bucket("dark_mode", &n.to_string()) < 30
+            })
+            .count();
+        assert!((600..1_200).contains(&in_both), "{in_both}");
+    }
+
+    #[test]
+    fn test_identity() {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_USER_ID, "mallory".parse().unwrap());
+        let claims = claims("alice", json!({}));
+
+        let caller = Caller::default();
+        assert_eq!(caller.identity(&headers), Some("mallory"));
+
+        let caller = Caller {
+            api_key_id: Some("key1"),
+            claims: Some(&claims),
+            session_user: Some("bob".to_string()),
+        };
+        assert_eq!(caller.identity(&headers), Some("alice"));
+
+        let caller = Caller {
+            api_key_id: Some("key1"),
+            session_user: Some("bob".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(caller.identity(&headers), Some("key1"));
+
+        let caller = Caller {
+            session_user: Some("bob".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(caller.identity(&headers), Some("bob"));
+
+        // Authenticated requests without a subject cannot claim another identity.
+        let mut claims = claims;
+        claims.sub = None;
+        let caller = Caller {
+            claims: Some(&claims),
+            ..Default::default()
+        };
+        assert_eq!(caller.identity(&headers), None);
+    }
+
+    #[test]
+    fn test_evaluate_rollout() {
+        let flag = FlagSettings {
+            rollout_percent: 50,
+            ..Default::default()
+        };
+        let features = Features::new(&[("new_checkout".to_string(), flag)].into_iter().collect());
+        let in_rollout = (0..100)
+            .map(|n| format!("user{n}"))
+            .find(|user| bucket("new_checkout", user) < 50)
+            .unwrap();
+        let not_in_rollout = (0..100)
+            .map(|n| format!("user{n}"))
+            .find(|user| bucket("new_checkout", user) >= 50)
+            .unwrap();
+
+        let in_rollout_claims = claims(&in_rollout, json!({}));
+        let caller = Caller {
+            claims: Some(&in_rollout_claims),
+            ..Default::default()
+        };
+        let flags = features.evaluate(&HeaderMap::new(), &caller);
+        assert!(flags.is_enabled("new_checkout"));
+
+        // The header does not override the identity of authenticated requests.
+        let mut headers = HeaderMap::new();
+        headers.insert(X_USER_ID, in_rollout.parse().unwrap());
+        let not_in_rollout_claims = claims(&not_in_rollout, json!({}));
+        let caller = Caller {
+            claims: Some(&not_in_rollout_claims),
+            ..Default::default()
+        };
+        assert!(!features
+            .evaluate(&headers, &caller)
+            .is_enabled("new_checkout"));
+
+        let flags = features.evaluate(&headers, &Caller::default());
+        assert!(flags.is_enabled("new_checkout"));
+    }
+
+    #[test]
+    fn test_evaluate_claims() {
+        let flag = FlagSettings {
+            claims: [
+                ("plan".to_string(), vec!["beta".to_string()]),
+                ("groups".to_string(), vec!["testers".to_string()]),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let features = Features::new(&[("new_checkout".to_string(), flag)].into_iter().collect());
+        let evaluate = |other| {
+            let claims = claims("alice", other);
+            let caller = Caller {
+                claims: Some(&claims),
+                ..Default::default()
+            };
+            features
+                .evaluate(&HeaderMap::new(), &caller)
+                .is_enabled("new_checkout")
+        };
+
+        assert!(evaluate(json!({ "plan": "beta" })));
+        assert!(evaluate(json!({ "groups": ["admins", "testers"] })));
+        assert!(!evaluate(json!({ "plan": "free", "groups": ["admins"] })));
+        assert!(!evaluate(json!({ "plan": ["free"], "other": "beta" })));
+        assert!(!evaluate(json!({})));
+        assert!(!features
+            .evaluate(&HeaderMap::new(), &Caller::default())
+            .is_enabled("new_checkout"));
+    }
+}
@@ -0,0 +1,176 @@
+//! Liveness, startup and readiness, the latter aggregating [HealthCheck]s of dependencies, e.g. databases
+//! or downstream services. The checks are run concurrently in the background and the cached
+//! result is served, such that probes neither hammer dependencies nor wait for slow ones.
+
+use crate::settings::HealthSettings;
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{AddExtensionLayer, Json, Router};
+use futures_util::future::join_all;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::time::{interval, timeout};
+
+/// Whether one-time initialization, i.e. the startup hooks of the
+/// [Lifecycle](crate::lifecycle::Lifecycle), has completed; cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct Startup(Arc<AtomicBool>);
+
+impl Startup {
+    pub fn complete(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// A check contributing to readiness, e.g. pinging a database.
+#[async_trait]
+pub trait HealthCheck: Send + Sync + 'static {
+    /// Name of this check, used in the readiness response.
+    fn name(&self) -> &str;
+
+    /// Timeout for this check, defaults to the configured one.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Check whether the respective component is ready.
+    async fn check(&self) -> Result<()>;
+}
+
+/// Registry of [HealthCheck]s backing the readiness endpoint.
+#[derive(Default)]
+pub struct HealthRegistry {
+    checks: Vec<Box<dyn HealthCheck>>,
+}
+
+impl HealthRegistry {
+    pub fn register(&mut self, check: impl HealthCheck) {
+        self.checks.push(Box::new(check));
+    }
+
+    async fn run(&self, default_timeout: Duration) -> Readiness {
+        let results = join_all(self.checks.iter().map(|check| async move {
+            let started = Instant::now();
+            let result = timeout(check.timeout().unwrap_or(default_timeout), check.check()).await;
+            let error = match result {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(format!("{e:#}")),
+                Err(_) => Some("Timeout".to_string()),
+            };
+            let result = CheckResult {
+                ready: error.is_none(),
+                error,
+                duration_millis: started.elapsed().as_millis() as u64,
+            };
+            (check.name().to_owned(), result)
+        }))
+        .await;
+
+        Readiness {
+            ready: results.iter().all(|(_, result)| result.ready),
+            checks: results.into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Readiness {
+    ready: bool,
+    checks: BTreeMap<String, CheckResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CheckResult {
+    ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    duration_millis: u64,
+}
+
+struct Health {
+    registry: HealthRegistry,
+    timeout: Duration,
+    readiness: Mutex<Option<Readiness>>,
+}
+
+impl Health {
+    async fn refresh(&self) -> Readiness {
+        let readiness = self.registry.run(self.timeout).await;
+        *self.readiness.lock().expect("readiness can be locked") = Some(readiness.clone());
+        readiness
+    }
+}
+
+/// Routes for liveness (`/healthz`), startup (`/startupz`), succeeding once the given [Startup]
+/// has completed, and readiness (`/readyz`). The checks of the given registry are run every
+/// `refresh_interval` until the routes are dropped.
+pub fn routes(registry: HealthRegistry, settings: &HealthSettings, startup: Startup) -> Router {
+    let health = Arc::new(Health {
+        registry,
+        timeout: settings.timeout,
+        readiness: Mutex::new(None),
+    });
+    tokio::spawn(refresh(Arc::downgrade(&health), settings.refresh_interval));
+
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/startupz", get(startupz))
+        .route("/readyz", get(readyz))
+        .layer(AddExtensionLayer::new(health))
+        .layer(AddExtensionLayer::new(startup))
+}
+
+async fn refresh(health: Weak<Health>, refresh_interval: Duration) {
+    let mut refresh_interval = interval(refresh_interval);
+    loop {
+        refresh_interval.tick().await;
+        match health.upgrade() {
+            Some(health) => {
+                health.refresh().await;
+            }
+            None => break,
+        }
+    }
+}
+
+async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+async fn startupz(Extension(startup): Extension<Startup>) -> StatusCode {
+    if startup.is_complete() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// Serves the cached result, unless the checks have not completed yet.
+async fn readyz(Extension(health): Extension<Arc<Health>>) -> impl IntoResponse {
+    let readiness = health
+        .readiness
+        .lock()
+        .expect("readiness can be locked")
+        .clone();
+    let readiness = match readiness {
+        Some(readiness) => readiness,
+        None => health.refresh().await,
+    };
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness)).into_response()
+}
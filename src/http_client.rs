@@ -0,0 +1,294 @@
+//! Outgoing HTTP requests with connection pooling, per target timeouts, propagation of the
+//! request ID and the W3C trace context of the incoming request, and latency metrics.
+//!
+//! Requests with idempotent methods are retried with jittered exponential backoff and each target
+//! host has a circuit breaker, see [circuit_breaker].
+//!
+//! Only plain HTTP is supported, because no TLS connector is available yet.
+
+pub mod circuit_breaker;
+
+use crate::health::HealthCheck;
+use crate::metrics::record_histogram;
+use crate::request_id::RequestId;
+use crate::settings::HttpClientSettings;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use axum::body::{Body, Bytes};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, Method, Request, Response, Uri};
+use axum::middleware::Next;
+use axum::response::Response as AxumResponse;
+use circuit_breaker::{CircuitBreakerCheck, CircuitBreakers};
+use hyper::client::HttpConnector;
+use hyper::Client;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::{sleep, timeout};
+
+const TRACEPARENT: &str = "traceparent";
+const X_REQUEST_ID: &str = "x-request-id";
+
+tokio::task_local! {
+    static CONTEXT: RequestContext;
+}
+
+/// The context of the incoming request propagated to outgoing ones.
+#[derive(Debug, Clone)]
+struct RequestContext {
+    request_id: Option<HeaderValue>,
+    trace_id: String,
+    trace_flags: String,
+}
+
+/// Pooled HTTP client, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client<HttpConnector>,
+    settings: Arc<HttpClientSettings>,
+    circuit_breakers: Arc<CircuitBreakers>,
+}
+
+impl HttpClient {
+    pub fn new(settings: &HttpClientSettings) -> Self {
+        let client = Client::builder()
+            .pool_idle_timeout(settings.pool_idle_timeout)
+            .pool_max_idle_per_host(settings.pool_max_idle_per_host)
+            .build_http();
+        Self {
+            client,
+            settings: Arc::new(settings.clone()),
+            circuit_breakers: Arc::new(CircuitBreakers::new(&settings.circuit_breaker)),
+        }
+    }
+
+    /// [HealthCheck](crate::health::HealthCheck) failing while the circuit of any target
+    /// configured in `http_client.targets` is open.
+    pub fn health_check(&self) -> CircuitBreakerCheck {
+        CircuitBreakerCheck {
+            circuit_breakers: self.circuit_breakers.clone(),
+            targets: self.settings.targets.keys().cloned().collect(),
+        }
+    }
+
+    /// [HealthCheck](crate::health::HealthCheck) with the given name, ready if `GET` of the
+    /// given URL responds with 2xx.
+    pub fn http_check(&self, name: &str, url: Uri, timeout: Option<Duration>) -> HttpCheck {
+        HttpCheck {
+            name: name.to_owned(),
+            url,
+            timeout,
+            client: self.clone(),
+        }
+    }
+
+    pub async fn get(&self, uri: Uri) -> Result<Response<Body>> {
+        let request = Request::get(uri)
+            .body(Body::empty())
+            .context("Cannot create request")?;
+        self.request(request).await
+    }
+
+    /// Send the given request with the timeout configured for its target host. Within the
+    /// handling of an incoming request, its request ID and trace ID are propagated.
+    ///
+    /// Requests with idempotent methods are retried after errors, timeouts and 502, 503 or 504
+    /// responses. Errors, timeouts and 5xx responses count as failures for the circuit breaker of
+    /// the target host; while its circuit is open, requests fail immediately.
+    ///
+    /// The latency of each attempt is recorded as `http_client_request_duration_seconds` by
+    /// target host, method and status code, or `error` or `timeout` if there is no response.
+    pub async fn request(&self, request: Request<Body>) -> Result<Response<Body>> {
+        let target = request.uri().host().unwrap_or_default().to_owned();
+        if !self.circuit_breakers.allow(&target) {
+            bail!("Circuit for {target} open");
+        }
+
+        let max_retries = self.settings.retry.max_retries;
+        if max_retries == 0 || !is_idempotent(request.method()) {
+            return self.send(request, &target).await;
+        }
+
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body)
+            .await
+            .context("Cannot read request body")?;
+        let mut retries = 0;
+        loop {
+            let result = self.send(copy_request(&parts, &body), &target).await;
+            let retry = match &result {
+                Ok(response) => matches!(response.status().as_u16(), 502..=504),
+                Err(_) => true,
+            };
+            if !retry || retries == max_retries || !self.circuit_breakers.allow(&target) {
+                return result;
+            }
+            sleep(self.settings.retry.backoff(retries)).await;
+            retries += 1;
+        }
+    }
+
+    /// Send the given request once without buffering its body, e.g. to forward it, with the
+    /// timeout configured for its target host applying to receiving the response head. Like for
+    /// [HttpClient::request], context is propagated and the circuit breaker is used.
+    pub async fn forward(&self, request: Request<Body>) -> Result<Response<Body>> {
+        let target = request.uri().host().unwrap_or_default().to_owned();
+        if !self.circuit_breakers.allow(&target) {
+            bail!("Circuit for {target} open");
+        }
+        self.send(request, &target).await
+    }
+
+    async fn send(&self, mut request: Request<Body>, target: &str) -> Result<Response<Body>> {
+        let method = request.method().clone();
+        inject_context(&mut request);
+
+        let request_timeout = self.settings.target_timeout(target);
+        let start = Instant::now();
+        let result = timeout(request_timeout, self.client.request(request)).await;
+        let (status, result) = match result {
+            Ok(Ok(response)) => (response.status().as_u16().to_string(), Ok(response)),
+            Ok(Err(e)) => (
+                "error".to_string(),
+                Err(anyhow!(e).context(format!("Request to {target} failed"))),
+            ),
+            Err(_) => (
+                "timeout".to_string(),
+                Err(anyhow!(
+                    "Request to {target} not completed within {request_timeout:?}"
+                )),
+            ),
+        };
+        record(target.to_owned(), &method, status, start.elapsed());
+
+        let success = matches!(&result, Ok(response) if !response.status().is_server_error());
+        self.circuit_breakers.record(target, success);
+        result
+    }
+}
+
+/// See [HttpClient::http_check].
+pub struct HttpCheck {
+    name: String,
+    url: Uri,
+    timeout: Option<Duration>,
+    client: HttpClient,
+}
+
+#[async_trait]
+impl HealthCheck for HttpCheck {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    async fn check(&self) -> Result<()> {
+        let response = self.client.get(self.url.clone()).await?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!("GET {} responded with status {status}", self.url))
+        }
+    }
+}
+
+/// Middleware capturing the request ID and the trace context, from a `traceparent` header or newly
+/// created, of incoming requests for propagation by [HttpClient].
+pub async fn capture_context<B>(request: Request<B>, next: Next<B>) -> AxumResponse {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| id.header_value().clone());
+    let (trace_id, trace_flags) = request
+        .headers()
+        .get(TRACEPARENT)
+        .and_then(|traceparent| traceparent.to_str().ok())
+        .and_then(parse_traceparent)
+        .unwrap_or_else(|| (random_hex(16), "01".to_string()));
+    let context = RequestContext {
+        request_id,
+        trace_id,
+        trace_flags,
+    };
+    CONTEXT.scope(context, next.run(request)).await
+}
+
+fn inject_context(request: &mut Request<Body>) {
+    let context = CONTEXT.try_with(Clone::clone).ok();
+    let headers = request.headers_mut();
+
+    if let Some(request_id) = context.as_ref().and_then(|c| c.request_id.clone()) {
+        headers.insert(X_REQUEST_ID, request_id);
+    }
+
+    let (trace_id, trace_flags) = match context {
+        Some(context) => (context.trace_id, context.trace_flags),
+        None => (random_hex(16), "01".to_string()),
+    };
+    let traceparent = format!("00-{trace_id}-{}-{trace_flags}", random_hex(8));
+    if let Ok(traceparent) = HeaderValue::from_str(&traceparent) {
+        headers.insert(TRACEPARENT, traceparent);
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    )
+}
+
+fn copy_request(parts: &Parts, body: &Bytes) -> Request<Body> {
+    let mut request = Request::new(Body::from(body.clone()));
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+    request
+}
+
+/// Trace ID and trace flags of a version 00 `traceparent` header.
+fn parse_traceparent(traceparent: &str) -> Option<(String, String)> {
+    let parts = traceparent.split('-').collect::<Vec<_>>();
+    match parts[..] {
+        ["00", trace_id, parent_id, flags]
+            if is_hex(trace_id, 32) && is_hex(parent_id, 16) && is_hex(flags, 2) =>
+        {
+            Some((trace_id.to_owned(), flags.to_owned()))
+        }
+        _ => None,
+    }
+}
+
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len
+        && s.bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        && s.bytes().any(|b| b != b'0')
+}
+
+/// Hex encoding of the given number of random bytes.
+fn random_hex(len: usize) -> String {
+    (0..len).fold(String::with_capacity(2 * len), |mut hex, _| {
+        let _ = write!(hex, "{:02x}", rand::random::<u8>());
+        hex
+    })
+}
+
+fn record(target: String, method: &Method, status: String, duration: Duration) {
+    let labels = [
+        ("target", target),
+        ("method", method.to_string()),
+        ("status", status),
+    ];
+    record_histogram(
+        "http_client_request_duration_seconds",
+        &labels,
+        duration.as_secs_f64(),
+    );
+}
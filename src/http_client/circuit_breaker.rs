@@ -0,0 +1,230 @@
+//! Circuit breakers per target host: after `failure_threshold` consecutive failures a circuit
+//! opens and requests are rejected for `open_duration`, after which a single request is let
+//! through to probe whether the target has recovered.
+//!
+//! Only the circuits of the targets configured in `http_client.targets`, i.e. the dependencies of
+//! this service, are part of its readiness, see [CircuitBreakerCheck].
+
+use crate::health::HealthCheck;
+use crate::metrics::set_gauge;
+use crate::settings::CircuitBreakerSettings;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::warn;
+
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    settings: CircuitBreakerSettings,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    failures: u32,
+    state: State,
+}
+
+impl Default for Circuit {
+    fn default() -> Self {
+        Self {
+            failures: 0,
+            state: State::Closed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed,
+    Open(Instant),
+    /// A probing request has been let through at the given time.
+    HalfOpen(Instant),
+}
+
+impl CircuitBreakers {
+    pub fn new(settings: &CircuitBreakerSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+            circuits: Default::default(),
+        }
+    }
+
+    /// Whether a request to the given target may be sent.
+    pub fn allow(&self, target: &str) -> bool {
+        let mut circuits = self.circuits();
+        let circuit = match circuits.get_mut(target) {
+            Some(circuit) => circuit,
+            None => return true,
+        };
+        match circuit.state {
+            State::Closed => true,
+            State::Open(since) | State::HalfOpen(since)
+                if since.elapsed() >= self.settings.open_duration =>
+            {
+                circuit.state = State::HalfOpen(Instant::now());
+                true
+            }
+            State::Open(_) | State::HalfOpen(_) => false,
+        }
+    }
+
+    /// Record the outcome of a request to the given target, opening or closing its circuit.
+    pub fn record(&self, target: &str, success: bool) {
+        if self.settings.failure_threshold == 0 {
+            return;
+        }
+
+        let mut circuits = self.circuits();
+        let circuit = circuits.entry(target.to_owned()).or_default();
+        if success {
+            if !matches!(circuit.state, State::Closed) {
+                set_gauge(
+                    "http_client_circuit_open",
+                    &[("target", target.to_owned())],
+                    0.0,
+                );
+            }
+            *circuit = Circuit::default();
+        } else {
+            circuit.failures += 1;
+            let open = match circuit.state {
+                State::Closed => circuit.failures >= self.settings.failure_threshold,
+                State::Open(_) => false,
+                State::HalfOpen(_) => true,
+            };
+            if open {
+                warn!(target, failures = circuit.failures, "Circuit opened");
+                circuit.state = State::Open(Instant::now());
+                set_gauge(
+                    "http_client_circuit_open",
+                    &[("target", target.to_owned())],
+                    1.0,
+                );
+            }
+        }
+    }
+
+    /// The given targets whose circuits are not closed.
+    fn open_targets<'a>(&self, targets: &'a [String]) -> Vec<&'a str> {
+        let circuits = self.circuits();
+        targets
+            .iter()
+            .filter(|target| {
+                circuits
+                    .get(target.as_str())
+                    .map_or(false, |circuit| !matches!(circuit.state, State::Closed))
+            })
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn circuits(&self) -> std::sync::MutexGuard<'_, HashMap<String, Circuit>> {
+        self.circuits.lock().expect("circuits can be locked")
+    }
+}
+
+/// [HealthCheck] failing while the circuit of any of the given targets is open. Other targets,
+/// e.g. called only once, do not make this service unready.
+pub struct CircuitBreakerCheck {
+    pub(super) circuit_breakers: Arc<CircuitBreakers>,
+    pub(super) targets: Vec<String>,
+}
+
+#[async_trait]
+impl HealthCheck for CircuitBreakerCheck {
+    fn name(&self) -> &str {
+        "http_client_circuits"
+    }
+
+    async fn check(&self) -> Result<()> {
+        let open_targets = self.circuit_breakers.open_targets(&self.targets);
+        if open_targets.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Circuits open for {}", open_targets.join(", ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    fn circuit_breakers(open_duration: Duration) -> Arc<CircuitBreakers> {
+        Arc::new(CircuitBreakers::new(&CircuitBreakerSettings {
+            failure_threshold: 2,
+            open_duration,
+        }))
+    }
+
+    #[test]
+    fn test_transitions() {
+        let circuit_breakers = circuit_breakers(Duration::from_millis(50));
+
+        // Closed: failures below the threshold and successes reset the count.
+        assert!(circuit_breakers.allow("a"));
+        circuit_breakers.record("a", false);
+        circuit_breakers.record("a", true);
+        circuit_breakers.record("a", false);
+        assert!(circuit_breakers.allow("a"));
+
+        // Open after the threshold is reached, other targets are not affected.
+        circuit_breakers.record("a", false);
+        assert!(!circuit_breakers.allow("a"));
+        assert!(circuit_breakers.allow("b"));
+
+        // Half-open after the open duration: a single probe, a failing one opens again.
+        sleep(Duration::from_millis(60));
+        assert!(circuit_breakers.allow("a"));
+        assert!(!circuit_breakers.allow("a"));
+        circuit_breakers.record("a", false);
+        assert!(!circuit_breakers.allow("a"));
+
+        // A successful probe closes the circuit.
+        sleep(Duration::from_millis(60));
+        assert!(circuit_breakers.allow("a"));
+        circuit_breakers.record("a", true);
+        assert!(circuit_breakers.allow("a"));
+        assert!(circuit_breakers.allow("a"));
+        circuit_breakers.record("a", false);
+        assert!(circuit_breakers.allow("a"));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let circuit_breakers = Arc::new(CircuitBreakers::new(&CircuitBreakerSettings {
+            failure_threshold: 0,
+            ..Default::default()
+        }));
+        for _ in 0..10 {
+            circuit_breakers.record("a", false);
+        }
+        assert!(circuit_breakers.allow("a"));
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let circuit_breakers = circuit_breakers(Duration::from_secs(60));
+        let check = CircuitBreakerCheck {
+            circuit_breakers: circuit_breakers.clone(),
+            targets: vec!["orders".to_string()],
+        };
+        assert!(check.check().await.is_ok());
+
+        // Only configured targets make this service unready.
+        circuit_breakers.record("once", false);
+        circuit_breakers.record("once", false);
+        assert!(!circuit_breakers.allow("once"));
+        assert!(check.check().await.is_ok());
+
+        circuit_breakers.record("orders", false);
+        circuit_breakers.record("orders", false);
+        let error = check.check().await.unwrap_err();
+        assert_eq!(error.to_string(), "Circuits open for orders");
+    }
+}
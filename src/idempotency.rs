@@ -0,0 +1,552 @@
+//! Safe retries of `POST` and `PATCH` requests carrying an `Idempotency-Key` header: the first
+//! response is stored in an [IdempotencyStore] and replayed for requests with the same key,
+//! method, path and API key, bearer token subject or session within the configured TTL. Requests
+//! of anonymous clients, i.e. with none of these, are not handled, because their keys could
+//! collide.
+//!
+//! While the first request is in flight, duplicates are rejected with 409. Server errors are not
+//! stored, such that the request can be retried, and neither are requests which are cancelled,
+//! e.g. by the request timeout, nor responses larger than `max_response_size` or of unknown size,
+//! e.g. streamed ones, which are not buffered.
+
+use crate::api_key::ApiKeyId;
+use crate::auth::Claims;
+use crate::error::{Error, Problem};
+use crate::hmac::{sha256, to_hex};
+use crate::log_error_chain;
+use crate::sessions::Session;
+use crate::settings::IdempotencySettings;
+use crate::tenancy::TenantId;
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::body::{boxed, Bytes, Full, HttpBody};
+use axum::http::{HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Set on replayed responses.
+pub const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// How often expired entries of the [InMemoryIdempotencyStore] are removed.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A stored response, including its [Problem], if any, which is completed when replayed.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub problem: Option<Problem>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Reservation {
+    /// The key was unknown and has been reserved for the current request.
+    Reserved,
+    InFlight,
+    Completed(Box<StoredResponse>),
+}
+
+/// Keeps track of idempotency keys and their responses, e.g. in memory or in Redis.
+#[async_trait]
+pub trait IdempotencyStore: Debug + Send + Sync + 'static {
+    /// Reserve the given key for the given TTL unless it is in flight or completed.
+    async fn reserve(&self, key: &str, ttl: Duration) -> Result<Reservation>;
+
+    /// Store the response for the given reserved key.
+    async fn complete(&self, key: &str, response: StoredResponse) -> Result<()>;
+
+    /// Release the given reserved key, e.g. after a server error.
+    async fn release(&self, key: &str) -> Result<()>;
+}
+
+/// [IdempotencyStore] in memory, i.e. not shared across instances.
+#[derive(Debug)]
+pub struct InMemoryIdempotencyStore {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    entries: HashMap<String, Entry>,
+    pruned: Instant,
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: Option<StoredResponse>,
+    expires: Instant,
+}
+
+impl Default for InMemoryIdempotencyStore {
+    fn default() -> Self {
+        let state = State {
+            entries: Default::default(),
+            pruned: Instant::now(),
+        };
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+}
+
+impl InMemoryIdempotencyStore {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("state can be locked")
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for InMemoryIdempotencyStore {
+    async fn reserve(&self, key: &str, ttl: Duration) -> Result<Reservation> {
+        let now = Instant::now();
+        let mut state = self.state();
+        if now.duration_since(state.pruned) >= PRUNE_INTERVAL {
+            state.entries.retain(|_, entry| entry.expires > now);
+            state.pruned = now;
+        }
+
+        match state.entries.get(key) {
+            Some(entry) if entry.expires > now => match &entry.response {
+                Some(response) => Ok(Reservation::Completed(Box::new(response.clone()))),
+                None => Ok(Reservation::InFlight),
+            },
+            _ => {
+                let entry = Entry {
+                    response: None,
+                    expires: now + ttl,
+                };
+                state.entries.insert(key.to_owned(), entry);
+                Ok(Reservation::Reserved)
+            }
+        }
+    }
+
+    async fn complete(&self, key: &str, response: StoredResponse) -> Result<()> {
+        if let Some(entry) = self.state().entries.get_mut(key) {
+            entry.response = Some(response);
+        }
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<()> {
+        self.state().entries.remove(key);
+        Ok(())
+    }
+}
+
+/// Middleware implementing idempotent `POST` and `PATCH` requests, see [crate::idempotency].
+pub async fn idempotent<B>(
+    request: Request<B>,
+    next: Next<B>,
+    store: Arc<dyn IdempotencyStore>,
+    settings: Arc<IdempotencySettings>,
+) -> Response {
+    if request.method() != Method::POST && request.method() != Method::PATCH {
+        return next.run(request).await;
+    }
+    let key = match request.headers().get(IDEMPOTENCY_KEY) {
+        Some(key) => match key.to_str() {
+            Ok(key) if !key.is_empty() => scoped_key(&request, key),
+            _ => return Error::Validation("Invalid Idempotency-Key".to_string()).into_response(),
+        },
+        None => return next.run(request).await,
+    };
+    // Anonymous clients cannot be told apart, hence would get each other's responses.
+    let key = match key {
+        Some(key) => key,
+        None => return next.run(request).await,
+    };
+
+    match store.reserve(&key, settings.ttl).await {
+        Ok(Reservation::Reserved) => {}
+        Ok(Reservation::InFlight) => {
+            return Error::Conflict("A request with this Idempotency-Key is in flight".to_string())
+                .into_response()
+        }
+        Ok(Reservation::Completed(response)) => return replay(*response),
+        Err(e) => return Error::Internal(e).into_response(),
+    }
+    let reserved = ReservedKey {
+        store,
+        key: Some(key),
+    };
+
+    let response = next.run(request).await;
+    let size = response.body().size_hint().exact();
+    let too_large = size.map_or(true, |size| size > settings.max_response_size as u64);
+    if response.status().is_server_error() || too_large {
+        if let Err(e) = reserved.release().await {
+            return Error::Internal(e).into_response();
+        }
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            let _ = reserved.release().await;
+            return Error::Internal(anyhow::anyhow!(e).context("Cannot read response body"))
+                .into_response();
+        }
+    };
+    let stored = StoredResponse {
+        status: parts.status,
+        headers: parts.headers.clone(),
+        body: body.clone(),
+        problem: parts.extensions.get::<Problem>().cloned(),
+    };
+    if let Err(e) = reserved.complete(stored).await {
+        return Error::Internal(e).into_response();
+    }
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+/// A reserved key, released when dropped before being completed, e.g. if the request times out
+/// or the client disconnects, such that the request can be retried instead of being rejected as
+/// in flight until the TTL has passed.
+struct ReservedKey {
+    store: Arc<dyn IdempotencyStore>,
+    /// Taken once completed or released.
+    key: Option<String>,
+}
+
+impl ReservedKey {
+    async fn complete(mut self, response: StoredResponse) -> Result<()> {
+        if let Some(key) = &self.key {
+            self.store.complete(key, response).await?;
+        }
+        self.key = None;
+        Ok(())
+    }
+
+    async fn release(mut self) -> Result<()> {
+        if let Some(key) = &self.key {
+            self.store.release(key).await?;
+        }
+        self.key = None;
+        Ok(())
+    }
+}
+
+impl Drop for ReservedKey {
+    fn drop(&mut self) {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => return,
+        };
+        let store = self.store.clone();
+        if let Ok(runtime) = Handle::try_current() {
+            runtime.spawn(async move {
+                if let Err(e) = store.release(&key).await {
+                    log_error_chain("Cannot release idempotency key", e.as_ref());
+                }
+            });
+        }
+    }
+}
+
+/// The given key scoped by method, path, API key or session and, if any, tenant, such that keys
+/// of different clients cannot collide; `None` for anonymous clients.
+fn scoped_key<B>(request: &Request<B>, key: &str) -> Option<String> {
+    let extensions = request.extensions();
+    let subject = extensions
+        .get::<Claims>()
+        .and_then(|claims| claims.sub.as_ref().map(|sub| (claims.iss.as_deref(), sub)));
+    let client = match (extensions.get::<ApiKeyId>(), subject) {
+        (Some(id), _) => format!("api_key={}", id.0),
+        (None, Some((iss, sub))) => format!("sub={}|{sub}", iss.unwrap_or_default()),
+        // The session ID is hashed, because it is a credential.
+        (None, None) => {
+            let id = extensions.get::<Session>().and_then(Session::id)?;
+            format!("session={}", to_hex(&sha256(id.as_bytes())))
+        }
+    };
+    let tenant_id = extensions
+        .get::<TenantId>()
+        .map(TenantId::as_str)
+        .unwrap_or_default();
+    let key = format!(
+        "{tenant_id}:{client}:{}:{}:{key}",
+        request.method(),
+        request.uri().path()
+    );
+    Some(key)
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let mut response = Response::new(boxed(Full::from(stored.body)));
+    *response.status_mut() = stored.status;
+    *response.headers_mut() = stored.headers;
+    response
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    if let Some(problem) = stored.problem {
+        response.extensions_mut().insert(problem);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, StreamBody};
+    use axum::routing::post;
+    use axum::{middleware, Router};
+    use futures_util::stream;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::time::sleep;
+    use tower::ServiceExt;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    /// An app counting the handled requests, with the API key ID `key1` if `authenticated`.
+    fn app(
+        store: Arc<dyn IdempotencyStore>,
+        calls: Arc<AtomicUsize>,
+        authenticated: bool,
+    ) -> Router {
+        let settings = Arc::new(IdempotencySettings {
+            max_response_size: 10,
+            ..Default::default()
+        });
+        let handler = |status: StatusCode, body: &'static str| {
+            let calls = calls.clone();
+            move || async move {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                (status, format!("{body}{n}"))
+            }
+        };
+        let stream_calls = calls.clone();
+        Router::new()
+            .route("/ok", post(handler(StatusCode::CREATED, "created")))
+            .route(
+                "/fail",
+                post(handler(StatusCode::SERVICE_UNAVAILABLE, "failed")),
+            )
+            .route("/large", post(handler(StatusCode::OK, "0123456789")))
+            .route(
+                "/stream",
+                post(move || async move {
+                    stream_calls.fetch_add(1, Ordering::SeqCst);
+                    StreamBody::new(stream::iter([Ok::<_, Infallible>("streamed")]))
+                }),
+            )
+            .layer(middleware::from_fn(move |request, next| {
+                idempotent(request, next, store.clone(), settings.clone())
+            }))
+            .layer(middleware::from_fn(
+                move |mut request: Request<Body>, next: Next<Body>| {
+                    if authenticated {
+                        request
+                            .extensions_mut()
+                            .insert(ApiKeyId("key1".to_string()));
+                    }
+                    next.run(request)
+                },
+            ))
+    }
+
+    fn request(path: &str, key: &str) -> Request<Body> {
+        Request::post(path)
+            .header(IDEMPOTENCY_KEY, key)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    /// The status, replayed header and body of the response to the given request.
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, bool, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let replayed = response.headers().contains_key(IDEMPOTENT_REPLAYED);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, replayed, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_reserve() {
+        let store = InMemoryIdempotencyStore::default();
+        assert!(matches!(
+            store.reserve("a", TTL).await.unwrap(),
+            Reservation::Reserved
+        ));
+        assert!(matches!(
+            store.reserve("a", TTL).await.unwrap(),
+            Reservation::InFlight
+        ));
+        assert!(matches!(
+            store.reserve("b", TTL).await.unwrap(),
+            Reservation::Reserved
+        ));
+
+        let response = StoredResponse {
+            status: StatusCode::CREATED,
+            headers: HeaderMap::new(),
+            body: Bytes::from("created"),
+            problem: None,
+        };
+        store.complete("a", response).await.unwrap();
+        assert!(matches!(
+            store.reserve("a", TTL).await.unwrap(),
+            Reservation::Completed(response) if response.body == "created"
+        ));
+
+        store.release("b").await.unwrap();
+        assert!(matches!(
+            store.reserve("b", TTL).await.unwrap(),
+            Reservation::Reserved
+        ));
+
+        // Expired keys can be reserved again.
+        assert!(matches!(
+            store.reserve("c", Duration::ZERO).await.unwrap(),
+            Reservation::Reserved
+        ));
+        assert!(matches!(
+            store.reserve("c", TTL).await.unwrap(),
+            Reservation::Reserved
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let calls = Arc::new(AtomicUsize::default());
+        let app = app(
+            Arc::new(InMemoryIdempotencyStore::default()),
+            calls.clone(),
+            true,
+        );
+
+        let response = send(&app, request("/ok", "k1")).await;
+        assert_eq!(
+            response,
+            (StatusCode::CREATED, false, "created0".to_string())
+        );
+        let response = send(&app, request("/ok", "k1")).await;
+        assert_eq!(
+            response,
+            (StatusCode::CREATED, true, "created0".to_string())
+        );
+
+        // Other keys and paths are handled separately.
+        let response = send(&app, request("/ok", "k2")).await;
+        assert_eq!(
+            response,
+            (StatusCode::CREATED, false, "created1".to_string())
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_in_flight() {
+        let store = Arc::new(InMemoryIdempotencyStore::default());
+        let calls = Arc::new(AtomicUsize::default());
+        let app = app(store.clone(), calls.clone(), true);
+
+        let mut in_flight = request("/ok", "k1");
+        in_flight
+            .extensions_mut()
+            .insert(ApiKeyId("key1".to_string()));
+        let key = scoped_key(&in_flight, "k1").unwrap();
+        store.reserve(&key, TTL).await.unwrap();
+
+        let (status, _, _) = send(&app, request("/ok", "k1")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_not_stored() {
+        let calls = Arc::new(AtomicUsize::default());
+        let authenticated = app(
+            Arc::new(InMemoryIdempotencyStore::default()),
+            calls.clone(),
+            true,
+        );
+
+        // Server errors, large or streamed responses are released, hence handled again.
+        for path in ["/fail", "/large", "/stream"] {
+            let (_, replayed, _) = send(&authenticated, request(path, "k1")).await;
+            assert!(!replayed);
+            let (_, replayed, _) = send(&authenticated, request(path, "k1")).await;
+            assert!(!replayed, "{path}");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+
+        // So are requests of anonymous clients.
+        let anonymous = app(Arc::new(InMemoryIdempotencyStore::default()), calls, false);
+        send(&anonymous, request("/ok", "k1")).await;
+        let (_, replayed, _) = send(&anonymous, request("/ok", "k1")).await;
+        assert!(!replayed);
+    }
+
+    #[tokio::test]
+    async fn test_drop() {
+        let store = Arc::new(InMemoryIdempotencyStore::default());
+        store.reserve("a", TTL).await.unwrap();
+        drop(ReservedKey {
+            store: store.clone(),
+            key: Some("a".to_string()),
+        });
+
+        for _ in 0..100 {
+            if matches!(
+                store.reserve("a", TTL).await.unwrap(),
+                Reservation::Reserved
+            ) {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("dropped key not released");
+    }
+
+    #[test]
+    fn test_scoped_key() {
+        let request = |path: &str| Request::post(path).body(()).unwrap();
+
+        // Anonymous clients are not handled.
+        assert_eq!(scoped_key(&request("/ok"), "k1"), None);
+
+        let mut with_api_key = request("/ok");
+        with_api_key
+            .extensions_mut()
+            .insert(ApiKeyId("key1".to_string()));
+        assert_eq!(
+            scoped_key(&with_api_key, "k1").as_deref(),
+            Some(":api_key=key1:POST:/ok:k1")
+        );
+
+        let mut with_claims = request("/ok");
+        with_claims.extensions_mut().insert(Claims {
+            sub: Some("alice".to_string()),
+            iss: Some("issuer".to_string()),
+            aud: vec![],
+            exp: 0,
+            nbf: None,
+            iat: None,
+            other: Default::default(),
+        });
+        with_claims
+            .extensions_mut()
+            .insert(TenantId::parse("acme").unwrap());
+        assert_eq!(
+            scoped_key(&with_claims, "k1").as_deref(),
+            Some("acme:sub=issuer|alice:POST:/ok:k1")
+        );
+
+        // Sessions without an ID are not established, i.e. anonymous.
+        let mut with_session = request("/ok");
+        with_session.extensions_mut().insert(Session::default());
+        assert_eq!(scoped_key(&with_session, "k1"), None);
+    }
+}
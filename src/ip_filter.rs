@@ -0,0 +1,140 @@
+//! Firewall-like restriction of client addresses by route group, e.g. to lock the admin routes
+//! down to office or VPN ranges, configured as `ip_filters`: requests to the paths of a group from
+//! addresses within its `deny` list, or not within its `allow` list if not empty, are rejected
+//! with 403 and counted as `ip_filter_blocked_total` labeled by group. Paths match at segment
+//! boundaries, i.e. `/admin` does not cover `/administrator`.
+//!
+//! The address is the [ClientIp](crate::client_ip::ClientIp); if it is unknown, e.g. for Unix
+//! domain sockets, requests to groups with an `allow` list are rejected.
+
+use crate::client_ip::client_ip;
+use crate::error::Error;
+use crate::has_path_prefix;
+use crate::metrics::increment_counter;
+use crate::settings::IpFilterSettings;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Shared state of the [filter_ip] middleware.
+#[derive(Debug, Clone)]
+pub struct IpFilters {
+    groups: Arc<BTreeMap<String, IpFilterSettings>>,
+}
+
+impl IpFilters {
+    /// The configured filters, if any.
+    pub fn new(groups: &BTreeMap<String, IpFilterSettings>) -> Option<Self> {
+        (!groups.is_empty()).then(|| Self {
+            groups: Arc::new(groups.clone()),
+        })
+    }
+
+    /// The name of the first group blocking the given address for the given path, if any.
+    fn blocking_group(&self, path: &str, ip: Option<IpAddr>) -> Option<&str> {
+        self.groups
+            .iter()
+            .filter(|(_, group)| {
+                group
+                    .paths
+                    .iter()
+                    .any(|prefix| has_path_prefix(path, prefix))
+            })
+            .find(|(_, group)| match ip {
+                Some(ip) => {
+                    let denied = group.deny.iter().any(|cidr| cidr.contains(ip));
+                    let allowed =
+                        group.allow.is_empty() || group.allow.iter().any(|cidr| cidr.contains(ip));
+                    denied || !allowed
+                }
+                None => !group.allow.is_empty(),
+            })
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Middleware rejecting requests from blocked client addresses, see [crate::ip_filter].
+pub async fn filter_ip<B>(request: Request<B>, next: Next<B>, filters: IpFilters) -> Response {
+    let ip = client_ip(&request);
+    if let Some(group) = filters.blocking_group(request.uri().path(), ip) {
+        debug!(group, ip = ?ip, "Request blocked by IP filter");
+        increment_counter("ip_filter_blocked_total", &[("group", group.to_string())]);
+        return Error::Forbidden("Client address not allowed".to_string()).into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    fn filters() -> IpFilters {
+        let admin = IpFilterSettings {
+            paths: vec!["/admin".to_string()],
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.0.0.13".parse().unwrap()],
+        };
+        let api = IpFilterSettings {
+            paths: vec!["/api/".to_string()],
+            allow: vec![],
+            deny: vec!["192.0.2.0/24".parse().unwrap()],
+        };
+        let groups = [("admin".to_string(), admin), ("api".to_string(), api)];
+        IpFilters::new(&groups.into_iter().collect()).unwrap()
+    }
+
+    #[test]
+    fn test_blocking_group() {
+        let filters = filters();
+
+        assert_eq!(filters.blocking_group("/admin", ip("10.0.0.1")), None);
+        assert_eq!(filters.blocking_group("/admin/users", ip("10.0.0.1")), None);
+        assert_eq!(
+            filters.blocking_group("/admin", ip("10.0.0.13")),
+            Some("admin")
+        );
+        assert_eq!(
+            filters.blocking_group("/admin/users", ip("192.0.2.1")),
+            Some("admin")
+        );
+        assert_eq!(filters.blocking_group("/admin", None), Some("admin"));
+
+        // Other paths starting with the same characters are not within the group.
+        assert_eq!(
+            filters.blocking_group("/administrator", ip("192.0.2.1")),
+            None
+        );
+        assert_eq!(filters.blocking_group("/admins/x", None), None);
+
+        assert_eq!(
+            filters.blocking_group("/api/v2/users", ip("192.0.2.1")),
+            Some("api")
+        );
+        assert_eq!(
+            filters.blocking_group("/api/v2/users", ip("198.51.100.1")),
+            None
+        );
+        assert_eq!(filters.blocking_group("/api", ip("192.0.2.1")), None);
+        assert_eq!(filters.blocking_group("/", None), None);
+    }
+
+    #[test]
+    fn test_has_path_prefix() {
+        assert!(has_path_prefix("/admin", "/admin"));
+        assert!(has_path_prefix("/admin/", "/admin"));
+        assert!(has_path_prefix("/admin/users", "/admin"));
+        assert!(!has_path_prefix("/administrator", "/admin"));
+        assert!(!has_path_prefix("/adm", "/admin"));
+        assert!(has_path_prefix("/api/v2", "/api/"));
+        assert!(!has_path_prefix("/api", "/api/"));
+        assert!(has_path_prefix("/anything", "/"));
+    }
+}
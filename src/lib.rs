@@ -0,0 +1,493 @@
+pub mod access_log;
+pub mod admin;
+pub mod api_key;
+pub mod audit;
+pub mod auth;
+pub mod aws;
+pub mod aws_secrets;
+pub mod blob_store;
+pub mod body_capture;
+pub mod build_info;
+pub mod canary;
+pub mod cidr;
+pub mod cli;
+pub mod client_ip;
+pub mod compute;
+pub mod conditional;
+pub mod config_watcher;
+pub mod cors;
+pub mod csrf;
+pub mod csv;
+pub mod domain_events;
+pub mod error;
+pub mod events;
+pub mod features;
+pub mod health;
+pub mod hmac;
+pub mod http_client;
+pub mod idempotency;
+pub mod ip_filter;
+pub mod lifecycle;
+pub mod limit;
+pub mod listener;
+pub mod maintenance;
+pub mod metrics;
+pub mod mirror;
+pub mod module;
+pub mod multipart;
+pub mod ndjson;
+pub mod negotiate;
+pub mod panic;
+pub mod proxy;
+pub mod proxy_protocol;
+pub mod rate_limit;
+pub mod request_id;
+pub mod response_cache;
+pub mod routes;
+pub mod runtime;
+pub mod scheduler;
+pub mod secret;
+pub mod security_headers;
+pub mod sessions;
+pub mod settings;
+pub mod state;
+pub mod static_files;
+pub mod systemd;
+pub mod telemetry;
+pub mod tenancy;
+pub mod uds;
+pub mod upload;
+pub mod validation;
+pub mod vault;
+pub mod webhook_signature;
+pub mod webhooks;
+
+pub use settings::Settings;
+pub use state::AppState;
+
+use anyhow::{anyhow, bail, Context, Result};
+use api_key::{ApiKeyAuth, StaticApiKeyStore};
+use auth::JwtAuth;
+use axum::error_handling::HandleErrorLayer;
+use axum::routing::{get, post};
+use axum::{middleware, AddExtensionLayer, Router, Server};
+use body_capture::BodyCapture;
+use canary::Canary;
+use csrf::Csrf;
+use futures_util::future::try_join_all;
+use health::HealthRegistry;
+use hyper::server::Builder;
+use ip_filter::IpFilters;
+use mirror::Mirror;
+use module::Modules;
+use request_id::MakeRequestUuid;
+use security_headers::SecurityHeaders;
+use sessions::Sessions;
+use settings::Http2Settings;
+use std::error::Error as StdError;
+use std::future::{pending, Future};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::timeout::TimeoutLayer;
+use tower::ServiceBuilder;
+use tower_http::request_id::{PropagateRequestIdLayer, SetRequestIdLayer};
+use tracing::{debug, error, info};
+use webhook_signature::WebhookSignatures;
+
+/// Build the application [Router] for the given [AppState] and [Modules], without binding any
+/// sockets. Unless an admin port is configured, it includes the [admin_app] routes.
+pub fn app(state: &AppState, modules: &Modules) -> Router {
+    let settings = &state.settings;
+
+    let mut app = Router::new()
+        .route("/", get(|| async { "Habe die Ehre!" }))
+        .merge(routes::routes())
+        .merge(events::routes())
+        .merge(modules.routes());
+    if settings.uploads.dir.is_some() || settings.storage.s3.is_some() {
+        app = app.route(upload::PATH, post(upload::upload));
+    }
+    if settings.idempotency.enabled {
+        let store = state.idempotency_store.clone();
+        let idempotency = Arc::new(settings.idempotency.clone());
+        app = app.layer(middleware::from_fn(move |request, next| {
+            idempotency::idempotent(request, next, store.clone(), idempotency.clone())
+        }));
+    }
+    if state.response_cache.is_enabled() {
+        let cache = state.response_cache.clone();
+        app = app.layer(middleware::from_fn(move |request, next| {
+            response_cache::cache_response(request, next, cache.clone())
+        }));
+    }
+    // Within authentication, rate limiting and maintenance, because canary requests may be
+    // forwarded, but outside of the response cache, which caches per variant.
+    if let Some(canary) = Canary::new(
+        &settings.canary,
+        &settings.server.trusted_proxies,
+        state.http_client.clone(),
+    ) {
+        app = app.layer(middleware::from_fn(move |request, next| {
+            canary::route_canary(request, next, canary.clone())
+        }));
+    }
+
+    // Within authentication, because the claim strategy reads the claims.
+    if settings.tenancy.strategy.is_some() {
+        let tenancy = Arc::new(settings.tenancy.clone());
+        app = app.layer(middleware::from_fn(move |request, next| {
+            tenancy::resolve_tenant(request, next, tenancy.clone())
+        }));
+    }
+    if let Some(csrf) = Csrf::new(&settings.csrf, &settings.sessions) {
+        app = app.layer(middleware::from_fn(move |request, next| {
+            csrf::protect(request, next, csrf.clone())
+        }));
+    }
+    if !settings.api_keys.is_empty() {
+        let auth = ApiKeyAuth::new(StaticApiKeyStore::new(&settings.api_keys));
+        app = app.layer(middleware::from_fn(move |request, next| {
+            api_key::require_api_key(request, next, auth.clone())
+        }));
+    }
+    if let Some(auth) = JwtAuth::new(&settings.auth) {
+        app = app.layer(middleware::from_fn(move |request, next| {
+            auth::authenticate(request, next, auth.clone())
+        }));
+    }
+    if let Some(signatures) = WebhookSignatures::new(&settings.webhook_signatures) {
+        app = app.layer(middleware::from_fn(move |request, next| {
+            webhook_signature::verify_signature(request, next, signatures.clone())
+        }));
+    }
+
+    if let Some(sessions) = Sessions::new(&settings.sessions, state.session_store.clone()) {
+        app = app.layer(middleware::from_fn(move |request, next| {
+            sessions::manage_session(request, next, sessions.clone())
+        }));
+    }
+
+    if let Some(cors) = cors::layer(&settings.cors) {
+        app = app.layer(cors);
+    }
+
+    // Also without any limits, because they may be added by reloading the settings.
+    let limits = state.rate_limits.clone();
+    app = app.layer(middleware::from_fn(move |request, next| {
+        rate_limit::limit_rate(request, next, limits.clone())
+    }));
+
+    if let Some(mirror) = Mirror::new(&settings.mirror, &settings.http_client) {
+        app = app.layer(middleware::from_fn(move |request, next| {
+            mirror::mirror_request(request, next, mirror.clone())
+        }));
+    }
+
+    // Outermost of the route layers to also capture rejected requests.
+    if let Some(capture) = BodyCapture::new(
+        &settings.logging.body_capture,
+        settings.environment.as_deref(),
+    ) {
+        app = app.layer(middleware::from_fn(move |request, next| {
+            body_capture::capture_bodies(request, next, capture.clone())
+        }));
+    }
+
+    app = static_files::mount(app, &settings.static_files);
+    app = proxy::mount(
+        app,
+        &settings.proxy,
+        &settings.server.trusted_proxies,
+        settings.server.max_body_size,
+        &state.http_client,
+    );
+    if !settings.conditional_requests.paths.is_empty() {
+        let conditional_requests = Arc::new(settings.conditional_requests.clone());
+        app = app.layer(middleware::from_fn(move |request, next| {
+            conditional::conditional(request, next, conditional_requests.clone())
+        }));
+    }
+
+    // After mounting the static files and proxies, which are unavailable in maintenance as well.
+    let maintenance = state.maintenance.clone();
+    app = app.layer(middleware::from_fn(move |request, next| {
+        maintenance::reject_in_maintenance(request, next, maintenance.clone())
+    }));
+
+    if settings.admin.port.is_none() {
+        app = app.merge(operational_routes(state));
+    }
+    with_middleware(app, state)
+}
+
+/// Build the [Router] for the operational endpoints, i.e. health, version, metrics and admin
+/// routes, served on the admin port if configured. The admin routes are only included if the state
+/// has a [FilterHandle](telemetry::FilterHandle) and an admin password is configured.
+pub fn admin_app(state: &AppState) -> Router {
+    with_middleware(operational_routes(state), state)
+}
+
+fn operational_routes(state: &AppState) -> Router {
+    let settings = &state.settings;
+
+    let mut health = HealthRegistry::default();
+    health.register(state.http_client.health_check());
+    health.register(state.maintenance.health_check());
+    for (name, check) in &settings.health.http {
+        if let Ok(url) = check.url.parse() {
+            health.register(state.http_client.http_check(name, url, check.timeout));
+        }
+    }
+
+    let mut routes =
+        health::routes(health, &settings.health, state.startup.clone()).merge(build_info::routes());
+    if settings.telemetry.metrics_port.is_none() {
+        routes = routes.merge(metrics::routes());
+    }
+    if let (Some(filter_handle), Some(password)) =
+        (state.filter_handle.clone(), &settings.admin.password)
+    {
+        routes = routes.merge(admin::routes(state, filter_handle, password.expose()));
+    }
+    routes
+}
+
+fn with_middleware(app: Router, state: &AppState) -> Router {
+    let settings = &state.settings;
+    let mut app = app.layer(AddExtensionLayer::new(state.clone()));
+    if let Some(filters) = IpFilters::new(&settings.ip_filters) {
+        app = app.layer(middleware::from_fn(move |request, next| {
+            ip_filter::filter_ip(request, next, filters.clone())
+        }));
+    }
+
+    let trusted_proxies = Arc::new(settings.server.trusted_proxies.clone());
+    let access_log = Arc::new(settings.logging.access_log.clone());
+    let expose_internal_details = settings.is_dev();
+    let max_body_size = settings.server.max_body_size;
+    // Proxied bodies are streamed to the upstreams instead of being buffered.
+    let streamed_paths = Arc::new(settings.proxy.keys().cloned().collect::<Vec<_>>());
+    app = app.layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(middleware::from_fn(move |request, next| {
+                client_ip::resolve_client_ip(request, next, trusted_proxies.clone())
+            }))
+            .layer(middleware::from_fn(move |request, next| {
+                access_log::log_access(request, next, access_log.clone())
+            }))
+            .layer(middleware::from_fn(http_client::capture_context))
+            .layer(middleware::from_fn(metrics::track))
+            .layer(middleware::from_fn(move |request, next| {
+                error::complete_problem(request, next, expose_internal_details)
+            }))
+            .layer(HandleErrorLayer::new(limit::handle_error))
+            .option_layer(settings.server.max_concurrent_requests.map(|max| {
+                // In contrast to ConcurrencyLimitLayer, which is applied to every route
+                // separately, this shares its permits across all routes.
+                ServiceBuilder::new()
+                    .load_shed()
+                    .layer(GlobalConcurrencyLimitLayer::new(max))
+            }))
+            .layer(TimeoutLayer::new(settings.server.request_timeout))
+            .layer(middleware::from_fn(move |request, next| {
+                limit::limit_body_size(request, next, max_body_size, streamed_paths.clone())
+            }))
+            .layer(middleware::from_fn(panic::catch_panic)),
+    );
+
+    // Outermost to also cover responses of the above middleware, e.g. timeouts.
+    if let Some(security_headers) = SecurityHeaders::new(&settings.security_headers) {
+        app = app.layer(middleware::from_fn(move |request, next| {
+            security_headers::add_security_headers(request, next, security_headers.clone())
+        }));
+    }
+    app
+}
+
+/// The listeners [serve] will bind, e.g. `api [::1]:80` or `admin [::1]:9000`.
+pub fn listeners(settings: &Settings) -> Vec<String> {
+    let api = settings
+        .server
+        .tcp_addrs()
+        .into_iter()
+        .map(|addr| format!("api {addr}"));
+    let uds = settings
+        .server
+        .uds_path
+        .iter()
+        .map(|path| format!("api unix:{}", path.display()));
+    let admin = settings.admin_addr().map(|addr| format!("admin {addr}"));
+    let metrics = settings
+        .metrics_addr()
+        .map(|addr| format!("metrics {addr}"));
+    api.chain(uds).chain(admin).chain(metrics).collect()
+}
+
+/// Serve the [app] for the given [AppState] and [Modules] until SIGTERM or SIGINT is received and
+/// in-flight requests have been drained.
+pub async fn serve(state: AppState, modules: &Modules) -> Result<()> {
+    let settings = &state.settings;
+    if let Some(addr) = settings.admin_addr() {
+        let admin_server = Server::try_bind(&addr)
+            .with_context(|| format!("Cannot bind admin server to {addr}"))?
+            .serve(admin_app(&state).into_make_service());
+        tokio::spawn(async move {
+            if let Err(e) = admin_server.await {
+                log_error_chain("Admin server completed with error", &e);
+            }
+        });
+    }
+
+    if let Some(addr) = settings.metrics_addr() {
+        let metrics_server = Server::try_bind(&addr)
+            .with_context(|| format!("Cannot bind metrics server to {addr}"))?
+            .serve(metrics::routes().into_make_service());
+        tokio::spawn(async move {
+            if let Err(e) = metrics_server.await {
+                log_error_chain("Metrics server completed with error", &e);
+            }
+        });
+    }
+
+    let app = app(&state, modules);
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let mut servers = Vec::new();
+
+    for addr in settings.server.tcp_addrs() {
+        let incoming = listener::bind(addr, &settings.server.tcp)?;
+        let server = with_http2(
+            Server::builder(listener::accept(
+                incoming,
+                addr,
+                &settings.server.tcp,
+                &settings.server.trusted_proxies,
+            )),
+            &settings.server.http2,
+        )
+        .serve(
+            app.clone()
+                .into_make_service_with_connect_info::<SocketAddr, &listener::Connection>(),
+        )
+        .with_graceful_shutdown(shutdown(shutdown_rx.clone()));
+        info!(%addr, "Listening");
+        servers.push(tokio::spawn(async move {
+            server.await.context("Server completed with error")
+        }));
+    }
+
+    if let Some(path) = settings.server.uds_path.clone() {
+        let listener = uds::bind(&path, settings.server.uds_mode)?;
+        let server = with_http2(
+            Server::builder(uds::accept(listener)),
+            &settings.server.http2,
+        )
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown(shutdown_rx.clone()));
+        servers.push(tokio::spawn(async move {
+            let result = server.await.context("Server completed with error");
+            uds::remove(&path);
+            result
+        }));
+    }
+
+    if servers.is_empty() {
+        bail!("Neither a TCP address nor a Unix domain socket configured");
+    }
+    systemd::ready();
+    systemd::spawn_watchdog();
+
+    let shutdown_signal = shutdown_signal()?;
+    tokio::spawn(async move {
+        shutdown_signal.await;
+        info!("Shutdown signal received, draining connections");
+        systemd::stopping();
+        let _ = shutdown_tx.send(());
+    });
+
+    let drain_timeout = settings.server.shutdown_timeout;
+    let drain_deadline = async move {
+        shutdown(shutdown_rx).await;
+        sleep(drain_timeout).await
+    };
+
+    let servers = try_join_all(
+        servers
+            .into_iter()
+            .map(|server| async move { server.await.context("Server panicked")? }),
+    );
+    tokio::select! {
+        result = servers => {
+            result?;
+            info!("Draining connections completed");
+            Ok(())
+        }
+        _ = drain_deadline => Err(anyhow!(
+            "Draining connections not completed within {drain_timeout:?}"
+        )),
+    }
+}
+
+fn with_http2<I>(builder: Builder<I>, settings: &Http2Settings) -> Builder<I> {
+    if !settings.enabled {
+        return builder.http1_only(true);
+    }
+    builder
+        .http2_only(settings.only)
+        .http2_max_concurrent_streams(settings.max_concurrent_streams)
+        .http2_initial_stream_window_size(settings.initial_stream_window_size)
+        .http2_initial_connection_window_size(settings.initial_connection_window_size)
+        .http2_adaptive_window(settings.adaptive_window)
+}
+
+/// Completes once a shutdown has been signaled via the respective sender.
+async fn shutdown(mut shutdown_rx: watch::Receiver<()>) {
+    if shutdown_rx.changed().await.is_err() {
+        pending().await
+    }
+}
+
+/// Completes when either SIGTERM or SIGINT has been received. The signal handlers are installed
+/// eagerly, i.e. before the returned future is polled.
+fn shutdown_signal() -> Result<impl Future<Output = ()>> {
+    let mut sigterm = signal(SignalKind::terminate()).context("Cannot install SIGTERM handler")?;
+    let mut sigint = signal(SignalKind::interrupt()).context("Cannot install SIGINT handler")?;
+    Ok(async move {
+        tokio::select! {
+            _ = sigterm.recv() => debug!("SIGTERM received"),
+            _ = sigint.recv() => debug!("SIGINT received"),
+        }
+    })
+}
+
+/// Whether the given path is below the given prefix at a segment boundary, e.g. `/admin` and
+/// `/admin/users`, but not `/administrator`, for the prefix `/admin`. Prefixes ending with `/`,
+/// e.g. `/`, match any path starting with them.
+pub fn has_path_prefix(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix).map_or(false, |rest| {
+        prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/')
+    })
+}
+
+/// Log the given message and error at ERROR level, including the whole chain of sources as a
+/// JSON array in the `sources` field, e.g. `["Connection refused (os error 111)"]`.
+pub fn log_error_chain(message: &str, error: &(dyn StdError + 'static)) {
+    let mut sources = Vec::new();
+    let mut source = error.source();
+    while let Some(e) = source {
+        sources.push(e.to_string());
+        source = e.source();
+    }
+
+    if sources.is_empty() {
+        error!(message, error = display(error));
+    } else {
+        let sources = serde_json::to_string(&sources).unwrap_or_default();
+        error!(message, error = display(error), sources = display(sources));
+    }
+}
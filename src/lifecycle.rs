@@ -0,0 +1,94 @@
+//! Hooks run while the server is starting, e.g. warming caches, and after it has been shut down,
+//! e.g. draining queues. Until the startup hooks have completed, `/startupz` responds with 503.
+
+use crate::log_error_chain;
+use anyhow::{anyhow, Context, Result};
+use futures_util::future::BoxFuture;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::info;
+
+type Hook = Box<dyn FnOnce() -> BoxFuture<'static, Result<()>> + Send>;
+
+struct NamedHook {
+    name: String,
+    timeout: Duration,
+    hook: Hook,
+}
+
+impl NamedHook {
+    fn new<F, Fut>(name: impl Into<String>, timeout: Duration, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            timeout,
+            hook: Box::new(move || Box::pin(hook())),
+        }
+    }
+
+    async fn run(self) -> Result<()> {
+        let Self {
+            name,
+            timeout: duration,
+            hook,
+        } = self;
+        timeout(duration, hook())
+            .await
+            .map_err(|_| anyhow!("Hook {name} not completed within {duration:?}"))?
+            .with_context(|| format!("Hook {name} failed"))?;
+        info!(hook = name.as_str(), "Hook completed");
+        Ok(())
+    }
+}
+
+/// Startup and shutdown hooks, each with its own timeout.
+#[derive(Default)]
+pub struct Lifecycle {
+    on_start: Vec<NamedHook>,
+    on_shutdown: Vec<NamedHook>,
+}
+
+impl Lifecycle {
+    /// Register a hook run by [Lifecycle::start].
+    pub fn on_start<F, Fut>(&mut self, name: impl Into<String>, timeout: Duration, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.on_start.push(NamedHook::new(name, timeout, hook));
+    }
+
+    /// Register a hook run by [Lifecycle::shutdown].
+    pub fn on_shutdown<F, Fut>(&mut self, name: impl Into<String>, timeout: Duration, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.on_shutdown.push(NamedHook::new(name, timeout, hook));
+    }
+
+    /// Run the startup hooks in the order of registration; the first failing or timed out one
+    /// aborts the startup.
+    pub async fn start(&mut self) -> Result<()> {
+        for hook in self.on_start.drain(..) {
+            hook.run().await?;
+        }
+        Ok(())
+    }
+
+    /// Run the shutdown hooks in the reverse order of registration, such that components are shut
+    /// down before the ones they depend on. Failing or timed out hooks are logged, but do not
+    /// prevent the remaining ones from running.
+    pub async fn shutdown(self) {
+        for hook in self.on_shutdown.into_iter().rev() {
+            if let Err(e) = hook.run().await {
+                log_error_chain("Shutdown hook failed", e.as_ref());
+            }
+        }
+        info!("Shutdown hooks completed");
+    }
+}
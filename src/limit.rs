@@ -0,0 +1,128 @@
+use crate::error::Problem;
+use crate::has_path_prefix;
+use crate::metrics::increment_counter;
+use crate::upload;
+use anyhow::anyhow;
+use axum::body::{Body, HttpBody};
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use futures_util::StreamExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tower::load_shed::error::Overloaded;
+use tower::timeout::error::Elapsed;
+
+/// Middleware rejecting requests with bodies larger than `max_body_size` bytes with 413. Bodies
+/// without a content length are buffered up to the limit, except for those below the given
+/// `streamed_paths`, which must be limited via [limited], e.g. by [crate::proxy]. Uploads are
+/// exempt, because they are streamed and limited by [crate::upload::upload] itself.
+pub async fn limit_body_size(
+    request: Request<Body>,
+    next: Next<Body>,
+    max_body_size: usize,
+    streamed_paths: Arc<Vec<String>>,
+) -> Response {
+    let path = request.uri().path();
+    if path == upload::PATH {
+        return next.run(request).await;
+    }
+    let streamed = streamed_paths
+        .iter()
+        .any(|prefix| has_path_prefix(path, prefix));
+
+    let content_length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+
+    match content_length {
+        Some(content_length) if content_length > max_body_size => payload_too_large(max_body_size),
+        Some(_) => next.run(request).await,
+        None if streamed => next.run(request).await,
+        None => {
+            let (parts, mut body) = request.into_parts();
+            let mut bytes = Vec::new();
+            while let Some(data) = body.data().await {
+                let data = match data {
+                    Ok(data) => data,
+                    Err(e) => {
+                        return Problem::new(StatusCode::BAD_REQUEST)
+                            .with_detail(format!("Cannot read request body: {e}"))
+                            .into_response()
+                    }
+                };
+                if bytes.len() + data.len() > max_body_size {
+                    return payload_too_large(max_body_size);
+                }
+                bytes.extend_from_slice(&data);
+            }
+            next.run(Request::from_parts(parts, Body::from(bytes)))
+                .await
+        }
+    }
+}
+
+/// Error handler for [tower::timeout::Timeout] and [tower::load_shed::LoadShed], responding with
+/// 408 for timeouts and with 503 for shed requests, which are counted as `requests_shed_total`.
+pub async fn handle_error(error: BoxError) -> Problem {
+    if error.is::<Elapsed>() {
+        Problem::new(StatusCode::REQUEST_TIMEOUT).with_detail("Request took too long")
+    } else if error.is::<Overloaded>() {
+        increment_counter("requests_shed_total", &[]);
+        Problem::new(StatusCode::SERVICE_UNAVAILABLE).with_detail("Server is overloaded")
+    } else {
+        Problem::new(StatusCode::INTERNAL_SERVER_ERROR).with_internal_detail(error.to_string())
+    }
+}
+
+/// The given body, streamed and failing once it exceeds `max_body_size` bytes, which is then
+/// recorded in `exceeded`, such that the request can be answered with [payload_too_large].
+pub(crate) fn limited(body: Body, max_body_size: usize, exceeded: Arc<AtomicBool>) -> Body {
+    let mut size = 0;
+    Body::wrap_stream(body.map(move |data| {
+        let data = data?;
+        size += data.len();
+        if size > max_body_size {
+            exceeded.store(true, Ordering::Release);
+            return Err(BoxError::from(anyhow!(
+                "Request body exceeds {max_body_size} bytes"
+            )));
+        }
+        Ok(data)
+    }))
+}
+
+pub(crate) fn payload_too_large(max_body_size: usize) -> Response {
+    Problem::new(StatusCode::PAYLOAD_TOO_LARGE)
+        .with_detail(format!(
+            "Request body must not exceed {max_body_size} bytes"
+        ))
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    fn chunked(chunks: &'static [&'static str]) -> Body {
+        Body::wrap_stream(stream::iter(chunks.iter().copied().map(Ok::<_, BoxError>)))
+    }
+
+    #[tokio::test]
+    async fn test_limited() {
+        let exceeded = Arc::new(AtomicBool::default());
+        let body = limited(chunked(&["0123", "4567", "89"]), 10, exceeded.clone());
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(&body[..], b"0123456789");
+        assert!(!exceeded.load(Ordering::Acquire));
+
+        let body = limited(chunked(&["0123", "4567", "89x"]), 10, exceeded.clone());
+        assert!(hyper::body::to_bytes(body).await.is_err());
+        assert!(exceeded.load(Ordering::Acquire));
+    }
+}
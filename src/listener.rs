@@ -0,0 +1,220 @@
+//! TCP listeners for the server addresses, built via socket2 to apply the [TcpSettings], optionally
+//! decoding the PROXY protocol, see [crate::proxy_protocol].
+
+use crate::cidr::{canonical, Cidr};
+use crate::metrics::increment_counter;
+use crate::proxy_protocol;
+use crate::settings::TcpSettings;
+use anyhow::{anyhow, Context as _, Result};
+use axum::extract::connect_info::Connected;
+use futures_util::future::poll_fn;
+use hyper::server::accept::{self, Accept};
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::{debug, warn};
+
+/// Bind a listener to the given address. IPv6 listeners only accept IPv6 connections, such that
+/// e.g. `0.0.0.0` and `::` can be bound at the same time.
+pub fn bind(addr: SocketAddr, settings: &TcpSettings) -> Result<AddrIncoming> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)
+        .context("Cannot create socket")?;
+    if addr.is_ipv6() {
+        socket
+            .set_only_v6(true)
+            .context("Cannot restrict socket to IPv6")?;
+    }
+    socket
+        .set_reuse_address(settings.reuse_address)
+        .context("Cannot set SO_REUSEADDR")?;
+    socket
+        .set_reuse_port(settings.reuse_port)
+        .context("Cannot set SO_REUSEPORT")?;
+    socket
+        .set_nonblocking(true)
+        .context("Cannot set O_NONBLOCK")?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("Cannot bind to {addr}"))?;
+    socket
+        .listen(settings.backlog)
+        .with_context(|| format!("Cannot listen on {addr}"))?;
+
+    let listener = TcpListener::from_std(socket.into()).context("Cannot create listener")?;
+    let mut incoming = AddrIncoming::from_listener(listener).context("Cannot create listener")?;
+    incoming
+        .set_nodelay(settings.nodelay)
+        .set_keepalive(settings.keepalive);
+    Ok(incoming)
+}
+
+/// An accepted connection; its remote address is the source address from the PROXY protocol
+/// header, if enabled, and the peer address otherwise.
+#[derive(Debug)]
+pub struct Connection {
+    stream: AddrStream,
+    remote_addr: SocketAddr,
+}
+
+impl Connection {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+}
+
+impl Connected<&Connection> for SocketAddr {
+    fn connect_info(connection: &Connection) -> Self {
+        connection.remote_addr
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Accept connections from the given listener, counting them as `http_connections_total` labeled
+/// by listener address.
+///
+/// With the PROXY protocol enabled, connections from peers other than the trusted proxies are
+/// closed and the headers are read in tasks of their own, such that slow peers do not hold up
+/// accepting others.
+pub fn accept(
+    incoming: AddrIncoming,
+    addr: SocketAddr,
+    settings: &TcpSettings,
+    trusted_proxies: &[Cidr],
+) -> impl Accept<Conn = Connection, Error = io::Error> {
+    let label = addr.to_string();
+    let (mut proxied, mut incoming) = if settings.proxy_protocol.enabled {
+        let proxied = accept_proxied(
+            incoming,
+            addr,
+            settings.proxy_protocol.timeout,
+            trusted_proxies.to_vec(),
+        );
+        (Some(proxied), None)
+    } else {
+        (None, Some(incoming))
+    };
+
+    accept::poll_fn(move |cx| {
+        let connection = match (&mut proxied, &mut incoming) {
+            (Some(proxied), _) => proxied.poll_recv(cx),
+            (None, Some(incoming)) => Pin::new(incoming).poll_accept(cx).map(|connection| {
+                connection.map(|connection| {
+                    connection.map(|stream| Connection {
+                        remote_addr: stream.remote_addr(),
+                        stream,
+                    })
+                })
+            }),
+            (None, None) => Poll::Ready(None),
+        };
+        if let Poll::Ready(Some(Ok(_))) = &connection {
+            increment_counter("http_connections_total", &[("listener", label.clone())]);
+        }
+        connection
+    })
+}
+
+fn accept_proxied(
+    mut incoming: AddrIncoming,
+    addr: SocketAddr,
+    timeout: Duration,
+    trusted_proxies: Vec<Cidr>,
+) -> mpsc::Receiver<io::Result<Connection>> {
+    let (connections_tx, connections_rx) = mpsc::channel(1);
+    let trusted_proxies = Arc::new(trusted_proxies);
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                stream = poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx)) => stream,
+                // The server has stopped accepting.
+                _ = connections_tx.closed() => return,
+            };
+            let mut stream = match stream {
+                Some(Ok(stream)) => stream,
+                Some(Err(e)) => {
+                    if connections_tx.send(Err(e)).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                None => return,
+            };
+
+            let peer = stream.remote_addr();
+            if !trusted_proxies
+                .iter()
+                .any(|cidr| cidr.contains(canonical(peer.ip())))
+            {
+                warn!(%addr, %peer, "Connection from untrusted peer closed");
+                continue;
+            }
+
+            let connections_tx = connections_tx.clone();
+            tokio::spawn(async move {
+                let remote_addr = match read_remote_addr(&mut stream, peer, timeout).await {
+                    Ok(remote_addr) => remote_addr,
+                    Err(e) => {
+                        let error = format!("{e:#}");
+                        debug!(%addr, %peer, error = error.as_str(), "Connection closed");
+                        return;
+                    }
+                };
+                let connection = Connection {
+                    stream,
+                    remote_addr,
+                };
+                // Fails only if the server has stopped accepting.
+                let _ = connections_tx.send(Ok(connection)).await;
+            });
+        }
+    });
+    connections_rx
+}
+
+/// The source address from the PROXY protocol header or else the given peer address.
+async fn read_remote_addr(
+    stream: &mut AddrStream,
+    peer: SocketAddr,
+    timeout: Duration,
+) -> Result<SocketAddr> {
+    let source = time::timeout(timeout, proxy_protocol::read_header(stream))
+        .await
+        .map_err(|_| anyhow!("No PROXY protocol header within {timeout:?}"))??;
+    Ok(source.unwrap_or(peer))
+}
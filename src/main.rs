@@ -1,95 +1,132 @@
-use anyhow::{Context, Error, Result};
-use axum::Server;
-use axum::{routing::get, Router};
-use config::{Config, Environment, File};
-use serde::Deserialize;
-use std::env;
-use std::error::Error as StdError;
-use std::net::{IpAddr, SocketAddr};
-use tower::ServiceBuilder;
-use tower_http::trace::TraceLayer;
-use tracing::{debug, error};
+use anyhow::Result;
+use bayer_axum::build_info::BUILD_INFO;
+use bayer_axum::cli::{Cli, Command};
+use bayer_axum::features;
+use bayer_axum::lifecycle::Lifecycle;
+use bayer_axum::module::Modules;
+use bayer_axum::rate_limit;
+use bayer_axum::runtime;
+use bayer_axum::scheduler::Scheduler;
+use bayer_axum::settings::{LoggingSettings, RuntimeSettings};
+use bayer_axum::telemetry::{self, FilterHandle};
+use bayer_axum::{config_watcher, listeners, log_error_chain, serve, AppState, Settings};
+use std::future::pending;
+use std::process;
+use tokio::select;
+use tracing::info;
 
-const ENVIRONMENT: &str = "ENVIRONMENT";
+fn main() {
+    let cli = Cli::parse();
+    let settings = runtime::bootstrap()
+        .and_then(|runtime| runtime.block_on(Settings::load(cli.config_dir(), &cli.overrides())));
 
-#[derive(Debug, Deserialize)]
-pub struct Settings {
-    pub addr: IpAddr,
-    pub port: u16,
-}
+    if cli.command == Some(Command::CheckConfig) {
+        check_config(settings);
+    }
 
-impl Settings {
-    /// First the file `config/default` is read, then the file `config/<ENVIRONMENT>`,
-    /// e.g. `config/dev`, if the environment variable `ENVIRONMENT` is defined,
-    /// and finally environment variables prefixed with `APP__` and separated by `__`
-    /// (double underscores are used as separators because of snake_cased keys).
-    fn new() -> Result<Self> {
-        env::var(ENVIRONMENT)
-            .iter()
-            .fold(
-                Config::builder().add_source(File::with_name("config/default")),
-                |config, env| config.add_source(File::with_name(&format!("config/{env}"))),
-            )
-            .add_source(Environment::with_prefix("app").separator("__"))
-            .build()?
-            .try_deserialize()
-            .context("Error creating configuration settings")
+    let default_runtime = RuntimeSettings::default();
+    let runtime_settings = settings.as_ref().map_or(&default_runtime, |s| &s.runtime);
+    match runtime::build(runtime_settings) {
+        Ok(runtime) => runtime.block_on(start(cli, settings)),
+        Err(e) => {
+            eprintln!("bayer-axum exited with ERROR: {e:#}");
+            process::exit(1)
+        }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .json()
-        .init();
+async fn start(cli: Cli, settings: Result<Settings>) {
+    let filter_handle = match &settings {
+        Ok(settings) => telemetry::init(&settings.logging),
+        Err(_) => telemetry::init(&LoggingSettings::default()),
+    };
 
-    if let Err(e) = run().await {
-        log_error("bayer-axum exited with ERROR", e);
+    let result = match settings {
+        Ok(settings) => run(cli, settings, filter_handle).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        log_error_chain("bayer-axum exited with ERROR", e.as_ref());
     };
 }
 
-async fn run() -> Result<()> {
-    let settings = Settings::new()?;
-    debug!("Starting with these settings: {settings:?}");
+async fn run(cli: Cli, settings: Settings, filter_handle: FilterHandle) -> Result<()> {
+    let settings_rx = config_watcher::spawn(
+        cli.config_dir().to_owned(),
+        cli.overrides(),
+        settings.clone(),
+    )?;
+    tokio::spawn(telemetry::apply_filter_changes(
+        settings_rx.clone(),
+        filter_handle.clone(),
+    ));
+
+    let startup_timeout = settings.server.startup_timeout;
+    let shutdown_timeout = settings.server.shutdown_timeout;
+    let mut lifecycle = Lifecycle::default();
 
-    let app = Router::new()
-        .route("/", get(|| async { "Habe die Ehre!" }))
-        .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()));
+    let scheduler = Scheduler::default().start(&settings.scheduler)?;
+    lifecycle.on_shutdown("scheduler", shutdown_timeout, move || async move {
+        scheduler.shutdown().await;
+        Ok(())
+    });
 
-    let addr = SocketAddr::new(settings.addr, settings.port);
-    tokio::spawn(async move { Server::bind(&addr).serve(app.into_make_service()).await })
-        .await
-        .map(|server_result| server_result.context("Server completed with error"))
-        .context("Server panicked")
-        .and_then(|r| r)
+    let state = AppState::builder(settings)
+        .filter_handle(filter_handle)
+        .build()?;
+    tokio::spawn(rate_limit::apply_limit_changes(
+        settings_rx.clone(),
+        state.rate_limits.clone(),
+    ));
+    tokio::spawn(features::apply_flag_changes(
+        settings_rx,
+        state.features.clone(),
+    ));
+    let modules = Modules::all();
+    log_startup(&state.settings, &modules);
+    modules.register_hooks(&mut lifecycle, &state, startup_timeout, shutdown_timeout);
+
+    // The startup hooks run while already serving, such that `/startupz` can report on them.
+    let startup = async {
+        lifecycle.start().await?;
+        state.startup.complete();
+        info!("Startup completed");
+        pending().await
+    };
+    let result = select! {
+        result = serve(state.clone(), &modules) => result,
+        result = startup => result,
+    };
+    lifecycle.shutdown().await;
+    result
 }
 
-fn log_error(message: &str, e: Error) {
-    let mut error_chain = Vec::new();
-    build_error_chain(&mut error_chain, e.source());
-    match error_chain[..] {
-        [] => error!(message = message, error = display(&e)),
-        [s] => error!(message = message, error = display(&e), source = display(&s)),
-        [s1, s2] => error!(
-            message = message,
-            error = display(&e),
-            source = display(&s1),
-            source2 = display(&s2),
-        ),
-        [s1, s2, s3, ..] => error!(
-            message = message,
-            error = display(&e),
-            source = display(&s1),
-            source2 = display(&s2),
-            source3 = display(&s3),
-        ),
-    }
+/// One event with the build info, the effective configuration with secrets redacted, the
+/// enabled modules and the listeners.
+fn log_startup(settings: &Settings, modules: &Modules) {
+    let config = serde_json::to_string(settings).unwrap_or_else(|e| e.to_string());
+    info!(
+        version = BUILD_INFO.version,
+        git_sha = BUILD_INFO.git_sha,
+        git_tag = BUILD_INFO.git_tag,
+        build_timestamp = BUILD_INFO.build_timestamp,
+        rustc_version = BUILD_INFO.rustc_version,
+        config = %config,
+        modules = ?modules.names(),
+        listeners = ?listeners(settings),
+        "Starting bayer-axum"
+    );
 }
 
-fn build_error_chain<'a>(chain: &mut Vec<&'a (dyn StdError)>, e: Option<&'a (dyn StdError)>) {
-    if let Some(e) = e {
-        chain.push(e);
-        build_error_chain(chain, e.source());
+fn check_config(settings: Result<Settings>) -> ! {
+    match settings.and_then(|settings| settings.to_redacted_json()) {
+        Ok(settings) => {
+            println!("{settings}");
+            process::exit(0)
+        }
+        Err(e) => {
+            eprintln!("Invalid configuration: {e:#}");
+            process::exit(1)
+        }
     }
 }
@@ -0,0 +1,69 @@
+//! Maintenance mode for planned downtime: while enabled, the API routes respond with 503 and a
+//! `Retry-After` header and readiness fails. It is enabled via settings or at runtime via
+//! `/admin/maintenance`.
+
+use crate::error::Error;
+use crate::health::HealthCheck;
+use crate::settings::MaintenanceSettings;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::{Arc, Mutex};
+
+/// The current maintenance settings, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Maintenance(Arc<Mutex<MaintenanceSettings>>);
+
+impl Maintenance {
+    pub fn new(settings: &MaintenanceSettings) -> Self {
+        Self(Arc::new(Mutex::new(settings.clone())))
+    }
+
+    pub fn get(&self) -> MaintenanceSettings {
+        self.0.lock().expect("maintenance can be locked").clone()
+    }
+
+    pub fn set(&self, settings: MaintenanceSettings) {
+        *self.0.lock().expect("maintenance can be locked") = settings;
+    }
+
+    /// [HealthCheck] failing while in maintenance.
+    pub fn health_check(&self) -> MaintenanceCheck {
+        MaintenanceCheck(self.clone())
+    }
+}
+
+/// See [Maintenance::health_check].
+pub struct MaintenanceCheck(Maintenance);
+
+#[async_trait]
+impl HealthCheck for MaintenanceCheck {
+    fn name(&self) -> &str {
+        "maintenance"
+    }
+
+    async fn check(&self) -> Result<()> {
+        let settings = self.0.get();
+        if settings.enabled {
+            bail!("In maintenance: {}", settings.message);
+        }
+        Ok(())
+    }
+}
+
+/// Middleware rejecting all requests with 503 and the configured message and `Retry-After` while
+/// in maintenance.
+pub async fn reject_in_maintenance<B>(
+    request: Request<B>,
+    next: Next<B>,
+    maintenance: Maintenance,
+) -> Response {
+    let settings = maintenance.get();
+    if settings.enabled {
+        Error::Unavailable(settings.message, settings.retry_after).into_response()
+    } else {
+        next.run(request).await
+    }
+}
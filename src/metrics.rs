@@ -0,0 +1,176 @@
+//! A minimal metrics registry in the spirit of the `metrics` crate facade, rendered in the
+//! Prometheus text exposition format.
+
+use axum::extract::MatchedPath;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{Headers, IntoResponse};
+use axum::routing::get;
+use axum::Router;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Upper bounds of histogram buckets in seconds, the Prometheus client default.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(Default::default);
+
+type Key = (&'static str, Vec<(&'static str, String)>);
+
+#[derive(Debug, Default)]
+struct Registry {
+    counters: BTreeMap<Key, u64>,
+    gauges: BTreeMap<Key, f64>,
+    histograms: BTreeMap<Key, Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Increment the counter with the given name and labels by one.
+pub fn increment_counter(name: &'static str, labels: &[(&'static str, String)]) {
+    *registry().counters.entry(key(name, labels)).or_default() += 1;
+}
+
+/// Set the gauge with the given name and labels to the given value.
+pub fn set_gauge(name: &'static str, labels: &[(&'static str, String)], value: f64) {
+    registry().gauges.insert(key(name, labels), value);
+}
+
+/// Record the given value, e.g. a latency in seconds, in the histogram with the given name and
+/// labels.
+pub fn record_histogram(name: &'static str, labels: &[(&'static str, String)], value: f64) {
+    let mut registry = registry();
+    let histogram = registry.histograms.entry(key(name, labels)).or_default();
+    for (bucket, upper_bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+        if value <= upper_bound {
+            *bucket += 1;
+        }
+    }
+    histogram.count += 1;
+    histogram.sum += value;
+}
+
+/// Render all metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let registry = registry();
+    let mut out = String::new();
+    let mut last_name = "";
+
+    for ((name, labels), value) in &registry.counters {
+        write_type(&mut out, &mut last_name, name, "counter");
+        let _ = writeln!(out, "{name}{} {value}", format_labels(labels, None));
+    }
+
+    for ((name, labels), value) in &registry.gauges {
+        write_type(&mut out, &mut last_name, name, "gauge");
+        let _ = writeln!(out, "{name}{} {value}", format_labels(labels, None));
+    }
+
+    for ((name, labels), histogram) in &registry.histograms {
+        write_type(&mut out, &mut last_name, name, "histogram");
+        for (count, upper_bound) in histogram.buckets.iter().zip(BUCKETS) {
+            let le = upper_bound.to_string();
+            let _ = writeln!(
+                out,
+                "{name}_bucket{} {count}",
+                format_labels(labels, Some(&le))
+            );
+        }
+        let all = format_labels(labels, Some("+Inf"));
+        let labels = format_labels(labels, None);
+        let _ = writeln!(out, "{name}_bucket{all} {}", histogram.count);
+        let _ = writeln!(out, "{name}_sum{labels} {}", histogram.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", histogram.count);
+    }
+
+    out
+}
+
+/// Route for `/metrics`.
+pub fn routes() -> Router {
+    Router::new().route("/metrics", get(metrics))
+}
+
+/// Middleware recording `http_requests_total` and `http_request_duration_seconds` labeled by
+/// method, matched route and status code.
+pub async fn track<B>(request: Request<B>, next: Next<B>) -> impl IntoResponse {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_owned());
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    increment_counter("http_requests_total", &labels);
+    record_histogram(
+        "http_request_duration_seconds",
+        &labels,
+        start.elapsed().as_secs_f64(),
+    );
+
+    response
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        Headers([(CONTENT_TYPE, "text/plain; version=0.0.4")]),
+        render(),
+    )
+}
+
+fn registry() -> MutexGuard<'static, Registry> {
+    // A panic while holding the lock cannot leave the registry in an inconsistent state.
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn key(name: &'static str, labels: &[(&'static str, String)]) -> Key {
+    (name, labels.to_vec())
+}
+
+fn write_type(out: &mut String, last_name: &mut &'static str, name: &'static str, kind: &str) {
+    if *last_name != name {
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        *last_name = name;
+    }
+}
+
+fn format_labels(labels: &[(&'static str, String)], le: Option<&str>) -> String {
+    let mut labels = labels
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect::<Vec<_>>();
+    if let Some(le) = le {
+        labels.push(format!("le=\"{le}\""));
+    }
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
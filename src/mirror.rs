@@ -0,0 +1,193 @@
+//! Mirroring of requests to a shadow upstream, e.g. to validate a rewrite against production
+//! traffic: the configured fraction of requests to the configured paths is sent to
+//! `mirror.upstream` in the background as well, marked with the `x-mirrored` header. Responses
+//! are discarded and failures do not affect the actual request.
+//!
+//! Only requests with a body size up to `max_body_size` are mirrored, because bodies have to be
+//! buffered to be sent twice; requests with unknown body size only if their method is safe, e.g.
+//! `GET`. At most `max_concurrent` mirrored requests are in flight, further ones are dropped.
+//! Mirrored requests are counted as `mirrored_requests_total` labeled by outcome.
+//!
+//! Credentials, i.e. the `Authorization`, `Proxy-Authorization`, `Cookie` and `x-api-key`
+//! headers, are removed unless `forward_credentials` is enabled. The mirror uses its own
+//! [HttpClient], such that failures of the shadow upstream do not open circuits which are part of
+//! the readiness of this service.
+
+use crate::api_key::X_API_KEY;
+use crate::error::Error;
+use crate::has_path_prefix;
+use crate::http_client::HttpClient;
+use crate::metrics::increment_counter;
+use crate::proxy::remove_hop_by_hop;
+use crate::settings::{HttpClientSettings, MirrorSettings};
+use axum::body::Body;
+use axum::http::header::{AUTHORIZATION, CONTENT_LENGTH, COOKIE, HOST, PROXY_AUTHORIZATION};
+use axum::http::request::Parts;
+use axum::http::uri::Uri;
+use axum::http::{HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+/// Shared state of the [mirror_request] middleware.
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    settings: Arc<MirrorSettings>,
+    /// The upstream URL without trailing slash.
+    upstream: Arc<str>,
+    host: HeaderValue,
+    http_client: HttpClient,
+    /// Permits for the mirrored requests in flight.
+    permits: Arc<Semaphore>,
+}
+
+impl Mirror {
+    /// The configured mirroring, if an upstream is configured, with its own [HttpClient] using
+    /// the given settings.
+    pub fn new(settings: &MirrorSettings, http_client: &HttpClientSettings) -> Option<Self> {
+        let upstream = settings.upstream.as_deref()?;
+        let host = upstream
+            .parse::<Uri>()
+            .ok()
+            .and_then(|uri| uri.authority().cloned())
+            .and_then(|authority| HeaderValue::from_str(authority.as_str()).ok())?;
+        Some(Self {
+            settings: Arc::new(settings.clone()),
+            upstream: upstream.trim_end_matches('/').into(),
+            host,
+            http_client: HttpClient::new(http_client),
+            permits: Arc::new(Semaphore::new(settings.max_concurrent)),
+        })
+    }
+
+    fn applies(&self, request: &Request<Body>) -> bool {
+        let path = request.uri().path();
+        let body_size = request
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        let paths = &self.settings.paths;
+        (paths.is_empty() || paths.iter().any(|prefix| has_path_prefix(path, prefix)))
+            && body_size.map_or(request.method().is_safe(), |size| {
+                size <= self.settings.max_body_size
+            })
+            && (self.settings.sample_rate >= 1.0
+                || rand::random::<f64>() < self.settings.sample_rate)
+    }
+
+    fn copy(&self, parts: &Parts, body: &[u8]) -> Option<Request<Body>> {
+        let path_and_query = parts.uri.path_and_query().map_or("/", |path| path.as_str());
+        let uri = format!("{}{path_and_query}", self.upstream).parse().ok()?;
+        let mut request = Request::new(Body::from(body.to_vec()));
+        *request.method_mut() = parts.method.clone();
+        *request.uri_mut() = uri;
+        let headers = request.headers_mut();
+        *headers = parts.headers.clone();
+        remove_hop_by_hop(headers);
+        if !self.settings.forward_credentials {
+            for name in [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE] {
+                headers.remove(name);
+            }
+            headers.remove(X_API_KEY);
+        }
+        headers.insert(HOST, self.host.clone());
+        headers.insert("x-mirrored", HeaderValue::from_static("true"));
+        Some(request)
+    }
+}
+
+/// Middleware mirroring requests to the shadow upstream, see [crate::mirror].
+pub async fn mirror_request(request: Request<Body>, next: Next<Body>, mirror: Mirror) -> Response {
+    if !mirror.applies(&request) {
+        return next.run(request).await;
+    }
+    // Acquired before buffering the body, such that a slow shadow upstream cannot make tasks
+    // and memory grow without limit.
+    let permit = match mirror.permits.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            increment_counter(
+                "mirrored_requests_total",
+                &[("outcome", "dropped".to_string())],
+            );
+            return next.run(request).await;
+        }
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            return Error::Validation(format!("Cannot read request body: {e}")).into_response()
+        }
+    };
+    if let Some(copy) = mirror.copy(&parts, &body) {
+        let http_client = mirror.http_client.clone();
+        tokio::spawn(async move {
+            let outcome = match http_client.forward(copy).await {
+                Ok(response) => {
+                    // Drain the body to allow reusing the connection.
+                    let _ = hyper::body::to_bytes(response.into_body()).await;
+                    "sent"
+                }
+                Err(e) => {
+                    debug!(error = format!("{e:#}").as_str(), "Cannot mirror request");
+                    "failed"
+                }
+            };
+            increment_counter(
+                "mirrored_requests_total",
+                &[("outcome", outcome.to_string())],
+            );
+            drop(permit);
+        });
+    }
+
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy(forward_credentials: bool) -> Request<Body> {
+        let settings = MirrorSettings {
+            upstream: Some("http://shadow:8080/".to_string()),
+            forward_credentials,
+            ..Default::default()
+        };
+        let mirror = Mirror::new(&settings, &Default::default()).unwrap();
+        let (parts, _) = Request::post("/orders?page=2")
+            .header(HOST, "api.example.com")
+            .header(AUTHORIZATION, "Bearer token")
+            .header(COOKIE, "session=abc")
+            .header(X_API_KEY, "key")
+            .header("x-custom", "1")
+            .body(())
+            .unwrap()
+            .into_parts();
+        mirror.copy(&parts, b"body").unwrap()
+    }
+
+    #[test]
+    fn test_copy() {
+        let request = copy(false);
+        assert_eq!(request.uri(), "http://shadow:8080/orders?page=2");
+        let headers = request.headers();
+        assert_eq!(headers[HOST], "shadow:8080");
+        assert_eq!(headers["x-mirrored"], "true");
+        assert_eq!(headers["x-custom"], "1");
+        assert!(headers.get(AUTHORIZATION).is_none());
+        assert!(headers.get(COOKIE).is_none());
+        assert!(headers.get(X_API_KEY).is_none());
+
+        let request = copy(true);
+        let headers = request.headers();
+        assert_eq!(headers[AUTHORIZATION], "Bearer token");
+        assert_eq!(headers[COOKIE], "session=abc");
+        assert_eq!(headers[X_API_KEY], "key");
+    }
+}
@@ -0,0 +1,83 @@
+//! Features packaged as [Module]s contributing routes and taking part in the lifecycle of the
+//! service, such that they can be added without touching `main.rs`.
+
+use crate::lifecycle::Lifecycle;
+use crate::state::AppState;
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::Router;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[async_trait]
+pub trait Module: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    /// Routes merged into the API routes; handlers get the [AppState] as extension.
+    fn routes(&self) -> Router {
+        Router::new()
+    }
+
+    /// Called while the server is starting, see [crate::lifecycle], e.g. to register handlers for
+    /// [crate::domain_events]; an error aborts the startup.
+    async fn start(&self, _state: &AppState) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after the server has been shut down.
+    async fn shutdown(&self) {}
+}
+
+/// Registered [Module]s.
+#[derive(Clone, Default)]
+pub struct Modules {
+    modules: Vec<Arc<dyn Module>>,
+}
+
+impl Modules {
+    /// The modules of this service; new ones are registered here.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, module: impl Module) {
+        self.modules.push(Arc::new(module));
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.modules.iter().map(|module| module.name()).collect()
+    }
+
+    /// The merged routes of all modules.
+    pub fn routes(&self) -> Router {
+        self.modules.iter().fold(Router::new(), |routes, module| {
+            routes.merge(module.routes())
+        })
+    }
+
+    /// Register the start and shutdown of all modules as hooks with the given [Lifecycle], each
+    /// with the respective timeout.
+    pub fn register_hooks(
+        &self,
+        lifecycle: &mut Lifecycle,
+        state: &AppState,
+        startup_timeout: Duration,
+        shutdown_timeout: Duration,
+    ) {
+        for module in &self.modules {
+            let name = format!("module {}", module.name());
+
+            let start_module = module.clone();
+            let state = state.clone();
+            lifecycle.on_start(name.clone(), startup_timeout, move || async move {
+                start_module.start(&state).await
+            });
+
+            let module = module.clone();
+            lifecycle.on_shutdown(name, shutdown_timeout, move || async move {
+                module.shutdown().await;
+                Ok(())
+            });
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! Streaming parser for `multipart/form-data` request bodies (RFC 7578), yielding the contents of
+//! each field in chunks, such that uploaded files need not be held in memory.
+
+use crate::error::Error;
+use axum::body::Bytes;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderMap;
+use axum::BoxError;
+use futures_util::{Stream, StreamExt};
+use std::mem;
+
+const MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+/// Metadata of a field, taken from its `Content-Disposition` and `Content-Type` headers.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    /// Only present for files.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// Fields are read one after the other: [Multipart::next_field] returns the next [Field], skipping
+/// what has not been read of the current one, and [Multipart::chunk] its contents.
+pub struct Multipart<S> {
+    body: S,
+    /// `CRLF--<boundary>`, preceding every part.
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Field,
+    Delimiter,
+    Done,
+}
+
+impl<S, E> Multipart<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<BoxError>,
+{
+    /// Rejects requests which are not `multipart/form-data` with 415 and those without a
+    /// boundary with 400.
+    pub fn new(body: S, headers: &HeaderMap) -> Result<Self, Error> {
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let mut params = content_type.split(';').map(str::trim);
+        if !params
+            .next()
+            .unwrap_or_default()
+            .eq_ignore_ascii_case("multipart/form-data")
+        {
+            return Err(Error::UnsupportedMediaType(
+                "Expected multipart/form-data".to_string(),
+            ));
+        }
+        let boundary = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+            .map(|(_, boundary)| unquote(boundary.trim()))
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+            .ok_or_else(|| Error::Validation("Missing multipart boundary".to_string()))?;
+
+        Ok(Self {
+            body,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            // Such that the first delimiter, which is not preceded by CRLF, is found, too.
+            buf: b"\r\n".to_vec(),
+            state: State::Preamble,
+        })
+    }
+
+    /// The next field or `None` after the last one.
+    pub async fn next_field(&mut self) -> Result<Option<Field>, Error> {
+        while matches!(self.state, State::Preamble | State::Field) {
+            self.read_part().await?;
+        }
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        while self.buf.len() < 2 {
+            self.fill().await?;
+        }
+        if self.buf.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+
+        // The headers start with the CRLF after the delimiter and end with an empty line.
+        let end = loop {
+            if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buf.len() > MAX_HEADERS_SIZE {
+                return Err(malformed("Multipart headers too large"));
+            }
+            self.fill().await?;
+        };
+        // The end may only have been found after a large chunk has been read.
+        if end > MAX_HEADERS_SIZE {
+            return Err(malformed("Multipart headers too large"));
+        }
+        let rest = self.buf.split_off(end + 4);
+        let headers = mem::replace(&mut self.buf, rest);
+        let headers = std::str::from_utf8(&headers[..end])
+            .map_err(|_| malformed("Invalid multipart headers"))?;
+
+        self.state = State::Field;
+        parse_headers(headers).map(Some)
+    }
+
+    /// The next chunk of the current field or `None` after its end.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        if self.state == State::Field {
+            self.read_part().await
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn read_part(&mut self) -> Result<Option<Bytes>, Error> {
+        loop {
+            if let Some(n) = find(&self.buf, &self.delimiter) {
+                let rest = self.buf.split_off(n + self.delimiter.len());
+                let mut data = mem::replace(&mut self.buf, rest);
+                data.truncate(n);
+                let preamble = self.state == State::Preamble;
+                self.state = State::Delimiter;
+                return Ok((!preamble && !data.is_empty()).then(|| data.into()));
+            }
+
+            // Everything but a potential prefix of the delimiter belongs to the part.
+            let safe = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 && self.state == State::Field {
+                let rest = self.buf.split_off(safe);
+                return Ok(Some(mem::replace(&mut self.buf, rest).into()));
+            }
+            if self.state == State::Preamble {
+                self.buf.drain(..safe);
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), Error> {
+        match self.body.next().await {
+            Some(Ok(bytes)) => {
+                self.buf.extend_from_slice(&bytes);
+                Ok(())
+            }
+            Some(Err(e)) => Err(Error::Validation(format!(
+                "Cannot read request body: {}",
+                e.into()
+            ))),
+            None => Err(malformed("Unexpected end of multipart body")),
+        }
+    }
+}
+
+fn parse_headers(headers: &str) -> Result<Field, Error> {
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+
+    for line in headers.split("\r\n").filter(|line| !line.is_empty()) {
+        let (header, value) = line
+            .split_once(':')
+            .ok_or_else(|| malformed("Invalid multipart header"))?;
+        let header = header.trim();
+        if header.eq_ignore_ascii_case("content-disposition") {
+            for param in value.split(';').skip(1) {
+                match param.split_once('=') {
+                    Some((key, value)) if key.trim().eq_ignore_ascii_case("name") => {
+                        name = Some(unquote(value.trim()))
+                    }
+                    Some((key, value)) if key.trim().eq_ignore_ascii_case("filename") => {
+                        filename = Some(unquote(value.trim()))
+                    }
+                    _ => {}
+                }
+            }
+        } else if header.eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_owned());
+        }
+    }
+
+    let name = name.ok_or_else(|| malformed("Multipart field without name"))?;
+    Ok(Field {
+        name,
+        filename,
+        content_type,
+    })
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(value) => value.replace(r#"\""#, r#"""#),
+        None => value.to_owned(),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn malformed(message: &str) -> Error {
+    Error::Validation(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use futures_util::stream::{self, Iter};
+    use std::convert::Infallible;
+    use std::vec::IntoIter;
+
+    const BODY: &[u8] = b"This is the preamble.\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Hello\r\n\
+        --XyZ\r\n\
+        content-disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line 1\r\n--XyY\r\n--Xy\r\n-- XyZ --XyZ\r\n\
+        \r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"empty\"; filename=\"empty.txt\"\r\n\
+        \r\n\
+        \r\n\
+        --XyZ--\r\n\
+        This is the epilogue.";
+
+    type Body = Iter<IntoIter<Result<Bytes, Infallible>>>;
+
+    fn headers(content_type: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        headers
+    }
+
+    /// The given body in chunks of the given size.
+    fn chunked(body: &[u8], size: usize) -> Body {
+        let chunks = body
+            .chunks(size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        stream::iter(chunks)
+    }
+
+    fn multipart(body: &[u8], size: usize, boundary: &str) -> Multipart<Body> {
+        let content_type = format!("multipart/form-data; boundary={boundary}");
+        Multipart::new(chunked(body, size), &headers(&content_type)).unwrap()
+    }
+
+    /// All fields with their contents.
+    async fn read_all(mut multipart: Multipart<Body>) -> Result<Vec<(Field, Vec<u8>)>, Error> {
+        let mut fields = vec![];
+        while let Some(field) = multipart.next_field().await? {
+            let mut data = vec![];
+            while let Some(chunk) = multipart.chunk().await? {
+                assert!(!chunk.is_empty());
+                data.extend_from_slice(&chunk);
+            }
+            fields.push((field, data));
+        }
+        Ok(fields)
+    }
+
+    fn message(error: Error) -> String {
+        match error {
+            Error::Validation(message) => message,
+            error => panic!("unexpected error {error}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fields() {
+        // Every chunk size splits the delimiters at other positions.
+        for size in 1..=BODY.len() {
+            let fields = read_all(multipart(BODY, size, "XyZ")).await.unwrap();
+            assert_eq!(fields.len(), 3, "chunk size {size}");
+
+            let (field, data) = &fields[0];
+            assert_eq!(field.name, "title");
+            assert_eq!(field.filename, None);
+            assert_eq!(field.content_type, None);
+            assert_eq!(data, b"Hello");
+
+            let (field, data) = &fields[1];
+            assert_eq!(field.name, "file");
+            assert_eq!(field.filename.as_deref(), Some(r#"a "b".txt"#));
+            assert_eq!(field.content_type.as_deref(), Some("text/plain"));
+            assert_eq!(data, b"line 1\r\n--XyY\r\n--Xy\r\n-- XyZ --XyZ\r\n");
+
+            let (field, data) = &fields[2];
+            assert_eq!(field.name, "empty");
+            assert_eq!(field.filename.as_deref(), Some("empty.txt"));
+            assert!(data.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skip_unread_fields() {
+        for size in [1, 7, BODY.len()] {
+            let mut multipart = multipart(BODY, size, "XyZ");
+            assert_eq!(multipart.next_field().await.unwrap().unwrap().name, "title");
+            assert_eq!(multipart.next_field().await.unwrap().unwrap().name, "file");
+            assert!(multipart.chunk().await.unwrap().is_some());
+            assert_eq!(multipart.next_field().await.unwrap().unwrap().name, "empty");
+            assert!(multipart.next_field().await.unwrap().is_none());
+            assert!(multipart.next_field().await.unwrap().is_none());
+            assert!(multipart.chunk().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_fields() {
+        let fields = read_all(multipart(b"--XyZ--", 3, "XyZ")).await.unwrap();
+        assert!(fields.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_missing_final_boundary() {
+        let truncated = [
+            &BODY[..BODY.iter().rposition(|b| *b == b'-').unwrap() - 6],
+            &BODY[..BODY.len() - b"--\r\nThis is the epilogue.".len()],
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno end",
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n",
+            b"no delimiter at all",
+            b"",
+        ];
+        for body in truncated {
+            for size in [1, 5, body.len().max(1)] {
+                let error = read_all(multipart(body, size, "XyZ")).await.unwrap_err();
+                assert_eq!(message(error), "Unexpected end of multipart body");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_boundary_parameter() {
+        let body = b"--a'(b)+_,-./:=? c\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\
+            \r\n\
+            1\r\n\
+            --a'(b)+_,-./:=? c--";
+        for content_type in [
+            r#"multipart/form-data; boundary="a'(b)+_,-./:=? c""#,
+            r#"Multipart/Form-Data;charset=utf-8;  BOUNDARY = "a'(b)+_,-./:=? c" "#,
+        ] {
+            let multipart = Multipart::new(chunked(body, 4), &headers(content_type)).unwrap();
+            let fields = read_all(multipart).await.unwrap();
+            assert_eq!(fields.len(), 1);
+            assert_eq!(fields[0].1, b"1");
+        }
+
+        let webkit = "----WebKitFormBoundary7MA4YWxkTrZu0gW";
+        let body = format!(
+            "--{webkit}\r\nContent-Disposition: form-data; name=a\r\n\r\n1\r\n--{webkit}--"
+        );
+        let fields = read_all(multipart(body.as_bytes(), 2, webkit))
+            .await
+            .unwrap();
+        assert_eq!(fields[0].0.name, "a");
+        assert_eq!(fields[0].1, b"1");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_content_type() {
+        for content_type in ["application/json", "multipart/mixed; boundary=a", ""] {
+            let error = Multipart::new(chunked(b"", 1), &headers(content_type)).err();
+            assert!(matches!(error, Some(Error::UnsupportedMediaType(_))));
+        }
+
+        let too_long = format!("multipart/form-data; boundary={}", "a".repeat(71));
+        for content_type in [
+            "multipart/form-data",
+            "multipart/form-data; charset=utf-8",
+            "multipart/form-data; boundary=",
+            r#"multipart/form-data; boundary="""#,
+            &too_long,
+        ] {
+            let error = Multipart::new(chunked(b"", 1), &headers(content_type)).err();
+            assert_eq!(message(error.unwrap()), "Missing multipart boundary");
+        }
+        let longest = format!("multipart/form-data; boundary={}", "a".repeat(70));
+        assert!(Multipart::new(chunked(b"", 1), &headers(&longest)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_headers() {
+        let body = format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=a\r\nX-Padding: {}\r\n\r\n1\r\n--XyZ--",
+            "x".repeat(MAX_HEADERS_SIZE)
+        );
+        for size in [1024, body.len()] {
+            let error = read_all(multipart(body.as_bytes(), size, "XyZ"))
+                .await
+                .unwrap_err();
+            assert_eq!(message(error), "Multipart headers too large");
+        }
+
+        let body = b"--XyZ\r\nContent-Disposition: form-data\r\n\r\n1\r\n--XyZ--";
+        let error = read_all(multipart(body, 8, "XyZ")).await.unwrap_err();
+        assert_eq!(message(error), "Multipart field without name");
+
+        let body = b"--XyZ\r\nContent-Disposition form-data; name=a\r\n\r\n1\r\n--XyZ--";
+        let error = read_all(multipart(body, 8, "XyZ")).await.unwrap_err();
+        assert_eq!(message(error), "Invalid multipart header");
+
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\xff\r\n\r\n1\r\n--XyZ--";
+        let error = read_all(multipart(body, 8, "XyZ")).await.unwrap_err();
+        assert_eq!(message(error), "Invalid multipart headers");
+    }
+
+    #[tokio::test]
+    async fn test_large_field() {
+        let data = (0..100_000).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+        let mut body =
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"; filename=\"a\"\r\n\r\n".to_vec();
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n--XyZ--");
+
+        // Contents are yielded before the end of the field has been read.
+        let mut multipart = multipart(&body, 1000, "XyZ");
+        multipart.next_field().await.unwrap().unwrap();
+        let first = multipart.chunk().await.unwrap().unwrap();
+        assert!(first.len() < 1000);
+        let mut read = first.to_vec();
+        while let Some(chunk) = multipart.chunk().await.unwrap() {
+            read.extend_from_slice(&chunk);
+        }
+        assert_eq!(read, data);
+    }
+}
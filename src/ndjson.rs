@@ -0,0 +1,48 @@
+//! Streaming responses with newline delimited JSON (NDJSON), see [Ndjson].
+
+use crate::log_error_chain;
+use axum::body::StreamBody;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderValue;
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+
+const APPLICATION_NDJSON: &str = "application/x-ndjson";
+
+/// Response streaming the items of the given stream as chunked `application/x-ndjson`, one JSON
+/// value per line, e.g. for large result sets which should not be buffered.
+///
+/// Items are only polled when the connection is ready to send more, hence slow clients apply
+/// backpressure to the stream. If the stream yields an error, it is logged and the response is
+/// aborted, such that clients do not mistake a partial result for a complete one.
+#[derive(Debug)]
+pub struct Ndjson<S>(pub S);
+
+impl<S, T, E> IntoResponse for Ndjson<S>
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Serialize,
+    E: Into<BoxError>,
+{
+    fn into_response(self) -> Response {
+        let lines = self.0.map(|item| {
+            let line = item.map_err(Into::into).and_then(|item| {
+                let mut line = serde_json::to_vec(&item)?;
+                line.push(b'\n');
+                Ok(line)
+            });
+            if let Err(e) = &line {
+                log_error_chain("Cannot stream NDJSON response", e.as_ref());
+            }
+            line
+        });
+
+        let mut response = StreamBody::new(lines).into_response();
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(APPLICATION_NDJSON));
+        response
+    }
+}
@@ -0,0 +1,209 @@
+//! Content negotiation: [Negotiate] deserializes request bodies according to their `Content-Type`
+//! and serializes responses in the preferred [Format] according to `Accept`, supporting JSON,
+//! MessagePack and CBOR.
+
+mod cbor;
+mod msgpack;
+
+use crate::error::Error;
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::body::{boxed, Bytes, Full, HttpBody};
+use axum::extract::{FromRequest, RequestParts};
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Representation formats supported by [Negotiate].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    /// The format for the given media type, ignoring parameters; `+json` suffixes and the
+    /// unregistered `x-msgpack` and `vnd.msgpack` subtypes are supported, too.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        let essence = media_type.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            "application/cbor" => Some(Format::Cbor),
+            essence if essence.starts_with("application/") && essence.ends_with("+json") => {
+                Some(Format::Json)
+            }
+            _ => None,
+        }
+    }
+
+    /// The acceptable format with the highest quality according to the given `Accept` header,
+    /// preferring JSON for wildcards and if there is no `Accept` header at all.
+    pub fn from_accept(headers: &HeaderMap) -> Result<Self, Error> {
+        let accept = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .collect::<Vec<_>>();
+        if accept.is_empty() {
+            return Ok(Format::Json);
+        }
+
+        let mut best = None;
+        for range in accept {
+            let quality = quality(range);
+            let format = match range.split(';').next().unwrap_or_default().trim() {
+                "*/*" | "application/*" => Some(Format::Json),
+                media_type => Format::from_media_type(media_type),
+            };
+            if let Some(format) = format.filter(|_| quality > 0.0) {
+                if best.map_or(true, |(_, best_quality)| quality > best_quality) {
+                    best = Some((format, quality));
+                }
+            }
+        }
+        best.map(|(format, _)| format).ok_or_else(|| {
+            Error::NotAcceptable(
+                "Supported are application/json, application/msgpack and application/cbor"
+                    .to_string(),
+            )
+        })
+    }
+
+    fn serialize<T>(self, value: &T) -> anyhow::Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        match self {
+            Format::Json => serde_json::to_vec(value).context("Cannot serialize as JSON"),
+            Format::MessagePack => serde_json::to_value(value)
+                .map(|value| msgpack::encode(&value))
+                .context("Cannot serialize as MessagePack"),
+            Format::Cbor => serde_json::to_value(value)
+                .map(|value| cbor::encode(&value))
+                .context("Cannot serialize as CBOR"),
+        }
+    }
+
+    fn deserialize<T>(self, bytes: &[u8]) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let value = match self {
+            Format::Json => return serde_json::from_slice(bytes).context("Invalid JSON"),
+            Format::MessagePack => msgpack::decode(bytes).context("Invalid MessagePack")?,
+            Format::Cbor => cbor::decode(bytes).context("Invalid CBOR")?,
+        };
+        serde_json::from_value(value).context("Invalid request body")
+    }
+}
+
+/// Extractor for the [Format] to respond with according to the `Accept` header, rejecting
+/// requests without any supported format with 406.
+#[async_trait]
+impl<B> FromRequest<B> for Format
+where
+    B: Send,
+{
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let headers = request
+            .headers()
+            .ok_or_else(|| anyhow::anyhow!("Headers already taken"))?;
+        Format::from_accept(headers)
+    }
+}
+
+/// Extractor deserializing the request body according to its `Content-Type`, together with the
+/// [Format] to respond with, and response serializing its value in the given format.
+///
+/// ```ignore
+/// async fn create(Negotiate(format, input): Negotiate<Input>) -> Negotiate<Output> {
+///     Negotiate(format, output(input))
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Negotiate<T>(pub Format, pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Negotiate<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let response_format = Format::from_request(request).await?;
+        let headers = request
+            .headers()
+            .ok_or_else(|| anyhow::anyhow!("Headers already taken"))?;
+        let format = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Format::from_media_type)
+            .ok_or_else(|| {
+                Error::UnsupportedMediaType(
+                    "Expected application/json, application/msgpack or application/cbor"
+                        .to_string(),
+                )
+            })?;
+        let bytes = Bytes::from_request(request)
+            .await
+            .map_err(|e| Error::Internal(e.into()))?;
+        let value = format
+            .deserialize(&bytes)
+            .map_err(|e| Error::Validation(format!("{e:#}")))?;
+        Ok(Self(response_format, value))
+    }
+}
+
+impl<T> IntoResponse for Negotiate<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        let Negotiate(format, value) = self;
+        match format.serialize(&value) {
+            Ok(body) => {
+                let mut response = Response::new(boxed(Full::from(body)));
+                response.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static(format.content_type()),
+                );
+                response
+            }
+            Err(e) => Error::Internal(e).into_response(),
+        }
+    }
+}
+
+/// The quality of the given media range, i.e. its `q` parameter defaulting to 1.
+fn quality(range: &str) -> f32 {
+    range
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.trim().strip_prefix("q="))
+        .find_map(|q| q.trim().parse().ok())
+        .unwrap_or(1.0)
+}
@@ -0,0 +1,368 @@
+//! Minimal CBOR (RFC 8949) encoding and decoding of JSON values. Byte strings are decoded as an
+//! array of bytes, tags are ignored and indefinite lengths are not supported.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{Map, Number, Value};
+
+const MAX_DEPTH: usize = 128;
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_value(value, &mut buf);
+    buf
+}
+
+fn encode_value(value: &Value, buf: &mut Vec<u8>) {
+    match value {
+        Value::Null => buf.push(0xf6),
+        Value::Bool(false) => buf.push(0xf4),
+        Value::Bool(true) => buf.push(0xf5),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                encode_head(UNSIGNED, n, buf);
+            } else if let Some(n) = n.as_i64() {
+                encode_head(NEGATIVE, !(n as u64), buf);
+            } else {
+                buf.push(0xfb);
+                buf.extend_from_slice(&n.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        }
+        Value::String(s) => {
+            encode_head(TEXT, s.len() as u64, buf);
+            buf.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(values) => {
+            encode_head(ARRAY, values.len() as u64, buf);
+            values.iter().for_each(|value| encode_value(value, buf));
+        }
+        Value::Object(entries) => {
+            encode_head(MAP, entries.len() as u64, buf);
+            for (key, value) in entries {
+                encode_head(TEXT, key.len() as u64, buf);
+                buf.extend_from_slice(key.as_bytes());
+                encode_value(value, buf);
+            }
+        }
+    }
+}
+
+fn encode_head(major: u8, n: u64, buf: &mut Vec<u8>) {
+    let major = major << 5;
+    if n < 24 {
+        buf.push(major | n as u8);
+    } else if n <= u8::MAX as u64 {
+        buf.push(major | 24);
+        buf.push(n as u8);
+    } else if n <= u16::MAX as u64 {
+        buf.push(major | 25);
+        buf.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u32::MAX as u64 {
+        buf.push(major | 26);
+        buf.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        buf.push(major | 27);
+        buf.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder { bytes, pos: 0 };
+    let value = decoder.value(0)?;
+    if decoder.pos != bytes.len() {
+        bail!("Trailing bytes after CBOR value");
+    }
+    Ok(value)
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("CBOR value nested too deeply");
+        }
+
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let info = initial & 0x1f;
+
+        if major == SIMPLE {
+            return match info {
+                20 => Ok(Value::Bool(false)),
+                21 => Ok(Value::Bool(true)),
+                22 | 23 => Ok(Value::Null),
+                25 => Ok(float(half_to_f64(self.uint(2)? as u16))),
+                26 => Ok(float(f32::from_bits(self.uint(4)? as u32) as f64)),
+                27 => Ok(float(f64::from_bits(self.uint(8)?))),
+                info => bail!("Unsupported CBOR simple value {info}"),
+            };
+        }
+
+        let n = match info {
+            0..=23 => info as u64,
+            24 => self.uint(1)?,
+            25 => self.uint(2)?,
+            26 => self.uint(4)?,
+            27 => self.uint(8)?,
+            31 => bail!("Unsupported indefinite length CBOR value"),
+            info => bail!("Invalid CBOR additional information {info}"),
+        };
+
+        let value = match major {
+            UNSIGNED => Value::from(n),
+            NEGATIVE => {
+                let n = i64::try_from(n).context("CBOR negative integer out of range")?;
+                Value::from(-1 - n)
+            }
+            BYTES => Value::Array(
+                self.take(len(n)?)?
+                    .iter()
+                    .map(|b| Value::from(*b))
+                    .collect(),
+            ),
+            TEXT => {
+                let s =
+                    std::str::from_utf8(self.take(len(n)?)?).context("Invalid UTF-8 in CBOR")?;
+                Value::String(s.to_owned())
+            }
+            ARRAY => {
+                let len = len(n)?;
+                let mut values = Vec::with_capacity(len.min(self.bytes.len() - self.pos));
+                for _ in 0..len {
+                    values.push(self.value(depth + 1)?);
+                }
+                Value::Array(values)
+            }
+            MAP => {
+                let mut entries = Map::new();
+                for _ in 0..len(n)? {
+                    let key = match self.value(depth + 1)? {
+                        Value::String(key) => key,
+                        _ => bail!("Unsupported non-string CBOR map key"),
+                    };
+                    entries.insert(key, self.value(depth + 1)?);
+                }
+                Value::Object(entries)
+            }
+            TAG => self.value(depth + 1)?,
+            _ => unreachable!("major type has three bits"),
+        };
+        Ok(value)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| anyhow!("Unexpected end of CBOR value"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |n, byte| (n << 8) | *byte as u64))
+    }
+}
+
+fn len(n: u64) -> Result<usize> {
+    usize::try_from(n).context("CBOR length out of range")
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let sign = if half & 0x8000 == 0 { 1.0 } else { -1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent - 25),
+    };
+    sign * magnitude
+}
+
+fn float(n: f64) -> Value {
+    Number::from_f64(n)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The encoding of the given value, which must decode to the same value.
+    fn round_trip(value: Value) -> Vec<u8> {
+        let bytes = encode(&value);
+        assert_eq!(decode(&bytes).unwrap(), value);
+        bytes
+    }
+
+    #[test]
+    fn test_integers() {
+        let cases: [(Value, &[u8]); 19] = [
+            (json!(0), &[0x00]),
+            (json!(23), &[0x17]),
+            (json!(24), &[0x18, 0x18]),
+            (json!(255), &[0x18, 0xff]),
+            (json!(256), &[0x19, 0x01, 0x00]),
+            (json!(65_535), &[0x19, 0xff, 0xff]),
+            (json!(65_536), &[0x1a, 0x00, 0x01, 0x00, 0x00]),
+            (json!(u32::MAX), &[0x1a, 0xff, 0xff, 0xff, 0xff]),
+            (json!(u32::MAX as u64 + 1), &[0x1b, 0, 0, 0, 1, 0, 0, 0, 0]),
+            (
+                json!(u64::MAX),
+                &[0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            ),
+            (json!(-1), &[0x20]),
+            (json!(-24), &[0x37]),
+            (json!(-25), &[0x38, 0x18]),
+            (json!(-256), &[0x38, 0xff]),
+            (json!(-257), &[0x39, 0x01, 0x00]),
+            (json!(-65_537), &[0x3a, 0x00, 0x01, 0x00, 0x00]),
+            (json!(i32::MIN), &[0x3a, 0x7f, 0xff, 0xff, 0xff]),
+            (json!(i32::MIN as i64 - 1), &[0x3a, 0x80, 0x00, 0x00, 0x00]),
+            (
+                json!(i64::MIN),
+                &[0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            ),
+        ];
+        for (value, bytes) in cases {
+            assert_eq!(round_trip(value), bytes);
+        }
+
+        // Below i64::MIN.
+        assert!(decode(&[0x3b, 0x80, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(
+            round_trip(json!(1.5)),
+            [0xfb, 0x3f, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        round_trip(json!(-2.5e-300));
+        round_trip(json!(f64::MAX));
+
+        // Half and single precision, see RFC 8949, appendix A.
+        assert_eq!(decode(&[0xf9, 0x3c, 0x00]).unwrap(), json!(1.0));
+        assert_eq!(decode(&[0xf9, 0x3e, 0x00]).unwrap(), json!(1.5));
+        assert_eq!(decode(&[0xf9, 0x7b, 0xff]).unwrap(), json!(65_504.0));
+        assert_eq!(decode(&[0xf9, 0xc4, 0x00]).unwrap(), json!(-4.0));
+        assert_eq!(
+            decode(&[0xf9, 0x00, 0x01]).unwrap(),
+            json!(5.960_464_477_539_063e-8)
+        );
+        assert_eq!(
+            decode(&[0xfa, 0x47, 0xc3, 0x50, 0x00]).unwrap(),
+            json!(100_000.0)
+        );
+        // NaN and infinity have no JSON representation.
+        assert_eq!(decode(&[0xf9, 0x7c, 0x00]).unwrap(), Value::Null);
+        assert_eq!(decode(&[0xf9, 0x7e, 0x00]).unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn test_strings() {
+        for (len, head) in [
+            (0, vec![0x60]),
+            (23, vec![0x77]),
+            (24, vec![0x78, 24]),
+            (255, vec![0x78, 0xff]),
+            (256, vec![0x79, 0x01, 0x00]),
+            (65_536, vec![0x7a, 0x00, 0x01, 0x00, 0x00]),
+        ] {
+            let bytes = round_trip(Value::String("x".repeat(len)));
+            assert_eq!(bytes[..head.len()], head);
+            assert_eq!(bytes.len(), head.len() + len);
+        }
+        round_trip(json!("Grüß Gott 👋"));
+    }
+
+    #[test]
+    fn test_arrays_and_maps() {
+        for (len, array_head, map_head) in [
+            (0, vec![0x80], vec![0xa0]),
+            (23, vec![0x97], vec![0xb7]),
+            (24, vec![0x98, 24], vec![0xb8, 24]),
+            (256, vec![0x99, 0x01, 0x00], vec![0xb9, 0x01, 0x00]),
+        ] {
+            let bytes = round_trip(Value::Array(vec![Value::Null; len]));
+            assert_eq!(bytes[..array_head.len()], array_head);
+
+            let map = (0..len)
+                .map(|n| (n.to_string(), Value::Bool(true)))
+                .collect::<Map<_, _>>();
+            let bytes = round_trip(Value::Object(map));
+            assert_eq!(bytes[..map_head.len()], map_head);
+        }
+    }
+
+    #[test]
+    fn test_nested() {
+        assert_eq!(
+            round_trip(json!({ "a": [1, { "b": null }] })),
+            [0xa1, 0x61, b'a', 0x82, 0x01, 0xa1, 0x61, b'b', 0xf6]
+        );
+        round_trip(json!({
+            "id": 42,
+            "tags": ["a", "b", []],
+            "owner": { "name": "x".repeat(300), "roles": [{ "admin": true }, null] },
+            "score": -0.25,
+        }));
+    }
+
+    #[test]
+    fn test_decode_bytes_tags_and_simple_values() {
+        assert_eq!(decode(&[0x43, 1, 2, 255]).unwrap(), json!([1, 2, 255]));
+        // Tag 1, epoch-based date/time.
+        assert_eq!(
+            decode(&[0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]).unwrap(),
+            json!(1_363_896_240)
+        );
+        assert_eq!(decode(&[0xf4]).unwrap(), json!(false));
+        assert_eq!(decode(&[0xf5]).unwrap(), json!(true));
+        assert_eq!(decode(&[0xf7]).unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // Truncated, including lengths exceeding the input.
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[0x19, 0x01]).is_err());
+        assert!(decode(&[0x63, b'a', b'b']).is_err());
+        assert!(decode(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode(&[0x82, 0x01]).is_err());
+
+        assert!(decode(&[0x01, 0x02]).is_err());
+        // Indefinite lengths and reserved additional information.
+        assert!(decode(&[0x9f, 0x01, 0xff]).is_err());
+        assert!(decode(&[0x1c]).is_err());
+        assert!(decode(&[0xf0]).is_err());
+        assert!(decode(&[0x62, 0xff, 0xfe]).is_err());
+        assert!(decode(&[0xa1, 0x01, 0x02]).is_err());
+
+        let mut nested = vec![0x81; MAX_DEPTH + 1];
+        nested.push(0xf6);
+        assert!(decode(&nested).is_err());
+        assert!(decode(&nested[1..]).is_ok());
+    }
+}
@@ -0,0 +1,389 @@
+//! Minimal MessagePack encoding and decoding of JSON values. Binary data is decoded as an array
+//! of bytes, extension types are not supported.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{Map, Number, Value};
+
+const MAX_DEPTH: usize = 128;
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_value(value, &mut buf);
+    buf
+}
+
+fn encode_value(value: &Value, buf: &mut Vec<u8>) {
+    match value {
+        Value::Null => buf.push(0xc0),
+        Value::Bool(false) => buf.push(0xc2),
+        Value::Bool(true) => buf.push(0xc3),
+        Value::Number(n) => encode_number(n, buf),
+        Value::String(s) => {
+            let len = s.len();
+            if len < 32 {
+                buf.push(0xa0 | len as u8);
+            } else if len <= u8::MAX as usize {
+                buf.push(0xd9);
+                buf.push(len as u8);
+            } else if len <= u16::MAX as usize {
+                buf.push(0xda);
+                buf.extend_from_slice(&(len as u16).to_be_bytes());
+            } else {
+                buf.push(0xdb);
+                buf.extend_from_slice(&(len as u32).to_be_bytes());
+            }
+            buf.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(values) => {
+            encode_len(values.len(), 0x90, 0xdc, 0xdd, buf);
+            values.iter().for_each(|value| encode_value(value, buf));
+        }
+        Value::Object(entries) => {
+            encode_len(entries.len(), 0x80, 0xde, 0xdf, buf);
+            for (key, value) in entries {
+                encode_value(&Value::String(key.to_owned()), buf);
+                encode_value(value, buf);
+            }
+        }
+    }
+}
+
+fn encode_number(n: &Number, buf: &mut Vec<u8>) {
+    if let Some(n) = n.as_u64() {
+        if n <= 0x7f {
+            buf.push(n as u8);
+        } else if n <= u8::MAX as u64 {
+            buf.push(0xcc);
+            buf.push(n as u8);
+        } else if n <= u16::MAX as u64 {
+            buf.push(0xcd);
+            buf.extend_from_slice(&(n as u16).to_be_bytes());
+        } else if n <= u32::MAX as u64 {
+            buf.push(0xce);
+            buf.extend_from_slice(&(n as u32).to_be_bytes());
+        } else {
+            buf.push(0xcf);
+            buf.extend_from_slice(&n.to_be_bytes());
+        }
+    } else if let Some(n) = n.as_i64() {
+        if n >= -32 {
+            buf.push(n as u8);
+        } else if n >= i8::MIN as i64 {
+            buf.push(0xd0);
+            buf.push(n as u8);
+        } else if n >= i16::MIN as i64 {
+            buf.push(0xd1);
+            buf.extend_from_slice(&(n as i16).to_be_bytes());
+        } else if n >= i32::MIN as i64 {
+            buf.push(0xd2);
+            buf.extend_from_slice(&(n as i32).to_be_bytes());
+        } else {
+            buf.push(0xd3);
+            buf.extend_from_slice(&n.to_be_bytes());
+        }
+    } else {
+        let n = n.as_f64().unwrap_or_default();
+        buf.push(0xcb);
+        buf.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn encode_len(len: usize, fix: u8, marker16: u8, marker32: u8, buf: &mut Vec<u8>) {
+    if len < 16 {
+        buf.push(fix | len as u8);
+    } else if len <= u16::MAX as usize {
+        buf.push(marker16);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        buf.push(marker32);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder { bytes, pos: 0 };
+    let value = decoder.value(0)?;
+    if decoder.pos != bytes.len() {
+        bail!("Trailing bytes after MessagePack value");
+    }
+    Ok(value)
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("MessagePack value nested too deeply");
+        }
+
+        let marker = self.take(1)?[0];
+        let value = match marker {
+            0x00..=0x7f => Value::from(marker),
+            0x80..=0x8f => self.map((marker & 0x0f) as usize, depth)?,
+            0x90..=0x9f => self.array((marker & 0x0f) as usize, depth)?,
+            0xa0..=0xbf => self.string((marker & 0x1f) as usize)?,
+            0xc0 => Value::Null,
+            0xc2 => Value::Bool(false),
+            0xc3 => Value::Bool(true),
+            0xc4 => self.binary(1)?,
+            0xc5 => self.binary(2)?,
+            0xc6 => self.binary(4)?,
+            0xca => float(f32::from_be_bytes(self.array_of()?) as f64),
+            0xcb => float(f64::from_be_bytes(self.array_of()?)),
+            0xcc => Value::from(self.uint(1)?),
+            0xcd => Value::from(self.uint(2)?),
+            0xce => Value::from(self.uint(4)?),
+            0xcf => Value::from(self.uint(8)?),
+            0xd0 => Value::from(i8::from_be_bytes(self.array_of()?)),
+            0xd1 => Value::from(i16::from_be_bytes(self.array_of()?)),
+            0xd2 => Value::from(i32::from_be_bytes(self.array_of()?)),
+            0xd3 => Value::from(i64::from_be_bytes(self.array_of()?)),
+            0xd9 => {
+                let len = self.uint(1)? as usize;
+                self.string(len)?
+            }
+            0xda => {
+                let len = self.uint(2)? as usize;
+                self.string(len)?
+            }
+            0xdb => {
+                let len = self.uint(4)? as usize;
+                self.string(len)?
+            }
+            0xdc => {
+                let len = self.uint(2)? as usize;
+                self.array(len, depth)?
+            }
+            0xdd => {
+                let len = self.uint(4)? as usize;
+                self.array(len, depth)?
+            }
+            0xde => {
+                let len = self.uint(2)? as usize;
+                self.map(len, depth)?
+            }
+            0xdf => {
+                let len = self.uint(4)? as usize;
+                self.map(len, depth)?
+            }
+            0xe0..=0xff => Value::from(marker as i8),
+            marker => bail!("Unsupported MessagePack marker {marker:#04x}"),
+        };
+        Ok(value)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| anyhow!("Unexpected end of MessagePack value"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array_of<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |n, byte| (n << 8) | *byte as u64))
+    }
+
+    fn string(&mut self, len: usize) -> Result<Value> {
+        let s = std::str::from_utf8(self.take(len)?).context("Invalid UTF-8 in MessagePack")?;
+        Ok(Value::String(s.to_owned()))
+    }
+
+    fn binary(&mut self, len_size: usize) -> Result<Value> {
+        let len = self.uint(len_size)? as usize;
+        Ok(Value::Array(
+            self.take(len)?.iter().map(|b| Value::from(*b)).collect(),
+        ))
+    }
+
+    fn array(&mut self, len: usize, depth: usize) -> Result<Value> {
+        let mut values = Vec::with_capacity(len.min(self.bytes.len() - self.pos));
+        for _ in 0..len {
+            values.push(self.value(depth + 1)?);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn map(&mut self, len: usize, depth: usize) -> Result<Value> {
+        let mut entries = Map::new();
+        for _ in 0..len {
+            let key = match self.value(depth + 1)? {
+                Value::String(key) => key,
+                _ => bail!("Unsupported non-string MessagePack map key"),
+            };
+            entries.insert(key, self.value(depth + 1)?);
+        }
+        Ok(Value::Object(entries))
+    }
+}
+
+fn float(n: f64) -> Value {
+    Number::from_f64(n)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The encoding of the given value, which must decode to the same value.
+    fn round_trip(value: Value) -> Vec<u8> {
+        let bytes = encode(&value);
+        assert_eq!(decode(&bytes).unwrap(), value);
+        bytes
+    }
+
+    #[test]
+    fn test_integers() {
+        let cases: [(Value, &[u8]); 12] = [
+            (json!(0), &[0x00]),
+            (json!(127), &[0x7f]),
+            (json!(128), &[0xcc, 0x80]),
+            (json!(255), &[0xcc, 0xff]),
+            (json!(256), &[0xcd, 0x01, 0x00]),
+            (json!(65_535), &[0xcd, 0xff, 0xff]),
+            (json!(65_536), &[0xce, 0x00, 0x01, 0x00, 0x00]),
+            (json!(u32::MAX), &[0xce, 0xff, 0xff, 0xff, 0xff]),
+            (json!(u32::MAX as u64 + 1), &[0xcf, 0, 0, 0, 1, 0, 0, 0, 0]),
+            (
+                json!(u64::MAX),
+                &[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            ),
+            (json!(-1), &[0xff]),
+            (json!(-32), &[0xe0]),
+        ];
+        for (value, bytes) in cases {
+            assert_eq!(round_trip(value), bytes);
+        }
+
+        let cases: [(Value, &[u8]); 8] = [
+            (json!(-33), &[0xd0, 0xdf]),
+            (json!(-128), &[0xd0, 0x80]),
+            (json!(-129), &[0xd1, 0xff, 0x7f]),
+            (json!(-32_768), &[0xd1, 0x80, 0x00]),
+            (json!(-32_769), &[0xd2, 0xff, 0xff, 0x7f, 0xff]),
+            (json!(i32::MIN), &[0xd2, 0x80, 0x00, 0x00, 0x00]),
+            (
+                json!(i32::MIN as i64 - 1),
+                &[0xd3, 0xff, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff],
+            ),
+            (json!(i64::MIN), &[0xd3, 0x80, 0, 0, 0, 0, 0, 0, 0]),
+        ];
+        for (value, bytes) in cases {
+            assert_eq!(round_trip(value), bytes);
+        }
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(
+            round_trip(json!(1.5)),
+            [0xcb, 0x3f, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        round_trip(json!(-2.5e-300));
+        round_trip(json!(f64::MAX));
+
+        assert_eq!(decode(&[0xca, 0x3f, 0xc0, 0x00, 0x00]).unwrap(), json!(1.5));
+        // NaN and infinity have no JSON representation.
+        assert_eq!(
+            decode(&[0xca, 0x7f, 0x80, 0x00, 0x00]).unwrap(),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        for (len, head) in [
+            (0, vec![0xa0]),
+            (31, vec![0xbf]),
+            (32, vec![0xd9, 32]),
+            (255, vec![0xd9, 0xff]),
+            (256, vec![0xda, 0x01, 0x00]),
+            (65_535, vec![0xda, 0xff, 0xff]),
+            (65_536, vec![0xdb, 0x00, 0x01, 0x00, 0x00]),
+        ] {
+            let bytes = round_trip(Value::String("x".repeat(len)));
+            assert_eq!(bytes[..head.len()], head);
+            assert_eq!(bytes.len(), head.len() + len);
+        }
+        round_trip(json!("Grüß Gott 👋"));
+    }
+
+    #[test]
+    fn test_arrays_and_maps() {
+        for (len, array_head, map_head) in [
+            (0, vec![0x90], vec![0x80]),
+            (15, vec![0x9f], vec![0x8f]),
+            (16, vec![0xdc, 0x00, 0x10], vec![0xde, 0x00, 0x10]),
+            (65_536, vec![0xdd, 0, 1, 0, 0], vec![0xdf, 0, 1, 0, 0]),
+        ] {
+            let bytes = round_trip(Value::Array(vec![Value::Null; len]));
+            assert_eq!(bytes[..array_head.len()], array_head);
+
+            let map = (0..len)
+                .map(|n| (n.to_string(), Value::Bool(true)))
+                .collect::<Map<_, _>>();
+            let bytes = round_trip(Value::Object(map));
+            assert_eq!(bytes[..map_head.len()], map_head);
+        }
+    }
+
+    #[test]
+    fn test_nested() {
+        round_trip(json!({
+            "id": 42,
+            "tags": ["a", "b", []],
+            "owner": { "name": "x".repeat(300), "roles": [{ "admin": true }, null] },
+            "score": -0.25,
+        }));
+    }
+
+    #[test]
+    fn test_decode_binary() {
+        assert_eq!(
+            decode(&[0xc4, 0x03, 1, 2, 255]).unwrap(),
+            json!([1, 2, 255])
+        );
+        assert_eq!(decode(&[0xc5, 0x00, 0x01, 7]).unwrap(), json!([7]));
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // Truncated, including lengths exceeding the input.
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[0xcd, 0x01]).is_err());
+        assert!(decode(&[0xa3, b'a', b'b']).is_err());
+        assert!(decode(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode(&[0x92, 0x01]).is_err());
+
+        assert!(decode(&[0x01, 0x02]).is_err());
+        assert!(decode(&[0xc1]).is_err());
+        // Extension types.
+        assert!(decode(&[0xd4, 0x01, 0x00]).is_err());
+        assert!(decode(&[0xa2, 0xff, 0xfe]).is_err());
+        assert!(decode(&[0x81, 0x01, 0x02]).is_err());
+
+        let mut nested = vec![0x91; MAX_DEPTH + 1];
+        nested.push(0xc0);
+        assert!(decode(&nested).is_err());
+        assert!(decode(&nested[1..]).is_ok());
+    }
+}
@@ -0,0 +1,47 @@
+use crate::error::Problem;
+use crate::request_id::RequestId;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use futures_util::FutureExt;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use tracing::error;
+
+const X_REQUEST_ID: &str = "x-request-id";
+
+/// Middleware catching panics of inner services, e.g. handlers, logging the panic message with
+/// the request ID and responding with a 500 [Problem] instead of aborting the connection.
+pub async fn catch_panic<B>(request: Request<B>, next: Next<B>) -> Response {
+    // Taken before, because the request is gone once the inner service has panicked.
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(RequestId::header_value)
+        .or_else(|| request.headers().get(X_REQUEST_ID))
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+
+    match AssertUnwindSafe(next.run(request)).catch_unwind().await {
+        Ok(response) => response,
+        Err(panic) => {
+            error!(
+                message = "Panic handling request",
+                panic = panic_message(&panic),
+                request_id = request_id.as_str()
+            );
+            Problem::new(StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    }
+}
+
+pub(crate) fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
@@ -0,0 +1,201 @@
+//! Forwarding of requests to upstreams by path prefix as configured in `proxy`, e.g. to front a
+//! legacy backend while migrating its endpoints one by one: requests to `/legacy/users` are
+//! forwarded to `http://legacy:8080/api/users` for the prefix `/legacy` and the upstream
+//! `http://legacy:8080/api`. Like static files, proxied requests bypass the route middleware like
+//! API keys, because the upstream is expected to do its own authentication.
+//!
+//! Bodies are streamed in both directions; request bodies are limited to `server.max_body_size`
+//! while being streamed, answering with 413 once it is exceeded. Hop-by-hop headers are removed,
+//! `Host` is set to the upstream authority unless `preserve_host` is enabled, and
+//! `X-Forwarded-For`, `-Host` and `-Proto` are set, the latter two kept if sent by a trusted
+//! proxy. Request ID and trace context are propagated by the [HttpClient]; failures are answered
+//! with 502.
+
+use crate::cidr::{canonical, Cidr};
+use crate::client_ip::ClientIp;
+use crate::error::Error;
+use crate::http_client::HttpClient;
+use crate::limit::{self, limited};
+use crate::settings::ProxySettings;
+use axum::body::{boxed, Body};
+use axum::extract::{ConnectInfo, OriginalUri};
+use axum::http::header::{HeaderName, CONNECTION, HOST, TE, TRAILER, TRANSFER_ENCODING, UPGRADE};
+use axum::http::uri::{Authority, Uri};
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::response::{IntoResponse, Response};
+use axum::routing::any;
+use axum::Router;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// Forwards requests to one upstream.
+#[derive(Debug)]
+pub(crate) struct Proxy {
+    settings: ProxySettings,
+    /// The upstream URL without trailing slash.
+    upstream: String,
+    authority: Authority,
+    trusted_proxies: Arc<Vec<Cidr>>,
+    http_client: HttpClient,
+}
+
+/// Mount the configured proxies under their path prefixes. Invalid upstream URLs are rejected when
+/// validating the settings.
+pub fn mount(
+    app: Router,
+    proxies: &BTreeMap<String, ProxySettings>,
+    trusted_proxies: &[Cidr],
+    max_body_size: usize,
+    http_client: &HttpClient,
+) -> Router {
+    let trusted_proxies = Arc::new(trusted_proxies.to_vec());
+    proxies.iter().fold(app, |app, (prefix, settings)| {
+        let proxy = match Proxy::new(settings, trusted_proxies.clone(), http_client.clone()) {
+            Some(proxy) => Arc::new(proxy),
+            None => return app,
+        };
+        let service = any(move |request: Request<Body>| {
+            let proxy = proxy.clone();
+            async move {
+                let exceeded = Arc::new(AtomicBool::default());
+                let request = request.map(|body| limited(body, max_body_size, exceeded.clone()));
+                let response = proxy.forward(request).await;
+                if exceeded.load(Ordering::Acquire) {
+                    limit::payload_too_large(max_body_size)
+                } else {
+                    response
+                }
+            }
+        });
+        app.nest(prefix.trim_end_matches('/'), service)
+    })
+}
+
+impl Proxy {
+    /// The proxy for the given settings, if the upstream is a valid URL.
+    pub(crate) fn new(
+        settings: &ProxySettings,
+        trusted_proxies: Arc<Vec<Cidr>>,
+        http_client: HttpClient,
+    ) -> Option<Self> {
+        let authority = settings
+            .upstream
+            .parse::<Uri>()
+            .ok()
+            .and_then(|upstream| upstream.authority().cloned())?;
+        Some(Self {
+            settings: settings.clone(),
+            upstream: settings.upstream.trim_end_matches('/').to_owned(),
+            authority,
+            trusted_proxies,
+            http_client,
+        })
+    }
+
+    /// Forward the given request to the upstream and its response back.
+    pub(crate) async fn forward(&self, request: Request<Body>) -> Response {
+        let (mut parts, body) = request.into_parts();
+
+        // Nesting has already stripped the prefix from the URI.
+        let uri = if self.settings.strip_prefix {
+            &parts.uri
+        } else {
+            parts
+                .extensions
+                .get::<OriginalUri>()
+                .map_or(&parts.uri, |OriginalUri(uri)| uri)
+        };
+        let path_and_query = uri.path_and_query().map_or("/", |path| path.as_str());
+        let uri = match format!("{}{path_and_query}", self.upstream).parse::<Uri>() {
+            Ok(uri) => uri,
+            Err(e) => {
+                return Error::Validation(format!("Cannot forward request: {e}")).into_response()
+            }
+        };
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| canonical(addr.ip()));
+        let trusted = peer.map_or(false, |peer| {
+            self.trusted_proxies.iter().any(|cidr| cidr.contains(peer))
+        });
+        let client_ip = parts.extensions.get::<ClientIp>().map(|ClientIp(ip)| *ip);
+
+        let headers = &mut parts.headers;
+        remove_hop_by_hop(headers);
+        if !trusted || !headers.contains_key(X_FORWARDED_HOST) {
+            if let Some(host) = headers.get(HOST).cloned() {
+                headers.insert(X_FORWARDED_HOST, host);
+            }
+        }
+        if !trusted || !headers.contains_key(X_FORWARDED_PROTO) {
+            headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("http"));
+        }
+        // Only the resolved client address, because untrusted clients could send anything.
+        headers.remove(X_FORWARDED_FOR);
+        if let Some(value) = client_ip.and_then(|ip| HeaderValue::from_str(&ip.to_string()).ok()) {
+            headers.insert(X_FORWARDED_FOR, value);
+        }
+        if !self.settings.preserve_host {
+            if let Ok(host) = HeaderValue::from_str(self.authority.as_str()) {
+                headers.insert(HOST, host);
+            }
+        }
+
+        let mut request = Request::new(body);
+        *request.method_mut() = parts.method;
+        *request.uri_mut() = uri;
+        *request.headers_mut() = parts.headers;
+
+        match self.http_client.forward(request).await {
+            Ok(response) => {
+                let (mut parts, body) = response.into_parts();
+                remove_hop_by_hop(&mut parts.headers);
+                Response::from_parts(parts, boxed(body))
+            }
+            Err(e) => {
+                let error = format!("{e:#}");
+                warn!(
+                    upstream = self.upstream.as_str(),
+                    error = error.as_str(),
+                    "Cannot forward request"
+                );
+                Error::BadGateway("Upstream not available".to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// Remove the hop-by-hop headers (RFC 7230, section 6.1), including the ones listed in
+/// `Connection`.
+pub(crate) fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in [CONNECTION, TE, TRAILER, TRANSFER_ENCODING, UPGRADE] {
+        headers.remove(name);
+    }
+    for name in [
+        "keep-alive",
+        "proxy-authenticate",
+        "proxy-authorization",
+        "proxy-connection",
+    ] {
+        headers.remove(name);
+    }
+}
@@ -0,0 +1,279 @@
+//! Decoding of the PROXY protocol header (version 1 and 2) which TCP load balancers like HAProxy
+//! or AWS NLB send at the beginning of a connection to convey the original source address.
+
+use anyhow::{bail, Context, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Maximum length of a version 1 header including the trailing CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Read the PROXY protocol header from the given stream, consuming exactly its bytes, and return
+/// the source address, if any: it is not for health checks of the load balancer itself, i.e. the
+/// `LOCAL` command or the `UNKNOWN` protocol, nor for address families other than TCP over IPv4
+/// or IPv6.
+pub async fn read_header<S>(stream: &mut S) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut first = [0; 1];
+    stream
+        .read_exact(&mut first)
+        .await
+        .context("Cannot read PROXY protocol header")?;
+    match first[0] {
+        b'P' => read_v1(stream).await,
+        b'\r' => read_v2(stream).await,
+        _ => bail!("Missing PROXY protocol header"),
+    }
+}
+
+/// Version 1, e.g. `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`, after the leading `P`.
+async fn read_v1<S>(stream: &mut S) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    // Read byte by byte to not consume any data after the header.
+    let mut line = vec![b'P'];
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            bail!("PROXY protocol header too long");
+        }
+        let mut byte = [0; 1];
+        stream
+            .read_exact(&mut byte)
+            .await
+            .context("Cannot read PROXY protocol header")?;
+        line.push(byte[0]);
+    }
+
+    let line =
+        std::str::from_utf8(&line[..line.len() - 2]).context("Invalid PROXY protocol header")?;
+    let fields = line.split(' ').collect::<Vec<_>>();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip = source
+                .parse::<IpAddr>()
+                .context("Invalid source address in PROXY protocol header")?;
+            let port = source_port
+                .parse::<u16>()
+                .context("Invalid source port in PROXY protocol header")?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => bail!("Invalid PROXY protocol header"),
+    }
+}
+
+/// Version 2, the binary format, after the leading `\r`.
+async fn read_v2<S>(stream: &mut S) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0; 15];
+    stream
+        .read_exact(&mut header)
+        .await
+        .context("Cannot read PROXY protocol header")?;
+    if header[..11] != V2_SIGNATURE[1..] {
+        bail!("Invalid PROXY protocol signature");
+    }
+    let version_command = header[11];
+    let family = header[12];
+    let len = u16::from_be_bytes([header[13], header[14]]) as usize;
+    if version_command >> 4 != 2 {
+        bail!("Unsupported PROXY protocol version");
+    }
+
+    // The address block may be followed by TLVs, which are skipped.
+    let mut addresses = vec![0; len];
+    stream
+        .read_exact(&mut addresses)
+        .await
+        .context("Cannot read PROXY protocol addresses")?;
+
+    match (version_command & 0x0f, family) {
+        // LOCAL
+        (0x0, _) => Ok(None),
+        // PROXY, TCP over IPv4
+        (0x1, 0x11) if len >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // PROXY, TCP over IPv6
+        (0x1, 0x21) if len >= 36 => {
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        (0x1, 0x11 | 0x21) => bail!("PROXY protocol addresses too short"),
+        (0x1, _) => Ok(None),
+        _ => bail!("Invalid PROXY protocol command"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The source address of the header at the start of the given bytes, and the bytes left.
+    async fn read(bytes: &[u8]) -> (Result<Option<SocketAddr>>, &[u8]) {
+        let mut stream = bytes;
+        let source = read_header(&mut stream).await;
+        (source, stream)
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn test_v1() {
+        let (source, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /").await;
+        assert_eq!(source.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let (source, rest) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").await;
+        assert_eq!(
+            source.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert!(rest.is_empty());
+
+        let (source, rest) = read(b"PROXY UNKNOWN\r\nGET /").await;
+        assert_eq!(source.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+
+        let (source, _) =
+            read(b"PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n").await;
+        assert_eq!(source.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_v1_invalid() {
+        let invalid: [&[u8]; 6] = [
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+            b"PROXY TCP4 192.0.2.256 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 65536 443\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"PROXY\tTCP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"GET / HTTP/1.1\r\n",
+        ];
+        for bytes in invalid {
+            assert!(read(bytes).await.0.is_err());
+        }
+
+        // Truncated.
+        assert!(read(b"PROXY TCP4 192.0.2.1 198.51.100.1 5632")
+            .await
+            .0
+            .is_err());
+        assert!(read(b"").await.0.is_err());
+
+        // The longest valid header has 107 bytes, longer ones are not read any further.
+        let mut line = b"PROXY UNKNOWN ".to_vec();
+        line.resize(V1_MAX_LEN - 2, b'x');
+        line.extend_from_slice(b"\r\n");
+        assert_eq!(read(&line).await.0.unwrap(), None);
+
+        let mut line = b"PROXY UNKNOWN ".to_vec();
+        line.resize(V1_MAX_LEN - 1, b'x');
+        line.extend_from_slice(b"\r\n");
+        let (source, rest) = read(&line).await;
+        assert_eq!(
+            source.unwrap_err().to_string(),
+            "PROXY protocol header too long"
+        );
+        assert_eq!(rest, b"\n");
+    }
+
+    #[tokio::test]
+    async fn test_v2() {
+        let addresses = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        let mut bytes = v2(0x1, 0x11, &addresses);
+        bytes.extend_from_slice(b"GET /");
+        let (source, rest) = read(&bytes).await;
+        assert_eq!(source.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let mut addresses = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        addresses.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        addresses.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+        let (source, _) = read(&v2(0x1, 0x21, &addresses)).await;
+        assert_eq!(
+            source.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+
+        // TLVs after the addresses are skipped.
+        let mut addresses = vec![192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        addresses.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        let mut bytes = v2(0x1, 0x11, &addresses);
+        bytes.extend_from_slice(b"GET /");
+        let (source, rest) = read(&bytes).await;
+        assert_eq!(source.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        // LOCAL, e.g. health checks of the load balancer, and other families have no source.
+        let mut bytes = v2(0x0, 0x00, &[]);
+        bytes.extend_from_slice(b"GET /");
+        let (source, rest) = read(&bytes).await;
+        assert_eq!(source.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+
+        let (source, _) = read(&v2(0x0, 0x11, &[0; 12])).await;
+        assert_eq!(source.unwrap(), None);
+
+        let (source, _) = read(&v2(0x1, 0x31, &[0; 216])).await;
+        assert_eq!(source.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_v2_invalid() {
+        let mut bytes = v2(0x1, 0x11, &[0; 12]);
+        bytes[4] = b'X';
+        assert_eq!(
+            read(&bytes).await.0.unwrap_err().to_string(),
+            "Invalid PROXY protocol signature"
+        );
+
+        let mut bytes = v2(0x1, 0x11, &[0; 12]);
+        bytes[12] = 0x11;
+        assert_eq!(
+            read(&bytes).await.0.unwrap_err().to_string(),
+            "Unsupported PROXY protocol version"
+        );
+
+        assert_eq!(
+            read(&v2(0x2, 0x11, &[0; 12]))
+                .await
+                .0
+                .unwrap_err()
+                .to_string(),
+            "Invalid PROXY protocol command"
+        );
+
+        assert_eq!(
+            read(&v2(0x1, 0x21, &[0; 12]))
+                .await
+                .0
+                .unwrap_err()
+                .to_string(),
+            "PROXY protocol addresses too short"
+        );
+
+        // Truncated within the fixed header and within the addresses.
+        let bytes = v2(0x1, 0x11, &[0; 12]);
+        assert!(read(&bytes[..10]).await.0.is_err());
+        assert!(read(&bytes[..20]).await.0.is_err());
+    }
+}
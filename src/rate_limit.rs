@@ -0,0 +1,293 @@
+//! Rate limiting with one token bucket per key, e.g. per API key or per client IP address.
+//!
+//! The limits of [limit_rate] are reloaded on SIGHUP, see [apply_limit_changes].
+
+use crate::client_ip::client_ip;
+use crate::error::Error;
+use crate::settings::{RateLimitSettings, RateLimitingSettings, Settings};
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::info;
+
+/// How often buckets which have been refilled completely, i.e. which are equivalent to absent
+/// ones, are removed.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token buckets by key, each holding up to `burst` tokens and refilled with `per_second` tokens
+/// per second.
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    state: Mutex<State<K>>,
+}
+
+#[derive(Debug)]
+struct State<K> {
+    buckets: HashMap<K, Bucket>,
+    pruned: Instant,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full: Instant,
+}
+
+impl<K> Default for RateLimiter<K> {
+    fn default() -> Self {
+        let state = State {
+            buckets: Default::default(),
+            pruned: Instant::now(),
+        };
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+}
+
+impl<K> RateLimiter<K>
+where
+    K: Hash + Eq,
+{
+    /// Take a token from the bucket for the given key or, if there is none left, return how long
+    /// to wait for the next one.
+    pub fn check(&self, key: K, limit: &RateLimitSettings) -> Result<(), Duration> {
+        let now = Instant::now();
+        let burst = limit.burst.max(1) as f64;
+        let per_second = limit.per_second.max(1) as f64;
+
+        let mut state = self.state.lock().expect("state can be locked");
+        if now.duration_since(state.pruned) >= PRUNE_INTERVAL {
+            state.buckets.retain(|_, bucket| bucket.full > now);
+            state.pruned = now;
+        }
+
+        let bucket = state.buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            full: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(burst);
+        bucket.updated = now;
+
+        let result = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        };
+        bucket.full = now + Duration::from_secs_f64((burst - bucket.tokens) / per_second);
+        result
+    }
+}
+
+/// Shared state of the [limit_rate] middleware, cheap to clone, all clones share the limits and
+/// the buckets.
+#[derive(Debug, Clone)]
+pub struct RouteRateLimits {
+    settings: Arc<RwLock<RateLimitingSettings>>,
+    /// Keyed by client IP address, if known, see [limit_rate].
+    global_limiter: Arc<RateLimiter<Option<IpAddr>>>,
+    route_limiter: Arc<RateLimiter<(String, Option<IpAddr>)>>,
+}
+
+impl RouteRateLimits {
+    pub fn new(settings: &RateLimitingSettings) -> Self {
+        Self {
+            settings: Arc::new(RwLock::new(settings.clone())),
+            global_limiter: Default::default(),
+            route_limiter: Default::default(),
+        }
+    }
+
+    /// Replace the limits; the buckets are kept, such that clients cannot reset them by
+    /// triggering a reload.
+    pub fn set(&self, settings: &RateLimitingSettings) {
+        *self.settings.write().expect("settings can be locked") = settings.clone();
+    }
+
+    /// The global limit and the one for the given route pattern, if any.
+    fn limits(
+        &self,
+        route: Option<&str>,
+    ) -> (Option<RateLimitSettings>, Option<RateLimitSettings>) {
+        let settings = self.settings.read().expect("settings can be locked");
+        let route_limit = route.and_then(|route| settings.routes.get(route)).cloned();
+        (settings.global.clone(), route_limit)
+    }
+}
+
+/// Apply changed `rate_limiting` settings received via the given receiver, see
+/// [crate::config_watcher].
+pub async fn apply_limit_changes(
+    mut settings_rx: watch::Receiver<Settings>,
+    limits: RouteRateLimits,
+) {
+    loop {
+        let changed = settings_rx.borrow_and_update().rate_limiting.clone();
+        if changed != *limits.settings.read().expect("settings can be locked") {
+            info!("Applying changed rate limits");
+            limits.set(&changed);
+        }
+        if settings_rx.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Middleware rejecting requests exceeding the global rate limit or the one for their route
+/// pattern, e.g. `/users/:id`, with 429. Requests are limited per client IP address; requests
+/// without a known client address, e.g. via the Unix domain socket, share one bucket, such that
+/// they cannot bypass the limits.
+pub async fn limit_rate<B>(
+    request: Request<B>,
+    next: Next<B>,
+    limits: RouteRateLimits,
+) -> Response {
+    let ip = client_ip(&request);
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|route| route.as_str().to_owned());
+    let (global, route_limit) = limits.limits(route.as_deref());
+    if let Some(global) = &global {
+        if let Err(retry_after) = limits.global_limiter.check(ip, global) {
+            return Error::TooManyRequests(retry_after).into_response();
+        }
+    }
+    if let (Some(route), Some(limit)) = (route, &route_limit) {
+        if let Err(retry_after) = limits.route_limiter.check((route, ip), limit) {
+            return Error::TooManyRequests(retry_after).into_response();
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_ip::ClientIp;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tokio::time::sleep;
+    use tower::ServiceExt;
+
+    fn limit(per_second: u32, burst: u32) -> RateLimitSettings {
+        RateLimitSettings { per_second, burst }
+    }
+
+    #[tokio::test]
+    async fn test_apply_limit_changes() {
+        let settings = Settings::default();
+        let (settings_tx, settings_rx) = watch::channel(settings.clone());
+        let limits = RouteRateLimits::new(&settings.rate_limiting);
+        tokio::spawn(apply_limit_changes(settings_rx, limits.clone()));
+        assert_eq!(limits.limits(Some("/users/:id")), (None, None));
+
+        let mut changed = settings;
+        changed.rate_limiting.global = Some(limit(10, 20));
+        changed
+            .rate_limiting
+            .routes
+            .insert("/users/:id".to_string(), limit(1, 2));
+        settings_tx.send_replace(changed);
+        for _ in 0..100 {
+            if limits.limits(None).0.is_some() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            limits.limits(Some("/users/:id")),
+            (Some(limit(10, 20)), Some(limit(1, 2)))
+        );
+        assert_eq!(limits.limits(Some("/orders")), (Some(limit(10, 20)), None));
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let limiter = RateLimiter::default();
+        let limit = limit(20, 3);
+
+        // Up to burst requests at once, then one per 1/per_second.
+        for _ in 0..3 {
+            assert_eq!(limiter.check("a", &limit), Ok(()));
+        }
+        let retry_after = limiter.check("a", &limit).unwrap_err();
+        assert!(retry_after <= Duration::from_millis(50), "{retry_after:?}");
+
+        // Keys have their own buckets.
+        assert_eq!(limiter.check("b", &limit), Ok(()));
+
+        sleep(Duration::from_millis(60)).await;
+        assert_eq!(limiter.check("a", &limit), Ok(()));
+        assert!(limiter.check("a", &limit).is_err());
+
+        // Refilled up to burst only.
+        sleep(Duration::from_millis(300)).await;
+        for _ in 0..3 {
+            assert_eq!(limiter.check("a", &limit), Ok(()));
+        }
+        assert!(limiter.check("a", &limit).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limit_rate() {
+        let mut settings = RateLimitingSettings {
+            global: Some(limit(1, 3)),
+            ..Default::default()
+        };
+        settings
+            .routes
+            .insert("/users/:id".to_string(), limit(1, 1));
+        let limits = RouteRateLimits::new(&settings);
+        let app = Router::new()
+            .route("/users/:id", get(|| async {}))
+            .route("/orders", get(|| async {}))
+            .layer(middleware::from_fn(move |request, next| {
+                limit_rate(request, next, limits.clone())
+            }));
+        let status = |path: &str, ip: Option<&str>| {
+            let mut request = Request::get(path).body(Body::empty()).unwrap();
+            if let Some(ip) = ip {
+                request
+                    .extensions_mut()
+                    .insert(ClientIp(ip.parse().unwrap()));
+            }
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        // The route limit applies per route, the global one across routes.
+        assert_eq!(status("/users/1", Some("192.0.2.1")).await, StatusCode::OK);
+        assert_eq!(
+            status("/users/2", Some("192.0.2.1")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(status("/orders", Some("192.0.2.1")).await, StatusCode::OK);
+        assert_eq!(
+            status("/orders", Some("192.0.2.1")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(status("/users/1", Some("192.0.2.2")).await, StatusCode::OK);
+
+        // Requests without client IP address share a bucket.
+        assert_eq!(status("/users/1", None).await, StatusCode::OK);
+        assert_eq!(
+            status("/users/1", None).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+}
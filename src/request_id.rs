@@ -0,0 +1,52 @@
+//! Request IDs, taken from an incoming `x-request-id` header or generated as UUIDs, stored in the
+//! request extensions as [RequestId], recorded on the request span and echoed in the response.
+
+use axum::http::{HeaderValue, Request};
+use std::fmt::Write;
+use tower_http::request_id::MakeRequestId;
+use tracing::Span;
+
+pub use tower_http::request_id::RequestId;
+
+/// Generates random (version 4) UUIDs as request IDs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MakeRequestUuid;
+
+impl MakeRequestId for MakeRequestUuid {
+    fn make_request_id<B>(&mut self, _request: &Request<B>) -> Option<RequestId> {
+        HeaderValue::from_str(&uuid_v4()).ok().map(RequestId::new)
+    }
+}
+
+/// Create the span for a request, including its request ID if any and the `tenant_id` recorded
+/// by [crate::tenancy].
+pub fn make_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id,
+        tenant_id = tracing::field::Empty,
+    )
+}
+
+fn uuid_v4() -> String {
+    let mut bytes = rand::random::<[u8; 16]>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let mut uuid = String::with_capacity(36);
+    for (n, byte) in bytes.iter().enumerate() {
+        if let 4 | 6 | 8 | 10 = n {
+            uuid.push('-');
+        }
+        let _ = write!(uuid, "{byte:02x}");
+    }
+    uuid
+}
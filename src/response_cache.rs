@@ -0,0 +1,363 @@
+//! In-process caching of `GET` responses for the routes configured in [ResponseCacheSettings],
+//! e.g. expensive read endpoints which tolerate slight staleness.
+//!
+//! Responses are cached per method, path and query, the configured `vary` request headers and,
+//! if any, API key, tenant and canary variant, such that clients never see each other's
+//! responses. Requests with `Authorization` or `Cookie` headers, e.g. of sessions, bypass the
+//! cache. Only `200` responses of known size are cached, and none with `Set-Cookie` or
+//! `Cache-Control: no-store` or `private`.
+//! Cached responses carry an `Age` header.
+
+use crate::api_key::ApiKeyId;
+use crate::canary::Variant;
+use crate::error::Error;
+use crate::settings::{ResponseCacheSettings, RouteCacheSettings};
+use crate::tenancy::TenantId;
+use axum::body::{boxed, Bytes, Full, HttpBody};
+use axum::extract::MatchedPath;
+use axum::http::header::{AGE, AUTHORIZATION, CACHE_CONTROL, COOKIE, SET_COOKIE};
+use axum::http::{HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Maximum size of cached response bodies.
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Cheap to clone, all clones share the cached responses.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    routes: Arc<BTreeMap<String, RouteCacheSettings>>,
+    max_entries: usize,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    route: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    created: Instant,
+    expires: Instant,
+}
+
+impl ResponseCache {
+    pub fn new(settings: &ResponseCacheSettings) -> Self {
+        Self {
+            routes: Arc::new(settings.routes.clone()),
+            max_entries: settings.max_entries,
+            entries: Default::default(),
+        }
+    }
+
+    /// Whether any routes are configured to be cached.
+    pub fn is_enabled(&self) -> bool {
+        !self.routes.is_empty()
+    }
+
+    /// Remove the cached responses for the given route pattern, e.g. `/users/:id`, or all if
+    /// none is given, returning the number of removed entries.
+    pub fn purge(&self, route: Option<&str>) -> usize {
+        let mut entries = self.entries();
+        let len = entries.len();
+        match route {
+            Some(route) => entries.retain(|_, entry| entry.route != route),
+            None => entries.clear(),
+        }
+        len - entries.len()
+    }
+
+    fn get(&self, key: &str) -> Option<Entry> {
+        let now = Instant::now();
+        let mut entries = self.entries();
+        match entries.get(key) {
+            Some(entry) if entry.expires > now => Some(entry.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Inserts the given entry, unless there are `max_entries` unexpired ones.
+    fn insert(&self, key: String, entry: Entry) {
+        let mut entries = self.entries();
+        if entries.len() >= self.max_entries {
+            let now = Instant::now();
+            entries.retain(|_, entry| entry.expires > now);
+        }
+        if entries.len() < self.max_entries {
+            entries.insert(key, entry);
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        self.entries.lock().expect("entries can be locked")
+    }
+}
+
+/// Middleware serving cached responses for the configured routes, see [crate::response_cache].
+pub async fn cache_response<B>(
+    request: Request<B>,
+    next: Next<B>,
+    cache: ResponseCache,
+) -> Response {
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(route) if request.method() == Method::GET && !has_credentials(&request) => {
+            route.as_str().to_owned()
+        }
+        _ => return next.run(request).await,
+    };
+    let settings = match cache.routes.get(&route) {
+        Some(settings) => settings.clone(),
+        None => return next.run(request).await,
+    };
+
+    let key = key(&request, &settings.vary);
+    if let Some(entry) = cache.get(&key) {
+        let mut response = Response::new(boxed(Full::from(entry.body)));
+        *response.status_mut() = entry.status;
+        *response.headers_mut() = entry.headers;
+        response
+            .headers_mut()
+            .insert(AGE, HeaderValue::from(entry.created.elapsed().as_secs()));
+        return response;
+    }
+
+    let response = next.run(request).await;
+    let cacheable = response.status() == StatusCode::OK
+        && !uncacheable(response.headers())
+        && response
+            .body()
+            .size_hint()
+            .exact()
+            .map_or(false, |size| size <= MAX_BODY_SIZE);
+    if !cacheable {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            let e = anyhow::anyhow!(e).context("Cannot read response body");
+            return Error::Internal(e).into_response();
+        }
+    };
+    let now = Instant::now();
+    let entry = Entry {
+        route,
+        status: parts.status,
+        headers: parts.headers.clone(),
+        body: body.clone(),
+        created: now,
+        expires: now + settings.ttl,
+    };
+    cache.insert(key, entry);
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+fn key<B>(request: &Request<B>, vary: &[String]) -> String {
+    let extensions = request.extensions();
+    let api_key_id = extensions
+        .get::<ApiKeyId>()
+        .map(|id| id.0.as_str())
+        .unwrap_or_default();
+    let tenant_id = extensions
+        .get::<TenantId>()
+        .map(TenantId::as_str)
+        .unwrap_or_default();
+    let variant = extensions
+        .get::<Variant>()
+        .map(Variant::as_str)
+        .unwrap_or_default();
+    let mut key = format!(
+        "{tenant_id}:{api_key_id}:{variant}:{}:{}",
+        request.method(),
+        request.uri()
+    );
+    for name in vary {
+        let values = request
+            .headers()
+            .get_all(name.as_str())
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect::<Vec<_>>();
+        key.push_str(&format!("\n{name}:{}", values.join(",")));
+    }
+    key
+}
+
+/// Whether the request carries credentials other than an API key, e.g. a session cookie, for
+/// which responses are likely personal.
+fn has_credentials<B>(request: &Request<B>) -> bool {
+    let headers = request.headers();
+    headers.contains_key(AUTHORIZATION) || headers.contains_key(COOKIE)
+}
+
+fn uncacheable(headers: &HeaderMap) -> bool {
+    headers.contains_key(SET_COOKIE)
+        || headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .any(|directive| {
+                directive.eq_ignore_ascii_case("no-store")
+                    || directive.eq_ignore_ascii_case("private")
+            })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::response::Headers;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(()).unwrap()
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_has_credentials() {
+        assert!(!has_credentials(&request("/", &[])));
+        assert!(!has_credentials(&request("/", &[("x-api-key", "secret")])));
+        assert!(has_credentials(&request(
+            "/",
+            &[("authorization", "Bearer abc")]
+        )));
+        assert!(has_credentials(&request("/", &[("cookie", "session=abc")])));
+    }
+
+    #[test]
+    fn test_uncacheable() {
+        assert!(!uncacheable(&headers(&[])));
+        assert!(!uncacheable(&headers(&[(
+            "cache-control",
+            "public, max-age=60"
+        )])));
+        assert!(uncacheable(&headers(&[("set-cookie", "session=abc")])));
+        assert!(uncacheable(&headers(&[("cache-control", "no-store")])));
+        assert!(uncacheable(&headers(&[(
+            "cache-control",
+            "max-age=60, No-Store"
+        )])));
+        assert!(uncacheable(&headers(&[("cache-control", "private")])));
+    }
+
+    #[test]
+    fn test_key() {
+        let vary = ["accept".to_string()];
+        let key = |request: &Request<()>| super::key(request, &vary);
+
+        let json = request("/items?page=1", &[("accept", "application/json")]);
+        let html = request("/items?page=1", &[("accept", "text/html")]);
+        assert_ne!(key(&json), key(&html));
+        assert_ne!(
+            key(&json),
+            key(&request("/items?page=2", &[("accept", "application/json")]))
+        );
+
+        // Headers not varied by are ignored.
+        let other = request(
+            "/items?page=1",
+            &[("accept", "application/json"), ("user-agent", "curl")],
+        );
+        assert_eq!(key(&json), key(&other));
+
+        let mut acme = request("/items?page=1", &[("accept", "application/json")]);
+        acme.extensions_mut()
+            .insert(TenantId::parse("acme").unwrap());
+        let mut other_tenant = request("/items?page=1", &[("accept", "application/json")]);
+        other_tenant
+            .extensions_mut()
+            .insert(TenantId::parse("other").unwrap());
+        assert_ne!(key(&acme), key(&json));
+        assert_ne!(key(&acme), key(&other_tenant));
+
+        let mut with_api_key = request("/items?page=1", &[("accept", "application/json")]);
+        with_api_key
+            .extensions_mut()
+            .insert(ApiKeyId("key1".to_string()));
+        assert_ne!(key(&with_api_key), key(&json));
+    }
+
+    #[tokio::test]
+    async fn test_cache_response() {
+        let calls = Arc::new(AtomicUsize::default());
+        let settings = ResponseCacheSettings {
+            routes: ["/ok", "/missing", "/cookie"]
+                .into_iter()
+                .map(|route| (route.to_string(), RouteCacheSettings::default()))
+                .collect(),
+            ..Default::default()
+        };
+        let cache = ResponseCache::new(&settings);
+        let handler = |response: fn() -> Response| {
+            let calls = calls.clone();
+            move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                response()
+            }
+        };
+        let app = Router::new()
+            .route("/ok", get(handler(|| "ok".into_response())))
+            .route(
+                "/missing",
+                get(handler(|| StatusCode::NOT_FOUND.into_response())),
+            )
+            .route(
+                "/cookie",
+                get(handler(|| {
+                    (Headers([(SET_COOKIE, "a=b")]), "ok").into_response()
+                })),
+            )
+            .layer(middleware::from_fn(move |request, next| {
+                cache_response(request, next, cache.clone())
+            }));
+
+        // Only the 200 response without Set-Cookie is cached.
+        for (path, cached) in [
+            ("/ok", false),
+            ("/ok", true),
+            ("/missing", false),
+            ("/missing", false),
+            ("/cookie", false),
+            ("/cookie", false),
+        ] {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.headers().contains_key(AGE), cached, "{path}");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+
+        // Requests with credentials bypass the cache.
+        let request = Request::get("/ok")
+            .header(COOKIE, "session=abc")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(!response.headers().contains_key(AGE));
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+    }
+}
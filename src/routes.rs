@@ -0,0 +1,41 @@
+//! Versioned API routes, nested under `/api/<version>`, with the handlers of each version in its
+//! own module.
+
+pub mod v1;
+pub mod v2;
+
+use axum::http::header::LINK;
+use axum::http::{HeaderValue, Request};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::Router;
+
+const DEPRECATION: &str = "deprecation";
+
+/// Routes for all API versions, where all but the latest are deprecated.
+pub fn routes() -> Router {
+    Router::new()
+        .nest(
+            "/api/v1",
+            v1::routes().layer(middleware::from_fn(|request, next| {
+                deprecated(request, next, "/api/v2")
+            })),
+        )
+        .nest("/api/v2", v2::routes())
+}
+
+/// Middleware adding a `Deprecation` header and a `Link` header pointing to the given successor
+/// version to responses.
+pub async fn deprecated<B>(
+    request: Request<B>,
+    next: Next<B>,
+    successor: &'static str,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(DEPRECATION, HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&format!(r#"<{successor}>; rel="successor-version""#)) {
+        headers.append(LINK, link);
+    }
+    response
+}
@@ -0,0 +1,12 @@
+//! Version 1 of the API, deprecated in favor of [super::v2].
+
+use axum::routing::get;
+use axum::Router;
+
+pub fn routes() -> Router {
+    Router::new().route("/greeting", get(greeting))
+}
+
+async fn greeting() -> &'static str {
+    "Habe die Ehre!"
+}
@@ -0,0 +1,20 @@
+//! Version 2 of the API, responding with JSON.
+
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+
+pub fn routes() -> Router {
+    Router::new().route("/greeting", get(greeting))
+}
+
+#[derive(Debug, Serialize)]
+struct Greeting {
+    greeting: &'static str,
+}
+
+async fn greeting() -> Json<Greeting> {
+    Json(Greeting {
+        greeting: "Habe die Ehre!",
+    })
+}
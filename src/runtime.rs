@@ -0,0 +1,35 @@
+//! The Tokio runtime, built explicitly instead of via `#[tokio::main]` such that it can be tuned
+//! via the `runtime` settings, e.g. fewer worker threads than cores for CPU-pinned deployments.
+
+use crate::settings::RuntimeSettings;
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::runtime::{Builder, Runtime};
+
+/// Build the multi-threaded runtime according to the given settings.
+pub fn build(settings: &RuntimeSettings) -> Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(worker_threads) = settings.worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    if let Some(max_blocking_threads) = settings.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads);
+    }
+    if let Some(thread_stack_size) = settings.thread_stack_size {
+        builder.thread_stack_size(thread_stack_size);
+    }
+    let prefix = settings.thread_name_prefix.clone();
+    let n = AtomicUsize::new(1);
+    builder.thread_name_fn(move || format!("{prefix}-{}", n.fetch_add(1, Ordering::Relaxed)));
+    builder.build().context("Cannot build runtime")
+}
+
+/// Build a single-threaded runtime for work before the actual one can be built, e.g. loading the
+/// settings, which may resolve secrets from Vault.
+pub fn bootstrap() -> Result<Runtime> {
+    Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Cannot build bootstrap runtime")
+}
@@ -0,0 +1,133 @@
+//! Background jobs run on cron schedules from the [SchedulerSettings].
+
+pub mod cron;
+
+use crate::log_error_chain;
+use crate::metrics::{increment_counter, set_gauge};
+use crate::settings::SchedulerSettings;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use cron::Schedule;
+use futures_util::future::join_all;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+/// A background job, run according to the schedule configured for its name.
+#[async_trait]
+pub trait Job: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    async fn run(&self) -> Result<()>;
+}
+
+/// Registered [Job]s, which are run once [started](Scheduler::start).
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Arc<dyn Job>>,
+}
+
+impl Scheduler {
+    pub fn register(&mut self, job: impl Job) {
+        self.jobs.push(Arc::new(job));
+    }
+
+    /// Run each registered [Job] on the schedule configured for its name, but never concurrently
+    /// with itself. Jobs without a configured schedule are not run.
+    ///
+    /// Each run records `job_runs_total` by job and outcome as well as the gauges
+    /// `job_last_run_timestamp_seconds` and `job_next_run_timestamp_seconds` by job.
+    pub fn start(self, settings: &SchedulerSettings) -> Result<SchedulerHandle> {
+        for name in settings.jobs.keys() {
+            if !self.jobs.iter().any(|job| job.name() == name) {
+                warn!(job = name.as_str(), "Schedule configured for unknown job");
+            }
+        }
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(());
+        let mut tasks = vec![];
+        for job in self.jobs {
+            let schedule = match settings.jobs.get(job.name()) {
+                Some(job_settings) => job_settings
+                    .schedule
+                    .parse::<Schedule>()
+                    .with_context(|| format!("Invalid schedule for job {}", job.name()))?,
+                None => {
+                    warn!(job = job.name(), "No schedule configured for job");
+                    continue;
+                }
+            };
+            tasks.push(tokio::spawn(run_job(job, schedule, shutdown_rx.clone())));
+        }
+
+        Ok(SchedulerHandle { shutdown_tx, tasks })
+    }
+}
+
+/// Handle for [shutting down](SchedulerHandle::shutdown) a started [Scheduler].
+pub struct SchedulerHandle {
+    shutdown_tx: watch::Sender<()>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl SchedulerHandle {
+    /// Stop scheduling jobs and wait for running ones to complete.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown_tx.send(());
+        join_all(self.tasks).await;
+        info!("Scheduler shut down");
+    }
+}
+
+async fn run_job(job: Arc<dyn Job>, schedule: Schedule, mut shutdown_rx: watch::Receiver<()>) {
+    let name = job.name();
+    loop {
+        let now = unix_secs(SystemTime::now());
+        let next = match schedule.next_after(now) {
+            Some(next) => next,
+            None => {
+                warn!(job = name, "Schedule never matches");
+                return;
+            }
+        };
+        set_gauge(
+            "job_next_run_timestamp_seconds",
+            &[("job", name.to_string())],
+            next as f64,
+        );
+        debug!(job = name, next, "Job scheduled");
+
+        let delay = UNIX_EPOCH + Duration::from_secs(next);
+        let delay = delay.duration_since(SystemTime::now()).unwrap_or_default();
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = shutdown_rx.changed() => return,
+        }
+
+        let outcome = match job.run().await {
+            Ok(()) => "success",
+            Err(e) => {
+                log_error_chain(&format!("Job {name} failed"), e.as_ref());
+                "failure"
+            }
+        };
+        increment_counter(
+            "job_runs_total",
+            &[("job", name.to_string()), ("outcome", outcome.to_string())],
+        );
+        set_gauge(
+            "job_last_run_timestamp_seconds",
+            &[("job", name.to_string())],
+            unix_secs(SystemTime::now()) as f64,
+        );
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
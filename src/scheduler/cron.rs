@@ -0,0 +1,325 @@
+//! Cron expressions with the five fields minute, hour, day of month, month and day of week, e.g.
+//! `*/15 8-18 * * 1-5`, evaluated in UTC.
+//!
+//! Fields support `*`, values, ranges like `1-5`, steps like `*/15` or `0-30/10` and lists of
+//! these like `0,30`. Days of week are 0 to 7, both 0 and 7 meaning Sunday. As usual, if both
+//! day of month and day of week are restricted, a day matching either of them matches.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::str::FromStr;
+
+const SECS_PER_MINUTE: u64 = 60;
+const SECS_PER_HOUR: u64 = 60 * SECS_PER_MINUTE;
+const SECS_PER_DAY: u64 = 24 * SECS_PER_HOUR;
+
+/// Searching for the next matching time gives up after this many days, which only happens for
+/// expressions which never match, e.g. `0 0 30 2 *`.
+const MAX_DAYS: u64 = 5 * 366;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    days_of_month_restricted: bool,
+    days_of_week_restricted: bool,
+}
+
+impl Schedule {
+    /// The first matching time after the given one, both in seconds since the Unix epoch.
+    pub fn next_after(&self, secs: u64) -> Option<u64> {
+        let mut secs = (secs / SECS_PER_MINUTE + 1) * SECS_PER_MINUTE;
+        let end = secs + MAX_DAYS * SECS_PER_DAY;
+
+        while secs < end {
+            let days = secs / SECS_PER_DAY;
+            let (_, month, day) = civil_from_days(days);
+            if !self.matches_day(month, day, days) {
+                secs = (days + 1) * SECS_PER_DAY;
+                continue;
+            }
+
+            let hour = secs % SECS_PER_DAY / SECS_PER_HOUR;
+            if !contains(self.hours, hour) {
+                secs = (secs / SECS_PER_HOUR + 1) * SECS_PER_HOUR;
+                continue;
+            }
+
+            let minute = secs % SECS_PER_HOUR / SECS_PER_MINUTE;
+            if !contains(self.minutes, minute) {
+                secs += SECS_PER_MINUTE;
+                continue;
+            }
+
+            return Some(secs);
+        }
+
+        None
+    }
+
+    fn matches_day(&self, month: u64, day: u64, days: u64) -> bool {
+        // 1970-01-01 was a Thursday.
+        let day_of_week = (days + 4) % 7;
+        let day_of_month = contains(self.days_of_month, day);
+        let day_of_week = contains(self.days_of_week, day_of_week);
+        let day = match (self.days_of_month_restricted, self.days_of_week_restricted) {
+            (true, true) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+        day && contains(self.months, month)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let (minutes, hours, days_of_month, months, days_of_week) = match fields[..] {
+            [minutes, hours, days_of_month, months, days_of_week] => {
+                (minutes, hours, days_of_month, months, days_of_week)
+            }
+            _ => bail!("Cron expression {s} must have five fields"),
+        };
+
+        let mut days_of_week_bits = parse_field(days_of_week, 0, 7)
+            .with_context(|| format!("Invalid day of week in cron expression {s}"))?;
+        if contains(days_of_week_bits, 7) {
+            days_of_week_bits |= 1;
+        }
+
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)
+                .with_context(|| format!("Invalid minute in cron expression {s}"))?,
+            hours: parse_field(hours, 0, 23)
+                .with_context(|| format!("Invalid hour in cron expression {s}"))?,
+            days_of_month: parse_field(days_of_month, 1, 31)
+                .with_context(|| format!("Invalid day of month in cron expression {s}"))?,
+            months: parse_field(months, 1, 12)
+                .with_context(|| format!("Invalid month in cron expression {s}"))?,
+            days_of_week: days_of_week_bits,
+            days_of_month_restricted: !days_of_month.starts_with('*'),
+            days_of_week_restricted: !days_of_week.starts_with('*'),
+        })
+    }
+}
+
+/// Parse a field into a bit set of the matching values within `min` and `max`.
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64> {
+    field.split(',').try_fold(0, |bits, part| {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("Step must not be zero");
+        }
+
+        let (first, last) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((first, last)) => (first.parse()?, last.parse()?),
+                None => {
+                    let value = range.parse()?;
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if first < min || last > max || first > last {
+            return Err(anyhow!("{part} is not within {min} and {max}"));
+        }
+
+        Ok((first..=last)
+            .step_by(step as usize)
+            .fold(bits, |bits, value| bits | 1 << value))
+    })
+}
+
+fn contains(bits: u64, value: u64) -> bool {
+    bits & 1 << value != 0
+}
+
+/// Year, month (1 to 12) and day (1 to 31) for the given days since the Unix epoch, see
+/// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+pub(crate) fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seconds since the Unix epoch for the given days since the epoch, hour and minute.
+    fn secs(days: u64, hour: u64, minute: u64) -> u64 {
+        days * SECS_PER_DAY + hour * SECS_PER_HOUR + minute * SECS_PER_MINUTE
+    }
+
+    fn bits(values: &[u64]) -> u64 {
+        values.iter().fold(0, |bits, value| bits | 1 << value)
+    }
+
+    #[test]
+    fn test_parse_field() {
+        assert_eq!(parse_field("*", 0, 5).unwrap(), bits(&[0, 1, 2, 3, 4, 5]));
+        assert_eq!(parse_field("7", 0, 59).unwrap(), bits(&[7]));
+        assert_eq!(parse_field("1-3", 0, 59).unwrap(), bits(&[1, 2, 3]));
+        assert_eq!(parse_field("*/15", 0, 59).unwrap(), bits(&[0, 15, 30, 45]));
+        assert_eq!(
+            parse_field("0-30/10", 0, 59).unwrap(),
+            bits(&[0, 10, 20, 30])
+        );
+        assert_eq!(parse_field("5/20", 0, 59).unwrap(), bits(&[5, 25, 45]));
+        assert_eq!(parse_field("*/5", 1, 12).unwrap(), bits(&[1, 6, 11]));
+        assert_eq!(
+            parse_field("0,30,45-46", 0, 59).unwrap(),
+            bits(&[0, 30, 45, 46])
+        );
+        assert_eq!(parse_field("0,0,0-1", 0, 59).unwrap(), bits(&[0, 1]));
+    }
+
+    #[test]
+    fn test_parse_field_invalid() {
+        assert!(parse_field("60", 0, 59).is_err());
+        assert!(parse_field("0", 1, 31).is_err());
+        assert!(parse_field("5-1", 0, 59).is_err());
+        assert!(parse_field("1-60", 0, 59).is_err());
+        assert!(parse_field("*/0", 0, 59).is_err());
+        assert!(parse_field("*/x", 0, 59).is_err());
+        assert!(parse_field("a", 0, 59).is_err());
+        assert!(parse_field("-1", 0, 59).is_err());
+        assert!(parse_field("1-2-3", 0, 59).is_err());
+        assert!(parse_field("1,", 0, 59).is_err());
+        assert!(parse_field("", 0, 59).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("".parse::<Schedule>().is_err());
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("* * * * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("* 24 * * *".parse::<Schedule>().is_err());
+        assert!("* * 0 * *".parse::<Schedule>().is_err());
+        assert!("* * 32 * *".parse::<Schedule>().is_err());
+        assert!("* * * 0 *".parse::<Schedule>().is_err());
+        assert!("* * * 13 *".parse::<Schedule>().is_err());
+        assert!("* * * * 8".parse::<Schedule>().is_err());
+        assert!("* * * JAN *".parse::<Schedule>().is_err());
+
+        let error = "0 0 * 13 *".parse::<Schedule>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid month in cron expression 0 0 * 13 *"
+        );
+    }
+
+    #[test]
+    fn test_parse_sunday() {
+        // Saturday 2024-06-15 to Sunday 2024-06-16.
+        for expression in ["0 0 * * 0", "0 0 * * 7"] {
+            let schedule = expression.parse::<Schedule>().unwrap();
+            assert_eq!(
+                schedule.next_after(secs(19_889, 13, 0)),
+                Some(secs(19_890, 0, 0))
+            );
+        }
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(58), (1970, 2, 28));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        // 2000 is a leap year, because it is divisible by 400.
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(20_088), (2024, 12, 31));
+        assert_eq!(civil_from_days(20_089), (2025, 1, 1));
+        // 2100 is no leap year, because it is divisible by 100 but not by 400.
+        assert_eq!(civil_from_days(47_540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn test_next_after() {
+        let schedule = "*/15 * * * *".parse::<Schedule>().unwrap();
+        assert_eq!(schedule.next_after(0), Some(secs(0, 0, 15)));
+        assert_eq!(
+            schedule.next_after(secs(0, 0, 14) + 59),
+            Some(secs(0, 0, 15))
+        );
+        // Strictly after the given time.
+        assert_eq!(schedule.next_after(secs(0, 0, 15)), Some(secs(0, 0, 30)));
+        assert_eq!(schedule.next_after(secs(0, 23, 50)), Some(secs(1, 0, 0)));
+
+        // Month boundary: 2024-01-31 to 2024-02-01.
+        let schedule = "0 0 1 * *".parse::<Schedule>().unwrap();
+        assert_eq!(
+            schedule.next_after(secs(19_753, 12, 0)),
+            Some(secs(19_754, 0, 0))
+        );
+
+        // Year boundary: 2024-12-31 to 2025-01-01.
+        let schedule = "0 0 1 1 *".parse::<Schedule>().unwrap();
+        assert_eq!(
+            schedule.next_after(secs(20_088, 23, 59)),
+            Some(secs(20_089, 0, 0))
+        );
+
+        // Leap days: 2023-03-01 to 2024-02-29, and 2099-03-01 to 2104-02-29, skipping 2100.
+        let schedule = "30 6 29 2 *".parse::<Schedule>().unwrap();
+        assert_eq!(
+            schedule.next_after(secs(19_417, 0, 0)),
+            Some(secs(19_782, 6, 30))
+        );
+        assert_eq!(
+            schedule.next_after(secs(47_176, 0, 0)),
+            Some(secs(49_001, 6, 30))
+        );
+
+        // Weekdays: Saturday 2024-06-15 to Monday 2024-06-17.
+        let schedule = "0 12 * * 1-5".parse::<Schedule>().unwrap();
+        assert_eq!(
+            schedule.next_after(secs(19_889, 13, 0)),
+            Some(secs(19_891, 12, 0))
+        );
+    }
+
+    #[test]
+    fn test_next_after_day_of_month_or_week() {
+        // Sunday 2024-09-01: Friday 2024-09-06 matches the day of week, 2024-09-13 both.
+        let schedule = "0 0 13 * 5".parse::<Schedule>().unwrap();
+        assert_eq!(
+            schedule.next_after(secs(19_967, 0, 0)),
+            Some(secs(19_972, 0, 0))
+        );
+        assert_eq!(
+            schedule.next_after(secs(19_972, 0, 0)),
+            Some(secs(19_979, 0, 0))
+        );
+
+        // Without a restricted day of week, only the day of month matches.
+        let schedule = "0 0 13 * *".parse::<Schedule>().unwrap();
+        assert_eq!(
+            schedule.next_after(secs(19_967, 0, 0)),
+            Some(secs(19_979, 0, 0))
+        );
+    }
+
+    #[test]
+    fn test_next_after_never() {
+        let schedule = "0 0 30 2 *".parse::<Schedule>().unwrap();
+        assert_eq!(schedule.next_after(0), None);
+    }
+}
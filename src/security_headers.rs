@@ -0,0 +1,63 @@
+//! Security headers like `Strict-Transport-Security` or `Content-Security-Policy`, added to all
+//! responses which do not have them already, such that handlers can still set their own, e.g. a
+//! more permissive CSP for a single page app. Environments override the defaults via their
+//! configuration files, e.g. `config/dev.toml` omits HSTS.
+
+use crate::settings::SecurityHeadersSettings;
+use axum::http::header::{
+    HeaderName, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+    X_CONTENT_TYPE_OPTIONS,
+};
+use axum::http::{HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+
+/// Shared state of the [add_security_headers] middleware.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    headers: Arc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl SecurityHeaders {
+    /// The configured headers, if enabled and any are not empty.
+    pub fn new(settings: &SecurityHeadersSettings) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+        let headers = [
+            (STRICT_TRANSPORT_SECURITY, &settings.hsts),
+            (X_CONTENT_TYPE_OPTIONS, &settings.content_type_options),
+            (REFERRER_POLICY, &settings.referrer_policy),
+            (CONTENT_SECURITY_POLICY, &settings.content_security_policy),
+            (
+                HeaderName::from_static("permissions-policy"),
+                &settings.permissions_policy,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        // Invalid values are rejected when validating the settings.
+        .filter_map(|(name, value)| HeaderValue::from_str(value).ok().map(|value| (name, value)))
+        .collect::<Vec<_>>();
+        (!headers.is_empty()).then(|| Self {
+            headers: Arc::new(headers),
+        })
+    }
+}
+
+/// Middleware adding the configured security headers, see [crate::security_headers].
+pub async fn add_security_headers<B>(
+    request: Request<B>,
+    next: Next<B>,
+    security_headers: SecurityHeaders,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    for (name, value) in security_headers.headers.iter() {
+        if !headers.contains_key(name) {
+            headers.insert(name, value.clone());
+        }
+    }
+    response
+}
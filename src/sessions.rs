@@ -0,0 +1,507 @@
+//! Cookie based sessions for browser clients: the session data is kept in a [SessionStore] and the
+//! cookie only carries the session ID, signed with HMAC-SHA256 using the configured secret, such
+//! that it can neither be forged nor read data.
+//!
+//! Sessions expire after `ttl` without requests; their expiry is extended, renewing the cookie,
+//! once half of the `ttl` has passed. Handlers access the current session via the [Session]
+//! extractor; new sessions are only stored, and the cookie is only set, once data is inserted.
+
+use crate::error::Error;
+use crate::hmac::{constant_time_eq, from_hex, hmac_sha256, to_hex};
+use crate::secret::Secret;
+use crate::settings::SessionSettings;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use axum::http::header::{COOKIE, SET_COOKIE};
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// Session key of the ID of the logged in user, to be inserted e.g. by the login handler, which
+/// identifies the user for percentage rollouts, see [crate::features].
+pub const USER_ID: &str = "user_id";
+
+/// How often expired sessions of the [InMemorySessionStore] are removed.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub type SessionData = BTreeMap<String, Value>;
+
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    pub data: SessionData,
+    pub expires: SystemTime,
+}
+
+/// Keeps session data by session ID, e.g. in memory or in Redis.
+#[async_trait]
+pub trait SessionStore: Debug + Send + Sync + 'static {
+    /// The unexpired record for the given ID, if any.
+    async fn load(&self, id: &str) -> Result<Option<SessionRecord>>;
+
+    async fn save(&self, id: &str, record: SessionRecord) -> Result<()>;
+
+    async fn delete(&self, id: &str) -> Result<()>;
+}
+
+/// [SessionStore] in memory, i.e. not shared across instances and lost on restart, hence only
+/// suitable for development or single instances.
+#[derive(Debug)]
+pub struct InMemorySessionStore {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    records: HashMap<String, SessionRecord>,
+    pruned: SystemTime,
+}
+
+impl Default for InMemorySessionStore {
+    fn default() -> Self {
+        let state = State {
+            records: Default::default(),
+            pruned: SystemTime::now(),
+        };
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+}
+
+impl InMemorySessionStore {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("state can be locked")
+    }
+}
+
+#[async_trait]
+impl SessionStore for InMemorySessionStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionRecord>> {
+        let now = SystemTime::now();
+        let mut state = self.state();
+        if now
+            .duration_since(state.pruned)
+            .map_or(false, |elapsed| elapsed >= PRUNE_INTERVAL)
+        {
+            state.records.retain(|_, record| record.expires > now);
+            state.pruned = now;
+        }
+        Ok(state
+            .records
+            .get(id)
+            .filter(|record| record.expires > now)
+            .cloned())
+    }
+
+    async fn save(&self, id: &str, record: SessionRecord) -> Result<()> {
+        self.state().records.insert(id.to_owned(), record);
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.state().records.remove(id);
+        Ok(())
+    }
+}
+
+/// Extractor for the session of the current request. Cheap to clone, all clones share the same
+/// session, changes to which are saved after the handler has responded.
+#[derive(Debug, Clone, Default)]
+pub struct Session(Arc<Mutex<SessionState>>);
+
+#[derive(Debug, Default)]
+struct SessionState {
+    id: Option<String>,
+    data: SessionData,
+    expires: Option<SystemTime>,
+    changed: bool,
+    rotate: bool,
+    destroyed: bool,
+}
+
+impl Session {
+    pub fn get<T>(&self, key: &str) -> Option<T>
+    where
+        T: DeserializeOwned,
+    {
+        let value = self.state().data.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn insert<T>(&self, key: impl Into<String>, value: T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.state();
+        state.data.insert(key.into(), value);
+        state.changed = true;
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        let mut state = self.state();
+        state.changed = true;
+        state.data.remove(key)
+    }
+
+    /// Issue a new session ID, keeping the data, e.g. after login to prevent session fixation.
+    pub fn rotate(&self) {
+        let mut state = self.state();
+        state.rotate = true;
+        state.changed = true;
+    }
+
+    /// Remove all data and the cookie, e.g. for logout.
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.destroyed = true;
+    }
+
+    /// Whether the request carried the cookie of an unexpired session.
+    pub fn is_established(&self) -> bool {
+        self.state().id.is_some()
+    }
+
+    /// The ID of the established session, if any, identifying the client like an API key.
+    pub(crate) fn id(&self) -> Option<String> {
+        self.state().id.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.0.lock().expect("session can be locked")
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Session
+where
+    B: Send,
+{
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        request
+            .extensions()
+            .ok_or_else(|| anyhow!("Extensions already taken"))?
+            .get::<Session>()
+            .cloned()
+            .ok_or_else(|| Error::Internal(anyhow!("Sessions are not configured")))
+    }
+}
+
+/// Shared state of the [manage_session] middleware.
+#[derive(Debug, Clone)]
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    settings: Arc<SessionSettings>,
+    secret: Arc<Secret<Vec<u8>>>,
+}
+
+impl Sessions {
+    /// The configured sessions, if a secret is configured.
+    pub fn new(settings: &SessionSettings, store: Arc<dyn SessionStore>) -> Option<Self> {
+        let secret = settings.secret.as_ref()?.expose().as_bytes().to_vec();
+        Some(Self {
+            store,
+            settings: Arc::new(settings.clone()),
+            secret: Arc::new(Secret::new(secret)),
+        })
+    }
+
+    fn signature(&self, id: &str) -> [u8; 32] {
+        hmac_sha256(self.secret.expose(), id.as_bytes())
+    }
+
+    /// The session ID of the given cookie value if its signature is valid.
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, signature) = value.split_once('.')?;
+        let signature = from_hex(signature)?;
+        constant_time_eq(&self.signature(id), &signature).then(|| id)
+    }
+
+    fn cookie(&self, value: &str, max_age: Duration) -> Option<HeaderValue> {
+        let mut cookie = format!(
+            "{}={value}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+            self.settings.cookie_name,
+            max_age.as_secs()
+        );
+        if self.settings.secure {
+            cookie.push_str("; Secure");
+        }
+        HeaderValue::from_str(&cookie).ok()
+    }
+}
+
+/// Middleware loading the [Session] for the signed session cookie, if any, and saving it after
+/// the response, setting or removing the cookie as needed.
+pub async fn manage_session<B>(
+    mut request: Request<B>,
+    next: Next<B>,
+    sessions: Sessions,
+) -> Response {
+    let id = cookie(request.headers(), &sessions.settings.cookie_name)
+        .and_then(|value| sessions.verify(&value).map(ToOwned::to_owned));
+    let record = match &id {
+        Some(id) => match sessions.store.load(id).await {
+            Ok(record) => record,
+            Err(e) => return Error::Internal(e.context("Cannot load session")).into_response(),
+        },
+        None => None,
+    };
+    let session = match record {
+        Some(record) => Session(Arc::new(Mutex::new(SessionState {
+            id,
+            data: record.data,
+            expires: Some(record.expires),
+            ..Default::default()
+        }))),
+        None => Session::default(),
+    };
+    request.extensions_mut().insert(session.clone());
+
+    let mut response = next.run(request).await;
+    if let Err(e) = save(&sessions, &session, response.headers_mut()).await {
+        return Error::Internal(e.context("Cannot save session")).into_response();
+    }
+    response
+}
+
+async fn save(sessions: &Sessions, session: &Session, headers: &mut HeaderMap) -> Result<()> {
+    match change(session, sessions.settings.ttl) {
+        Change::None => {}
+
+        Change::Delete(id) => {
+            sessions.store.delete(&id).await?;
+            if let Some(cookie) = sessions.cookie("", Duration::ZERO) {
+                headers.append(SET_COOKIE, cookie);
+            }
+        }
+
+        Change::Save { id, record, old_id } => {
+            if let Some(old_id) = old_id {
+                sessions.store.delete(&old_id).await?;
+            }
+            sessions.store.save(&id, record).await?;
+            let value = format!("{id}.{}", to_hex(&sessions.signature(&id)));
+            if let Some(cookie) = sessions.cookie(&value, sessions.settings.ttl) {
+                headers.append(SET_COOKIE, cookie);
+            }
+        }
+    }
+    Ok(())
+}
+
+enum Change {
+    None,
+    Delete(String),
+    Save {
+        id: String,
+        record: SessionRecord,
+        /// The previous ID of rotated sessions.
+        old_id: Option<String>,
+    },
+}
+
+/// What to store for the given session: destroyed or emptied sessions are deleted, changed ones
+/// saved, as are unchanged ones with less than half of the `ttl` left, extending their expiry.
+fn change(session: &Session, ttl: Duration) -> Change {
+    let mut state = session.state();
+
+    if state.destroyed || state.data.is_empty() {
+        return match state.id.take() {
+            Some(id) if state.destroyed || state.changed => Change::Delete(id),
+            _ => Change::None,
+        };
+    }
+
+    let now = SystemTime::now();
+    let half_expired = state
+        .expires
+        .and_then(|expires| expires.duration_since(now).ok())
+        .map_or(true, |remaining| remaining < ttl / 2);
+    if !state.changed && !half_expired {
+        return Change::None;
+    }
+
+    let old_id = if state.rotate { state.id.take() } else { None };
+    let id = state.id.get_or_insert_with(new_id).clone();
+    let record = SessionRecord {
+        data: state.data.clone(),
+        expires: now + ttl,
+    };
+    Change::Save { id, record, old_id }
+}
+
+fn new_id() -> String {
+    to_hex(&rand::random::<[u8; 32]>())
+}
+
+/// The value of the cookie with the given name, if any.
+pub(crate) fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn sessions() -> Sessions {
+        let settings = SessionSettings {
+            secret: Some(Secret::new("0123456789abcdef0123456789abcdef".to_string())),
+            ..Default::default()
+        };
+        Sessions::new(&settings, Arc::new(InMemorySessionStore::default())).unwrap()
+    }
+
+    /// An established session with the given ID, some data and the given remaining time.
+    fn established(id: &str, remaining: Duration) -> Session {
+        let data = [(USER_ID.to_string(), Value::from("alice"))]
+            .into_iter()
+            .collect();
+        Session(Arc::new(Mutex::new(SessionState {
+            id: Some(id.to_string()),
+            data,
+            expires: Some(SystemTime::now() + remaining),
+            ..Default::default()
+        })))
+    }
+
+    #[test]
+    fn test_verify() {
+        let sessions = sessions();
+        let signature = to_hex(&sessions.signature("abc"));
+        assert_eq!(sessions.verify(&format!("abc.{signature}")), Some("abc"));
+
+        // Tampered signatures or IDs.
+        let mut tampered = signature.clone();
+        tampered.replace_range(..1, if signature.starts_with('0') { "1" } else { "0" });
+        assert_eq!(sessions.verify(&format!("abc.{tampered}")), None);
+        assert_eq!(sessions.verify(&format!("abd.{signature}")), None);
+        assert_eq!(sessions.verify(&format!("abc.{}", &signature[2..])), None);
+
+        // Unsigned or malformed values.
+        assert_eq!(sessions.verify("abc"), None);
+        assert_eq!(sessions.verify("abc."), None);
+        assert_eq!(sessions.verify("abc.xyz"), None);
+        assert_eq!(sessions.verify(""), None);
+    }
+
+    #[test]
+    fn test_change_new() {
+        // New sessions are only saved once data is inserted.
+        let session = Session::default();
+        assert!(matches!(change(&session, TTL), Change::None));
+
+        session.insert(USER_ID, "alice").unwrap();
+        match change(&session, TTL) {
+            Change::Save { id, record, old_id } => {
+                assert_eq!(id.len(), 64);
+                assert_eq!(record.data[USER_ID], "alice");
+                assert!(old_id.is_none());
+            }
+            _ => panic!("expected save"),
+        }
+    }
+
+    #[test]
+    fn test_change_renewal() {
+        // Unchanged sessions are only saved once half of the ttl has passed.
+        let session = established("abc", TTL / 2 + Duration::from_secs(5));
+        assert!(matches!(change(&session, TTL), Change::None));
+
+        let session = established("abc", TTL / 2 - Duration::from_secs(5));
+        match change(&session, TTL) {
+            Change::Save { id, record, old_id } => {
+                assert_eq!(id, "abc");
+                assert!(record.expires > SystemTime::now() + TTL / 2);
+                assert!(old_id.is_none());
+            }
+            _ => panic!("expected save"),
+        }
+
+        let session = established("abc", TTL);
+        session.insert("theme", "dark").unwrap();
+        assert!(matches!(change(&session, TTL), Change::Save { id, .. } if id == "abc"));
+    }
+
+    #[test]
+    fn test_change_rotation() {
+        let session = established("abc", TTL);
+        session.rotate();
+        match change(&session, TTL) {
+            Change::Save { id, record, old_id } => {
+                assert_ne!(id, "abc");
+                assert_eq!(record.data[USER_ID], "alice");
+                assert_eq!(old_id.as_deref(), Some("abc"));
+            }
+            _ => panic!("expected save"),
+        }
+    }
+
+    #[test]
+    fn test_change_destroy() {
+        let session = established("abc", TTL);
+        session.destroy();
+        assert!(matches!(change(&session, TTL), Change::Delete(id) if id == "abc"));
+
+        // Emptied sessions are deleted as well.
+        let session = established("abc", TTL);
+        session.remove(USER_ID);
+        assert!(matches!(change(&session, TTL), Change::Delete(id) if id == "abc"));
+
+        // Destroying a new session does nothing.
+        let session = Session::default();
+        session.destroy();
+        assert!(matches!(change(&session, TTL), Change::None));
+    }
+
+    #[test]
+    fn test_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.append(COOKIE, "theme=dark; session=abc.def".parse().unwrap());
+        headers.append(COOKIE, "other=1;csrf=xyz".parse().unwrap());
+
+        assert_eq!(cookie(&headers, "session").as_deref(), Some("abc.def"));
+        assert_eq!(cookie(&headers, "csrf").as_deref(), Some("xyz"));
+        assert_eq!(cookie(&headers, "sess"), None);
+        assert_eq!(cookie(&HeaderMap::new(), "session"), None);
+    }
+
+    #[tokio::test]
+    async fn test_save() {
+        let sessions = sessions();
+        let session = Session::default();
+        session.insert(USER_ID, "alice").unwrap();
+        let mut headers = HeaderMap::new();
+        save(&sessions, &session, &mut headers).await.unwrap();
+
+        let set_cookie = headers[SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.ends_with("; Path=/; Max-Age=86400; HttpOnly; SameSite=Lax; Secure"));
+        let value = set_cookie
+            .strip_prefix("session=")
+            .and_then(|cookie| cookie.split(';').next())
+            .unwrap();
+        let id = sessions.verify(value).unwrap();
+        let record = sessions.store.load(id).await.unwrap().unwrap();
+        assert_eq!(record.data[USER_ID], "alice");
+    }
+}
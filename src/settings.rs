@@ -0,0 +1,1501 @@
+pub(crate) mod units;
+mod validate;
+
+use crate::cidr::Cidr;
+use crate::secret::Secret;
+use crate::{aws_secrets, vault};
+use anyhow::{Context, Result};
+use config::builder::DefaultState;
+use config::{Config, ConfigBuilder, ConfigError, Environment, File, FileFormat};
+use rand::Rng;
+use serde::de::{self, IntoDeserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+use std::mem;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const DEFAULT_CONFIG_DIR: &str = "config";
+
+const ENVIRONMENT: &str = "ENVIRONMENT";
+
+/// All sections and their values have defaults, hence no configuration files are required.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub logging: LoggingSettings,
+    pub telemetry: TelemetrySettings,
+    pub admin: AdminSettings,
+    pub cors: CorsSettings,
+    /// API keys by key ID; if any are defined, the API routes require one of them in the
+    /// `x-api-key` header.
+    pub api_keys: BTreeMap<String, ApiKeySettings>,
+    pub auth: AuthSettings,
+    pub rate_limiting: RateLimitingSettings,
+    pub events: EventsSettings,
+    pub scheduler: SchedulerSettings,
+    pub http_client: HttpClientSettings,
+    pub health: HealthSettings,
+    pub maintenance: MaintenanceSettings,
+    pub tenancy: TenancySettings,
+    pub idempotency: IdempotencySettings,
+    /// Feature flags by name; reloaded on SIGHUP.
+    pub features: BTreeMap<String, FlagSettings>,
+    pub vault: VaultSettings,
+    pub aws: AwsSettings,
+    pub aws_secrets: AwsSecretsSettings,
+    pub uploads: UploadSettings,
+    pub storage: StorageSettings,
+    pub static_files: StaticFilesSettings,
+    pub conditional_requests: ConditionalRequestsSettings,
+    pub response_cache: ResponseCacheSettings,
+    pub sessions: SessionSettings,
+    pub csrf: CsrfSettings,
+    /// Signature verification for inbound webhooks by route, e.g. `/webhooks/github`.
+    pub webhook_signatures: BTreeMap<String, WebhookSignatureSettings>,
+    pub webhooks: WebhooksSettings,
+    pub audit: AuditSettings,
+    /// IP allow and deny lists by name of the route group, e.g. `admin`, see [crate::ip_filter].
+    pub ip_filters: BTreeMap<String, IpFilterSettings>,
+    pub security_headers: SecurityHeadersSettings,
+    /// Forwarding to upstreams by path prefix, e.g. `/legacy`, see [crate::proxy].
+    pub proxy: BTreeMap<String, ProxySettings>,
+    pub mirror: MirrorSettings,
+    pub canary: CanarySettings,
+    pub runtime: RuntimeSettings,
+    pub compute: ComputeSettings,
+    /// The value of the environment variable `ENVIRONMENT`, e.g. `dev`.
+    #[serde(skip)]
+    pub environment: Option<String>,
+}
+
+impl Settings {
+    /// First the optional file `default` in the given configuration directory, usually `config`,
+    /// is read, then the file `<ENVIRONMENT>`, e.g. `config/dev`, if the environment variable
+    /// `ENVIRONMENT` is defined, then the values from AWS Secrets Manager or SSM Parameter Store,
+    /// if `aws_secrets.source` is defined, see [crate::aws_secrets], then environment variables
+    /// prefixed with `APP__` and separated
+    /// by `__` (double underscores are used as separators because of snake_cased keys), e.g.
+    /// `APP__SERVER__PORT` or `APP__API_KEYS__CI__KEY` for the lowercased map key `ci`, with lists
+    /// given as comma-separated values, and finally the given overrides, e.g. from command line
+    /// arguments.
+    /// Values referencing secrets in Vault, e.g. `vault:secret/data/app#db_password`, are
+    /// resolved last, see [crate::vault].
+    pub async fn load(config_dir: &Path, overrides: &[(&str, String)]) -> Result<Self> {
+        let environment = env::var(ENVIRONMENT).ok();
+        let files = || {
+            environment.iter().fold(
+                Config::builder()
+                    .add_source(File::from(config_dir.join("default")).required(false)),
+                |config, env| config.add_source(File::from(config_dir.join(env))),
+            )
+        };
+        let config = with_env_and_overrides(files(), overrides)?;
+
+        let aws_secrets_settings = section::<AwsSecretsSettings>(&config, "aws_secrets")?;
+        let config = if aws_secrets_settings.source.is_some() {
+            let aws_settings = section::<AwsSettings>(&config, "aws")?;
+            let values = aws_secrets::load(&aws_secrets_settings, &aws_settings)
+                .await
+                .context("Cannot load settings from AWS")?;
+            let files = files().add_source(File::from_str(&values.to_string(), FileFormat::Json));
+            with_env_and_overrides(files, overrides)?
+        } else {
+            config
+        };
+
+        let values = config.build_cloned()?.try_deserialize::<Value>()?;
+        validate::check_renamed_keys(&values)?;
+        let references = vault::references(values);
+        let config = if references.is_empty() {
+            config
+        } else {
+            let vault_settings = section::<VaultSettings>(&config, "vault")?;
+            vault::resolve(references, &vault_settings)
+                .await
+                .context("Cannot resolve Vault references")?
+                .into_iter()
+                .try_fold(config, |config, (key, value)| {
+                    config.set_override(key, value)
+                })?
+        };
+
+        let settings = config
+            .build()?
+            .try_deserialize::<Self>()
+            .context("Error creating configuration settings")?;
+        settings.validate()?;
+        Ok(Self {
+            environment,
+            ..settings
+        })
+    }
+
+    /// Pretty printed JSON with the values of all [Secret]s redacted.
+    pub fn to_redacted_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Cannot serialize settings")
+    }
+
+    /// Address of the admin server, if it is served on its own port.
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin.port.map(|port| {
+            let addr = self
+                .admin
+                .addr
+                .unwrap_or_else(|| self.server.primary_addr());
+            SocketAddr::new(addr, port)
+        })
+    }
+
+    /// Address of the metrics server, if it is served on its own port.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.telemetry
+            .metrics_port
+            .map(|port| SocketAddr::new(self.server.primary_addr(), port))
+    }
+
+    pub fn is_dev(&self) -> bool {
+        self.environment.as_deref() == Some("dev")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    /// One or more addresses, e.g. `["0.0.0.0", "::"]` for dual-stack, each served by its own
+    /// listener.
+    #[serde(deserialize_with = "list")]
+    pub addr: Vec<IpAddr>,
+    pub port: u16,
+    /// Whether to listen on `addr` and `port`; may be disabled if listening on a Unix domain
+    /// socket.
+    pub tcp_enabled: bool,
+    pub tcp: TcpSettings,
+    pub http2: Http2Settings,
+    /// If defined, the server (also) listens on a Unix domain socket at this path.
+    pub uds_path: Option<PathBuf>,
+    /// Permissions of the Unix domain socket, e.g. `0o660`.
+    pub uds_mode: Option<u32>,
+    /// Timeout for each startup hook, see [crate::lifecycle].
+    #[serde(with = "units::duration")]
+    pub startup_timeout: Duration,
+    /// Timeout for draining connections and for each shutdown hook.
+    #[serde(with = "units::duration")]
+    pub shutdown_timeout: Duration,
+    #[serde(with = "units::duration")]
+    pub request_timeout: Duration,
+    /// Maximum size of request bodies, e.g. `"2MiB"` or in bytes.
+    #[serde(deserialize_with = "units::byte_size")]
+    pub max_body_size: usize,
+    /// If defined, requests exceeding this number of concurrently handled ones are rejected with
+    /// 503 instead of being queued.
+    pub max_concurrent_requests: Option<usize>,
+    /// Reverse proxies and load balancers, e.g. `["10.0.0.0/8"]`, whose forwarding headers are
+    /// used to determine the client address, see [crate::client_ip].
+    #[serde(deserialize_with = "list")]
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+impl ServerSettings {
+    /// The first address, used for the admin and metrics servers unless configured otherwise.
+    pub fn primary_addr(&self) -> IpAddr {
+        self.addr
+            .first()
+            .copied()
+            .unwrap_or_else(|| Ipv6Addr::LOCALHOST.into())
+    }
+
+    /// The addresses to listen on, none if TCP is disabled.
+    pub fn tcp_addrs(&self) -> Vec<SocketAddr> {
+        if self.tcp_enabled {
+            self.addr
+                .iter()
+                .map(|addr| SocketAddr::new(*addr, self.port))
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            addr: vec![Ipv6Addr::LOCALHOST.into()],
+            port: 80,
+            tcp_enabled: true,
+            tcp: Default::default(),
+            http2: Default::default(),
+            uds_path: None,
+            uds_mode: None,
+            startup_timeout: Duration::from_secs(60),
+            shutdown_timeout: Duration::from_secs(20),
+            request_timeout: Duration::from_secs(30),
+            max_body_size: 2 * 1024 * 1024,
+            max_concurrent_requests: None,
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+/// Options of the TCP listener sockets and the accepted connections.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TcpSettings {
+    /// `SO_REUSEADDR`
+    pub reuse_address: bool,
+    /// `SO_REUSEPORT`, allowing several processes to share the load of a port.
+    pub reuse_port: bool,
+    /// Maximum length of the queue of pending connections.
+    pub backlog: i32,
+    /// `TCP_NODELAY`
+    pub nodelay: bool,
+    /// If defined, TCP keepalive probes are sent after connections have been idle for this
+    /// duration.
+    #[serde(default, with = "units::option_duration")]
+    pub keepalive: Option<Duration>,
+    pub proxy_protocol: ProxyProtocolSettings,
+}
+
+impl Default for TcpSettings {
+    fn default() -> Self {
+        Self {
+            reuse_address: true,
+            reuse_port: false,
+            backlog: 1024,
+            nodelay: false,
+            keepalive: None,
+            proxy_protocol: Default::default(),
+        }
+    }
+}
+
+/// PROXY protocol, version 1 or 2, sent by TCP load balancers which cannot add forwarding
+/// headers; if enabled, connections must start with its header and are only accepted from the
+/// `server.trusted_proxies`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProxyProtocolSettings {
+    pub enabled: bool,
+    /// Connections not sending the header within this duration are closed.
+    #[serde(with = "units::duration")]
+    pub timeout: Duration,
+}
+
+impl Default for ProxyProtocolSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// HTTP/2 over cleartext (h2c) with prior knowledge, served alongside HTTP/1.1 on the same
+/// listeners; settings not defined use the defaults of hyper.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Http2Settings {
+    pub enabled: bool,
+    /// Only serve HTTP/2, rejecting HTTP/1.1; ignored unless enabled.
+    pub only: bool,
+    pub max_concurrent_streams: Option<u32>,
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    /// Adapt the window sizes based on the measured bandwidth, overriding the initial ones.
+    pub adaptive_window: bool,
+}
+
+impl Default for Http2Settings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            only: false,
+            max_concurrent_streams: None,
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            adaptive_window: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LoggingSettings {
+    pub format: LogFormat,
+    /// Filter directives like `info,bayer_axum=debug`; if not defined, `RUST_LOG` is used.
+    /// Changes are applied when reloading the settings.
+    pub filter: Option<String>,
+    pub access_log: AccessLogSettings,
+    pub body_capture: BodyCaptureSettings,
+}
+
+/// One event with target `access_log` per request, see [crate::access_log].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AccessLogSettings {
+    pub enabled: bool,
+    /// Fractions of requests logged by route, e.g. `0.01` for `/api/v1/hot`; all by default.
+    /// Server errors are always logged.
+    pub sample_rates: BTreeMap<String, f64>,
+}
+
+impl Default for AccessLogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_rates: BTreeMap::new(),
+        }
+    }
+}
+
+/// Logging of request and response bodies for debugging, see [crate::body_capture].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BodyCaptureSettings {
+    /// Routes, i.e. path templates like `/api/v1/orders/:id`, for which bodies are logged.
+    #[serde(deserialize_with = "list")]
+    pub routes: Vec<String>,
+    /// Environments in which bodies are logged at all, only `dev` by default.
+    #[serde(deserialize_with = "list")]
+    pub environments: Vec<String>,
+    /// Bodies are truncated to this size, e.g. `"4KiB"` or in bytes.
+    #[serde(deserialize_with = "units::byte_size")]
+    pub max_size: usize,
+    /// Headers logged as `[redacted]`, case-insensitive.
+    #[serde(deserialize_with = "list")]
+    pub redact_headers: Vec<String>,
+    /// Fields of JSON and form bodies logged as `[redacted]`, case-insensitive.
+    #[serde(deserialize_with = "list")]
+    pub redact_fields: Vec<String>,
+}
+
+impl Default for BodyCaptureSettings {
+    fn default() -> Self {
+        Self {
+            routes: vec![],
+            environments: vec!["dev".to_string()],
+            max_size: 4 * 1024,
+            redact_headers: [
+                "authorization",
+                "cookie",
+                "proxy-authorization",
+                "set-cookie",
+                "x-api-key",
+            ]
+            .map(ToString::to_string)
+            .to_vec(),
+            redact_fields: [
+                "access_token",
+                "password",
+                "refresh_token",
+                "secret",
+                "token",
+            ]
+            .map(ToString::to_string)
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TelemetrySettings {
+    /// If defined, `/metrics` is served on this port instead of the server port.
+    pub metrics_port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AdminSettings {
+    /// Address of the admin server, defaults to the server address.
+    pub addr: Option<IpAddr>,
+    /// If defined, the health, metrics and admin routes are served on this port instead of the
+    /// server port.
+    pub port: Option<u16>,
+    pub username: String,
+    /// The admin routes are only served if a password is defined.
+    pub password: Option<Secret<String>>,
+}
+
+impl Default for AdminSettings {
+    fn default() -> Self {
+        Self {
+            addr: None,
+            port: None,
+            username: "admin".to_string(),
+            password: None,
+        }
+    }
+}
+
+/// Cross-origin resource sharing, where `*` allows any origin, method or header. By default no
+/// cross-origin requests are allowed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CorsSettings {
+    #[serde(deserialize_with = "list")]
+    pub allowed_origins: Vec<String>,
+    #[serde(deserialize_with = "list")]
+    pub allowed_methods: Vec<String>,
+    #[serde(deserialize_with = "list")]
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache the responses to preflight requests.
+    #[serde(with = "units::duration")]
+    pub max_age: Duration,
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec![],
+            max_age: Duration::from_secs(60 * 60),
+            allow_credentials: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiKeySettings {
+    pub key: Secret<String>,
+    pub rate_limit: Option<RateLimitSettings>,
+}
+
+/// Authentication via JWT bearer tokens, see [crate::auth]; enabled if any keys are defined.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AuthSettings {
+    /// Signing keys by key ID, matched against the `kid` header of tokens.
+    pub keys: BTreeMap<String, JwtKeySettings>,
+    /// Required value of the `iss` claim, if defined.
+    pub issuer: Option<String>,
+    /// Required value of the `aud` claim, if defined.
+    pub audience: Option<String>,
+    /// Tolerated clock skew when checking the `exp` and `nbf` claims.
+    #[serde(with = "units::duration")]
+    pub leeway: Duration,
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        Self {
+            keys: BTreeMap::new(),
+            issuer: None,
+            audience: None,
+            leeway: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The `secret` for HS256, which must be defined.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JwtKeySettings {
+    pub secret: Option<Secret<String>>,
+}
+
+/// A token bucket holding up to `burst` requests, refilled with `per_second` requests per second.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RateLimitSettings {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+/// Rate limits per client IP address: `global` across all routes and `routes` by route pattern,
+/// e.g. `/users/:id`; reloaded on SIGHUP.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitingSettings {
+    pub global: Option<RateLimitSettings>,
+    pub routes: BTreeMap<String, RateLimitSettings>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EventsSettings {
+    #[serde(with = "units::duration")]
+    pub heartbeat_interval: Duration,
+    /// Number of most recent events kept for clients resuming via `Last-Event-ID`.
+    pub replay_capacity: usize,
+    /// Number of events per type buffered for lagging handlers, see [crate::domain_events].
+    pub domain_capacity: usize,
+}
+
+impl Default for EventsSettings {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_secs(15),
+            replay_capacity: 100,
+            domain_capacity: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SchedulerSettings {
+    /// Schedules by job name.
+    pub jobs: BTreeMap<String, JobSettings>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JobSettings {
+    /// Cron expression like `*/15 8-18 * * 1-5`, in UTC.
+    pub schedule: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HttpClientSettings {
+    #[serde(with = "units::duration")]
+    pub timeout: Duration,
+    #[serde(with = "units::duration")]
+    pub pool_idle_timeout: Duration,
+    pub pool_max_idle_per_host: usize,
+    pub retry: RetrySettings,
+    pub circuit_breaker: CircuitBreakerSettings,
+    /// Settings by target host, overriding the general ones; the circuits of these targets are
+    /// part of the readiness.
+    pub targets: BTreeMap<String, HttpTargetSettings>,
+}
+
+impl HttpClientSettings {
+    /// The timeout for the given target host.
+    pub fn target_timeout(&self, target: &str) -> Duration {
+        self.targets
+            .get(target)
+            .and_then(|target| target.timeout)
+            .unwrap_or(self.timeout)
+    }
+}
+
+impl Default for HttpClientSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            pool_idle_timeout: Duration::from_secs(90),
+            pool_max_idle_per_host: 32,
+            retry: Default::default(),
+            circuit_breaker: Default::default(),
+            targets: BTreeMap::new(),
+        }
+    }
+}
+
+/// Retries of requests with idempotent methods, waiting a random duration between half and all of
+/// the exponentially growing backoff.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetrySettings {
+    pub max_retries: u32,
+    #[serde(with = "units::duration")]
+    pub initial_backoff: Duration,
+    #[serde(with = "units::duration")]
+    pub max_backoff: Duration,
+}
+
+impl RetrySettings {
+    /// The jittered backoff before the given retry, starting with zero.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .checked_mul(2u32.saturating_pow(retry))
+            .unwrap_or(Duration::MAX)
+            .min(self.max_backoff);
+        rand::thread_rng().gen_range(backoff / 2..=backoff)
+    }
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// A `failure_threshold` of zero disables the circuit breakers.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CircuitBreakerSettings {
+    pub failure_threshold: u32,
+    /// How long requests are rejected once the circuit has opened.
+    #[serde(with = "units::duration")]
+    pub open_duration: Duration,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HttpTargetSettings {
+    #[serde(default, with = "units::option_duration")]
+    pub timeout: Option<Duration>,
+}
+
+/// Readiness checks are run every `refresh_interval`, each with `timeout` unless configured
+/// otherwise, see [crate::health].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HealthSettings {
+    #[serde(with = "units::duration")]
+    pub refresh_interval: Duration,
+    #[serde(with = "units::duration")]
+    pub timeout: Duration,
+    /// Downstream HTTP dependencies by name, ready if `GET` of their URL responds with 2xx.
+    pub http: BTreeMap<String, HttpCheckSettings>,
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        Self {
+            refresh_interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+            http: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpCheckSettings {
+    pub url: String,
+    #[serde(default, with = "units::option_duration")]
+    pub timeout: Option<Duration>,
+}
+
+/// Initial maintenance mode, see [crate::maintenance].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MaintenanceSettings {
+    pub enabled: bool,
+    /// Detail of the 503 responses.
+    pub message: String,
+    #[serde(with = "units::duration")]
+    pub retry_after: Duration,
+}
+
+impl Default for MaintenanceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            message: "The service is down for maintenance".to_string(),
+            retry_after: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// Idempotent `POST` and `PATCH` requests, see [crate::idempotency].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IdempotencySettings {
+    pub enabled: bool,
+    /// How long responses are replayed.
+    #[serde(with = "units::duration")]
+    pub ttl: Duration,
+    /// Responses with larger or unknown body size are not stored, e.g. `"64KiB"` or in bytes.
+    #[serde(deserialize_with = "units::byte_size")]
+    pub max_response_size: usize,
+}
+
+impl Default for IdempotencySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl: Duration::from_secs(24 * 60 * 60),
+            max_response_size: 64 * 1024,
+        }
+    }
+}
+
+/// How the tenant of API requests is determined, see [crate::tenancy]; by default, i.e. without
+/// strategy, there is no multi-tenancy.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TenancySettings {
+    pub strategy: Option<TenantStrategy>,
+    /// Header carrying the tenant ID for the `header` strategy.
+    pub header: String,
+    /// Domain below which the first label is the tenant ID for the `subdomain` strategy, e.g.
+    /// `example.com` for `acme.example.com`.
+    pub domain: Option<String>,
+}
+
+impl Default for TenancySettings {
+    fn default() -> Self {
+        Self {
+            strategy: None,
+            header: "x-tenant-id".to_string(),
+            domain: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TenantStrategy {
+    Header,
+    Subdomain,
+    /// The JWT claim with the given name, e.g. `{ claim = { name = "tenant_id" } }`, which
+    /// requires `auth` to be configured.
+    Claim {
+        name: String,
+    },
+}
+
+/// A feature flag, see [crate::features]: enabled for all requests if `enabled`, otherwise for
+/// requests authenticated with one of `api_keys` or a JWT with one of the values of any of
+/// `claims` and for `rollout_percent` percent of the identities, i.e. `sub` claims, API key IDs,
+/// session users or, for unauthenticated requests only, `x-user-id` header values. If
+/// `header_override` is set, requests can enable or disable it via the `x-features` header.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FlagSettings {
+    pub enabled: bool,
+    #[serde(deserialize_with = "list")]
+    pub api_keys: Vec<String>,
+    /// Values by claim name, e.g. `plan = ["beta"]`.
+    pub claims: BTreeMap<String, Vec<String>>,
+    pub rollout_percent: u8,
+    pub header_override: bool,
+}
+
+/// Vault is accessed via plain HTTP, e.g. via a local Vault Agent.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VaultSettings {
+    /// Defaults to the environment variable `VAULT_ADDR` or `http://127.0.0.1:8200`.
+    pub addr: Option<String>,
+    /// Defaults to the environment variable `VAULT_TOKEN`.
+    pub token: Option<Secret<String>>,
+    #[serde(with = "units::duration")]
+    pub timeout: Duration,
+}
+
+impl VaultSettings {
+    pub fn addr(&self) -> String {
+        self.addr
+            .clone()
+            .or_else(|| env::var("VAULT_ADDR").ok())
+            .unwrap_or_else(|| "http://127.0.0.1:8200".to_string())
+    }
+
+    pub fn token(&self) -> Option<String> {
+        self.token
+            .as_ref()
+            .map(|token| token.expose().to_owned())
+            .or_else(|| env::var("VAULT_TOKEN").ok())
+    }
+}
+
+impl Default for VaultSettings {
+    fn default() -> Self {
+        Self {
+            addr: None,
+            token: None,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Access to AWS, see [crate::aws].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AwsSettings {
+    /// Defaults to the environment variable `AWS_REGION` or `AWS_DEFAULT_REGION`.
+    pub region: Option<String>,
+    /// Static credentials; if not defined, credentials are taken from the environment or the
+    /// container credentials endpoint, see [CredentialsProvider](crate::aws::CredentialsProvider).
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<Secret<String>>,
+    pub session_token: Option<Secret<String>>,
+    /// Timeout for fetching credentials from the container credentials endpoint.
+    #[serde(with = "units::duration")]
+    pub timeout: Duration,
+}
+
+impl Default for AwsSettings {
+    fn default() -> Self {
+        Self {
+            region: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Values from AWS Secrets Manager or SSM Parameter Store, see [crate::aws_secrets]; disabled
+/// unless `source` is defined.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AwsSecretsSettings {
+    pub source: Option<AwsSecretsSource>,
+    /// Prefix of the secret names or path of the parameters, e.g. `/bayer-axum/prod`.
+    pub prefix: String,
+    /// Base URL of the API, which must be plain HTTP on the loopback interface, because the
+    /// secrets are received in cleartext, e.g. an egress proxy sidecar originating TLS at
+    /// `http://localhost:4566`, see [crate::aws].
+    pub endpoint: Option<String>,
+    #[serde(with = "units::duration")]
+    pub timeout: Duration,
+}
+
+impl Default for AwsSecretsSettings {
+    fn default() -> Self {
+        Self {
+            source: None,
+            prefix: String::new(),
+            endpoint: None,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AwsSecretsSource {
+    SecretsManager,
+    Ssm,
+}
+
+/// Multipart file uploads, see [crate::upload].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UploadSettings {
+    /// Directory of the local blob store; uploads are disabled unless defined or `storage.s3` is
+    /// defined.
+    pub dir: Option<PathBuf>,
+    /// Maximum size of each uploaded file, e.g. `"10MiB"` or in bytes.
+    #[serde(deserialize_with = "units::byte_size")]
+    pub max_file_size: usize,
+    /// Maximum size of upload requests, which are exempt from `server.max_body_size`.
+    #[serde(deserialize_with = "units::byte_size")]
+    pub max_total_size: usize,
+}
+
+impl Default for UploadSettings {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_file_size: 10 * 1024 * 1024,
+            max_total_size: 50 * 1024 * 1024,
+        }
+    }
+}
+
+/// Where blobs like uploaded files are stored, see [crate::blob_store]: in an S3 bucket if `s3` is
+/// defined, otherwise in `uploads.dir`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub s3: Option<S3Settings>,
+}
+
+/// An S3 bucket, accessed with the region and credentials of the `aws` settings via an egress
+/// proxy, see `endpoint`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct S3Settings {
+    pub bucket: String,
+    /// Base URL of the S3 API, which must be plain HTTP, see [crate::aws], e.g.
+    /// `http://minio:9000`. Requests and object bodies are sent in cleartext, hence this must be
+    /// an egress proxy originating TLS towards AWS, e.g. a sidecar on `localhost`, or an
+    /// S3-compatible store within a trusted network.
+    pub endpoint: String,
+    /// Whether the bucket is addressed in the path, e.g. `http://minio:9000/uploads/a.txt`, as
+    /// most S3-compatible stores require, or in the host name, e.g.
+    /// `http://uploads.s3.example.com/a.txt`.
+    pub path_style: bool,
+    /// Prefix of the object keys, e.g. `uploads/`.
+    pub prefix: String,
+    /// Size of the parts of multipart uploads; smaller blobs are uploaded at once.
+    #[serde(deserialize_with = "units::byte_size")]
+    pub part_size: usize,
+}
+
+impl Default for S3Settings {
+    fn default() -> Self {
+        Self {
+            bucket: String::new(),
+            endpoint: String::new(),
+            path_style: true,
+            prefix: String::new(),
+            part_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// Serving static files, see [crate::static_files]; disabled unless `dir` is defined.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StaticFilesSettings {
+    pub dir: Option<PathBuf>,
+    /// Path to mount the files under, e.g. `/app`.
+    pub path: String,
+    /// Whether to answer unknown paths with the root `index.html`.
+    pub spa_fallback: bool,
+    /// Duration files other than `index.html` may be cached.
+    #[serde(with = "units::duration")]
+    pub max_age: Duration,
+}
+
+impl Default for StaticFilesSettings {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            path: "/".to_string(),
+            spa_fallback: true,
+            max_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Conditional requests, see [crate::conditional], for the route groups given by path prefixes,
+/// e.g. `/api/v2`; none disables them.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConditionalRequestsSettings {
+    #[serde(deserialize_with = "list")]
+    pub paths: Vec<String>,
+    /// Maximum size of response bodies buffered to compute an `ETag`.
+    #[serde(deserialize_with = "units::byte_size")]
+    pub max_body_size: usize,
+}
+
+impl Default for ConditionalRequestsSettings {
+    fn default() -> Self {
+        Self {
+            paths: vec!["/".to_string()],
+            max_body_size: 1024 * 1024,
+        }
+    }
+}
+
+/// In-process response caching, see [crate::response_cache], for the routes given by pattern,
+/// e.g. `/users/:id`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ResponseCacheSettings {
+    pub routes: BTreeMap<String, RouteCacheSettings>,
+    /// Maximum number of cached responses across all routes.
+    pub max_entries: usize,
+}
+
+impl Default for ResponseCacheSettings {
+    fn default() -> Self {
+        Self {
+            routes: BTreeMap::new(),
+            max_entries: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RouteCacheSettings {
+    #[serde(with = "units::duration")]
+    pub ttl: Duration,
+    /// Request headers responses vary by, e.g. `accept`.
+    #[serde(deserialize_with = "list")]
+    pub vary: Vec<String>,
+}
+
+impl Default for RouteCacheSettings {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(60),
+            vary: vec!["accept".to_string(), "accept-encoding".to_string()],
+        }
+    }
+}
+
+/// Cookie based sessions, see [crate::sessions]; enabled if a `secret` is configured.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SessionSettings {
+    pub cookie_name: String,
+    /// Key for signing session cookies, at least 32 bytes.
+    pub secret: Option<Secret<String>>,
+    /// How long sessions last without requests.
+    #[serde(with = "units::duration")]
+    pub ttl: Duration,
+    /// Whether cookies are only sent via HTTPS.
+    pub secure: bool,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            cookie_name: "session".to_string(),
+            secret: None,
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: true,
+        }
+    }
+}
+
+/// CSRF protection for session authenticated requests to routes below the given path prefixes,
+/// e.g. `/app`, see [crate::csrf]; none disables it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CsrfSettings {
+    #[serde(deserialize_with = "list")]
+    pub paths: Vec<String>,
+    /// Cookie carrying the token, readable by scripts.
+    pub cookie_name: String,
+    /// Header state-changing requests must repeat the token in.
+    pub header: String,
+    /// Whether the cookie is only sent via HTTPS.
+    pub secure: bool,
+}
+
+impl Default for CsrfSettings {
+    fn default() -> Self {
+        Self {
+            paths: vec![],
+            cookie_name: "csrf_token".to_string(),
+            header: "x-csrf-token".to_string(),
+            secure: true,
+        }
+    }
+}
+
+/// Verification of HMAC signatures of inbound webhooks, see [crate::webhook_signature].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WebhookSignatureSettings {
+    pub secret: Secret<String>,
+    pub algorithm: SignatureAlgorithm,
+    /// Header carrying the hex encoded signature.
+    pub header: String,
+    /// Prefix of the signature, e.g. `sha256=` for GitHub.
+    pub prefix: String,
+    /// Header carrying the Unix time of signing; if defined, `<timestamp>.<body>` is signed
+    /// instead of the body and the timestamp must be within the `tolerance`. Timestamps within
+    /// the signature header, e.g. `t=<timestamp>,v1=<signature>`, are not supported.
+    pub timestamp_header: Option<String>,
+    #[serde(with = "units::duration")]
+    pub tolerance: Duration,
+}
+
+impl Default for WebhookSignatureSettings {
+    fn default() -> Self {
+        Self {
+            secret: Default::default(),
+            algorithm: SignatureAlgorithm::HmacSha256,
+            header: "x-signature".to_string(),
+            prefix: String::new(),
+            timestamp_header: None,
+            tolerance: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureAlgorithm {
+    HmacSha256,
+}
+
+/// Outbound webhooks, see [crate::webhooks].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WebhooksSettings {
+    /// Subscribers by name.
+    pub subscribers: BTreeMap<String, SubscriberSettings>,
+    /// Retries of failed deliveries; `max_retries` and backoffs are per delivery.
+    pub retry: RetrySettings,
+    /// Maximum number of deliveries kept for inspection.
+    pub max_deliveries: usize,
+}
+
+impl Default for WebhooksSettings {
+    fn default() -> Self {
+        Self {
+            subscribers: BTreeMap::new(),
+            retry: RetrySettings {
+                max_retries: 8,
+                initial_backoff: Duration::from_secs(1),
+                max_backoff: Duration::from_secs(10 * 60),
+            },
+            max_deliveries: 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SubscriberSettings {
+    /// Plain HTTP URL, see [crate::http_client].
+    pub url: String,
+    /// Key for signing the payloads, shared with the subscriber.
+    pub secret: Secret<String>,
+    /// Event types to deliver; none means all.
+    #[serde(deserialize_with = "list")]
+    pub events: Vec<String>,
+}
+
+/// Audit logging, see [crate::audit].
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AuditSettings {
+    /// File the records are appended to as JSON lines; if not defined, they are logged.
+    pub path: Option<PathBuf>,
+}
+
+/// Restricts the client addresses for requests to the paths with the given prefixes: addresses
+/// within `deny` are rejected, and if `allow` is not empty, all addresses not within it.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IpFilterSettings {
+    /// Path prefixes, e.g. `/admin`.
+    #[serde(deserialize_with = "list")]
+    pub paths: Vec<String>,
+    #[serde(deserialize_with = "list")]
+    pub allow: Vec<Cidr>,
+    #[serde(deserialize_with = "list")]
+    pub deny: Vec<Cidr>,
+}
+
+/// Headers added to all responses not having them already, see [crate::security_headers]; empty
+/// values omit the respective header, e.g. `hsts` for environments without TLS.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SecurityHeadersSettings {
+    pub enabled: bool,
+    /// `Strict-Transport-Security`
+    pub hsts: String,
+    /// `X-Content-Type-Options`
+    pub content_type_options: String,
+    /// `Referrer-Policy`
+    pub referrer_policy: String,
+    /// `Content-Security-Policy`
+    pub content_security_policy: String,
+    /// `Permissions-Policy`
+    pub permissions_policy: String,
+}
+
+impl Default for SecurityHeadersSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            hsts: "max-age=31536000; includeSubDomains".to_string(),
+            content_type_options: "nosniff".to_string(),
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            content_security_policy: "default-src 'self'; frame-ancestors 'none'".to_string(),
+            permissions_policy: "camera=(), geolocation=(), microphone=()".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProxySettings {
+    /// Absolute http URL, optionally with a path prefix, e.g. `http://legacy:8080/api`.
+    pub upstream: String,
+    /// Whether to remove the path prefix before appending the path to the upstream URL.
+    pub strip_prefix: bool,
+    /// Whether to forward the `Host` header instead of setting it to the upstream authority.
+    pub preserve_host: bool,
+}
+
+impl Default for ProxySettings {
+    fn default() -> Self {
+        Self {
+            upstream: String::new(),
+            strip_prefix: true,
+            preserve_host: false,
+        }
+    }
+}
+
+/// Mirroring of requests to a shadow upstream, see [crate::mirror].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MirrorSettings {
+    /// Absolute http URL of the shadow upstream, e.g. `http://rewrite:8080`; if not defined,
+    /// requests are not mirrored.
+    pub upstream: Option<String>,
+    /// Fraction of requests mirrored, e.g. `0.1`.
+    pub sample_rate: f64,
+    /// Path prefixes of the mirrored requests; all if empty.
+    #[serde(deserialize_with = "list")]
+    pub paths: Vec<String>,
+    /// Requests with larger or unknown body size are not mirrored, e.g. `"64KiB"` or in bytes.
+    #[serde(deserialize_with = "units::byte_size")]
+    pub max_body_size: usize,
+    /// Maximum number of mirrored requests in flight; further ones are dropped.
+    pub max_concurrent: usize,
+    /// Whether the `Authorization`, `Proxy-Authorization`, `Cookie` and `x-api-key` headers are
+    /// sent to the shadow upstream as well.
+    pub forward_credentials: bool,
+}
+
+impl Default for MirrorSettings {
+    fn default() -> Self {
+        Self {
+            upstream: None,
+            sample_rate: 1.0,
+            paths: vec![],
+            max_body_size: 64 * 1024,
+            max_concurrent: 100,
+            forward_credentials: false,
+        }
+    }
+}
+
+/// Routing of requests to a canary variant, see [crate::canary].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CanarySettings {
+    /// Path prefixes of the routed requests; if empty, all requests use the stable variant.
+    #[serde(deserialize_with = "list")]
+    pub paths: Vec<String>,
+    /// Header selecting the variant: the canary if its value is `value`, else the stable one.
+    pub header: String,
+    /// Cookie selecting the variant like `header`, which takes precedence.
+    pub cookie: String,
+    pub value: String,
+    /// Fraction of requests without header or cookie routed to the canary, bucketed by client
+    /// address, e.g. `0.05`.
+    pub fraction: f64,
+    /// Absolute http URL canary requests are forwarded to, see [crate::proxy]; if not defined,
+    /// they are handled by this service, which can select the implementation via the variant.
+    pub upstream: Option<String>,
+}
+
+impl Default for CanarySettings {
+    fn default() -> Self {
+        Self {
+            paths: vec![],
+            header: "x-canary".to_string(),
+            cookie: "canary".to_string(),
+            value: "true".to_string(),
+            fraction: 0.0,
+            upstream: None,
+        }
+    }
+}
+
+/// The Tokio runtime, see [crate::runtime]; changes require a restart. Undefined values use the
+/// Tokio defaults, e.g. one worker thread per core.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RuntimeSettings {
+    /// Number of worker threads, e.g. fewer than cores for CPU-pinned deployments.
+    pub worker_threads: Option<usize>,
+    /// Maximum number of threads for blocking operations like `spawn_blocking`.
+    pub max_blocking_threads: Option<usize>,
+    /// Stack size of worker and blocking threads, e.g. `"4MiB"` or in bytes.
+    #[serde(default, deserialize_with = "units::option_byte_size")]
+    pub thread_stack_size: Option<usize>,
+    /// Prefix of the thread names, followed by a sequence number, e.g. `bayer-axum-1`.
+    pub thread_name_prefix: String,
+}
+
+impl Default for RuntimeSettings {
+    fn default() -> Self {
+        Self {
+            worker_threads: None,
+            max_blocking_threads: None,
+            thread_stack_size: None,
+            thread_name_prefix: "bayer-axum".to_string(),
+        }
+    }
+}
+
+/// The pool for CPU-bound work, see [crate::compute].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ComputeSettings {
+    /// Number of threads; if not defined, one per core.
+    pub threads: Option<usize>,
+    /// Number of tasks waiting for a free thread before further ones are rejected; if 0, tasks
+    /// are only accepted if a thread is free.
+    pub queue_size: usize,
+    /// `Retry-After` for rejected tasks, e.g. `"1s"`.
+    #[serde(with = "units::duration")]
+    pub retry_after: Duration,
+}
+
+impl Default for ComputeSettings {
+    fn default() -> Self {
+        Self {
+            threads: None,
+            queue_size: 64,
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Flatten the given value into dotted keys, e.g. `logging.filter`, and values other than
+/// objects.
+fn with_env_and_overrides(
+    config: ConfigBuilder<DefaultState>,
+    overrides: &[(&str, String)],
+) -> Result<ConfigBuilder<DefaultState>, ConfigError> {
+    let config = config.add_source(Environment::with_prefix("app").separator("__"));
+    overrides.iter().try_fold(config, |config, (key, value)| {
+        config.set_override(*key, value.as_str())
+    })
+}
+
+/// The given section of the configuration, needed before it can be loaded completely, or its
+/// defaults if it is not defined.
+fn section<T>(config: &ConfigBuilder<DefaultState>, key: &str) -> Result<T>
+where
+    T: Default + for<'de> Deserialize<'de>,
+{
+    match config.build_cloned()?.get(key) {
+        Err(ConfigError::NotFound(_)) => Ok(T::default()),
+        section => Ok(section?),
+    }
+}
+
+pub(crate) fn flatten(key: String, value: Value, values: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map {
+                let key = if key.is_empty() {
+                    name
+                } else {
+                    format!("{key}.{name}")
+                };
+                flatten(key, value, values);
+            }
+        }
+        value => values.push((key, value)),
+    }
+}
+
+/// Lists either as sequences or as comma-separated strings, e.g. from environment variables,
+/// which cannot express sequences. Commas within values are escaped with a backslash, e.g.
+/// `a\,b,c`.
+fn list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct ListVisitor<T>(PhantomData<T>);
+
+    impl<'de, T> Visitor<'de> for ListVisitor<T>
+    where
+        T: Deserialize<'de>,
+    {
+        type Value = Vec<T>;
+
+        fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            write!(f, "a list or a comma-separated string")
+        }
+
+        fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            split_list(s)
+                .into_iter()
+                .map(|value| T::deserialize(value.into_deserializer()))
+                .collect()
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut values = Vec::new();
+            while let Some(value) = seq.next_element()? {
+                values.push(value);
+            }
+            Ok(values)
+        }
+    }
+
+    deserializer.deserialize_any(ListVisitor(PhantomData))
+}
+
+fn split_list(s: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next()),
+            ',' => values.push(mem::take(&mut value)),
+            c => value.push(c),
+        }
+    }
+    values.push(value);
+    values
+        .into_iter()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Json,
+    Pretty,
+    Compact,
+}
+
+impl LogFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogFormat::Json => "json",
+            LogFormat::Pretty => "pretty",
+            LogFormat::Compact => "compact",
+        }
+    }
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Json
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = ParseLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            _ => Err(ParseLogFormatError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseLogFormatError;
+
+impl Display for ParseLogFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Log format must be one of json, pretty or compact")
+    }
+}
+
+impl std::error::Error for ParseLogFormatError {}
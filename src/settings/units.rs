@@ -0,0 +1,172 @@
+//! Human-friendly durations, e.g. `"30s"` or `"1h30m"`, and byte sizes, e.g. `"10MiB"`.
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::fmt::{self, Formatter};
+
+/// Durations as a sequence of numbers with units `ms`, `s`, `m`, `h` or `d`, e.g. `"1m30s"`; use
+/// with `#[serde(with = "units::duration")]`.
+pub mod duration {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format(*duration))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse(&s).map_err(serde::de::Error::custom)
+    }
+
+    pub fn parse(s: &str) -> Result<Duration, String> {
+        let invalid = || format!("invalid duration {s}, expected e.g. 30s or 1h30m");
+
+        let mut millis = 0_u64;
+        let mut rest = s.trim();
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .ok_or_else(invalid)?;
+            let n = rest[..digits].parse::<u64>().map_err(|_| invalid())?;
+            rest = &rest[digits..];
+            let unit = rest
+                .find(|c: char| c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let factor = match &rest[..unit] {
+                "ms" => 1,
+                "s" => 1_000,
+                "m" => 60 * 1_000,
+                "h" => 60 * 60 * 1_000,
+                "d" => 24 * 60 * 60 * 1_000,
+                _ => return Err(invalid()),
+            };
+            millis = n
+                .checked_mul(factor)
+                .and_then(|n| millis.checked_add(n))
+                .ok_or_else(invalid)?;
+            rest = &rest[unit..];
+        }
+        Ok(Duration::from_millis(millis))
+    }
+
+    /// The largest unit which represents the given duration exactly, e.g. `"90s"`.
+    pub fn format(duration: Duration) -> String {
+        let millis = duration.as_millis();
+        [
+            ("d", 24 * 60 * 60 * 1_000),
+            ("h", 60 * 60 * 1_000),
+            ("m", 60 * 1_000),
+            ("s", 1_000),
+        ]
+        .iter()
+        .find(|(_, factor)| millis != 0 && millis % factor == 0)
+        .map(|(unit, factor)| format!("{}{unit}", millis / factor))
+        .unwrap_or_else(|| format!("{millis}ms"))
+    }
+}
+
+/// Optional durations, see [duration]; use with
+/// `#[serde(default, with = "units::option_duration")]`.
+pub mod option_duration {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match duration {
+            Some(duration) => serializer.serialize_str(&super::duration::format(*duration)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| super::duration::parse(&s).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// Byte sizes as a number of bytes or a number with unit `B`, `kB`, `MB`, `GB` (powers of 1000)
+/// or `KiB`, `MiB`, `GiB` (powers of 1024), e.g. `"10MiB"`; use with
+/// `#[serde(deserialize_with = "units::byte_size")]`.
+pub fn byte_size<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct ByteSizeVisitor;
+
+    impl<'de> Visitor<'de> for ByteSizeVisitor {
+        type Value = usize;
+
+        fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            write!(f, "a number of bytes or a size like 10MiB")
+        }
+
+        fn visit_u64<E>(self, n: u64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            usize::try_from(n).map_err(E::custom)
+        }
+
+        fn visit_i64<E>(self, n: i64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            usize::try_from(n).map_err(E::custom)
+        }
+
+        fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            parse_byte_size(s).ok_or_else(|| E::custom(format!("invalid byte size {s}")))
+        }
+    }
+
+    deserializer.deserialize_any(ByteSizeVisitor)
+}
+
+/// Optional byte sizes, see [byte_size]; use with
+/// `#[serde(default, deserialize_with = "units::option_byte_size")]`.
+pub fn option_byte_size<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct ByteSize(#[serde(deserialize_with = "byte_size")] usize);
+
+    Option::<ByteSize>::deserialize(deserializer).map(|size| size.map(|ByteSize(size)| size))
+}
+
+fn parse_byte_size(s: &str) -> Option<usize> {
+    let s = s.trim();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n = s[..digits].parse::<usize>().ok()?;
+    let factor = match s[digits..].trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return None,
+    };
+    n.checked_mul(factor)
+}
@@ -0,0 +1,682 @@
+//! Validation of constraints [Settings] cannot express via their types, reporting all violations
+//! at once instead of only the first one.
+
+use super::{AwsSecretsSource, RateLimitSettings, Settings, TenantStrategy};
+use crate::aws_secrets::is_loopback;
+use crate::scheduler::cron::Schedule;
+use anyhow::{bail, Result};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::Uri;
+use serde_json::Value;
+
+const ANY: &str = "*";
+
+/// Keys which took a number of seconds or milliseconds, where `*` matches any map key, and what
+/// their last segment was renamed to when they were changed to take durations.
+const RENAMED_KEYS: &[(&str, &str)] = &[
+    ("server.startup_timeout_secs", "startup_timeout"),
+    ("server.shutdown_timeout_secs", "shutdown_timeout"),
+    ("server.request_timeout_secs", "request_timeout"),
+    ("server.tcp.keepalive_secs", "keepalive"),
+    ("cors.max_age_secs", "max_age"),
+    ("events.heartbeat_interval_secs", "heartbeat_interval"),
+    ("http_client.timeout_secs", "timeout"),
+    ("http_client.pool_idle_timeout_secs", "pool_idle_timeout"),
+    (
+        "http_client.retry.initial_backoff_millis",
+        "initial_backoff",
+    ),
+    ("http_client.retry.max_backoff_millis", "max_backoff"),
+    ("http_client.circuit_breaker.open_secs", "open_duration"),
+    ("http_client.targets.*.timeout_secs", "timeout"),
+    ("vault.timeout_secs", "timeout"),
+    ("webhooks.retry.initial_backoff_millis", "initial_backoff"),
+    ("webhooks.retry.max_backoff_millis", "max_backoff"),
+];
+
+/// Keys, with `*` matching any segment, of settings which are not supported and the reason why.
+const UNSUPPORTED_KEYS: &[(&str, &str)] = &[
+    (
+        "auth.jwks_url",
+        "is not supported, because JWKS documents cannot be fetched via TLS",
+    ),
+    (
+        "auth.keys.*.public_key",
+        "is not supported, only HS256 keys are",
+    ),
+];
+
+/// Reject the given raw settings values if they use any of the [RENAMED_KEYS] or the
+/// [UNSUPPORTED_KEYS], which would otherwise be ignored silently.
+pub(super) fn check_renamed_keys(values: &Value) -> Result<()> {
+    let mut violations = Violations::default();
+    for (pattern, renamed) in RENAMED_KEYS {
+        let pattern = pattern.split('.').collect::<Vec<_>>();
+        for key in matching_keys(values, &pattern) {
+            let (parent, _) = key.rsplit_once('.').unwrap_or_default();
+            violations.push(
+                &key,
+                format!("was renamed to {parent}.{renamed}, which takes a duration like 30s"),
+            );
+        }
+    }
+    for (pattern, reason) in UNSUPPORTED_KEYS {
+        let pattern = pattern.split('.').collect::<Vec<_>>();
+        for key in matching_keys(values, &pattern) {
+            violations.push(&key, *reason);
+        }
+    }
+    violations.into_result()
+}
+
+/// The dotted keys of the given values matching the given pattern segments.
+fn matching_keys(values: &Value, pattern: &[&str]) -> Vec<String> {
+    let (segment, rest) = match pattern.split_first() {
+        Some(split) => split,
+        None => return vec![String::new()],
+    };
+    let object = match values {
+        Value::Object(object) => object,
+        _ => return vec![],
+    };
+    object
+        .iter()
+        .filter(|(key, _)| *segment == "*" || key == segment)
+        .flat_map(|(key, value)| {
+            matching_keys(value, rest).into_iter().map(move |rest| {
+                if rest.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{key}.{rest}")
+                }
+            })
+        })
+        .collect()
+}
+
+impl Settings {
+    /// Check cross-field constraints, e.g. that the admin port differs from the server port; the
+    /// error lists all violations by dotted key.
+    pub fn validate(&self) -> Result<()> {
+        let mut violations = Violations::default();
+
+        let server = &self.server;
+        if server.tcp_enabled {
+            violations.check(!server.addr.is_empty(), "server.addr", "must not be empty");
+            violations.check(server.port != 0, "server.port", "must not be 0");
+        } else {
+            violations.check(
+                server.uds_path.is_some(),
+                "server.uds_path",
+                "must be defined if server.tcp_enabled is false",
+            );
+        }
+        violations.check(
+            !server.startup_timeout.is_zero(),
+            "server.startup_timeout",
+            "must not be 0",
+        );
+        violations.check(
+            !server.request_timeout.is_zero(),
+            "server.request_timeout",
+            "must not be 0",
+        );
+        violations.check(
+            server.max_body_size != 0,
+            "server.max_body_size",
+            "must not be 0",
+        );
+        violations.check(
+            server.max_concurrent_requests != Some(0),
+            "server.max_concurrent_requests",
+            "must not be 0",
+        );
+        let proxy_protocol = &server.tcp.proxy_protocol;
+        if proxy_protocol.enabled {
+            violations.check(
+                !server.trusted_proxies.is_empty(),
+                "server.trusted_proxies",
+                "must not be empty if server.tcp.proxy_protocol.enabled is true",
+            );
+            violations.check(
+                !proxy_protocol.timeout.is_zero(),
+                "server.tcp.proxy_protocol.timeout",
+                "must not be 0",
+            );
+        }
+
+        let ports = [
+            ("admin.port", self.admin.port),
+            ("telemetry.metrics_port", self.telemetry.metrics_port),
+        ];
+        for (i, (key, port)) in ports.iter().enumerate() {
+            if let Some(port) = port {
+                violations.check(*port != 0, key, "must not be 0");
+                violations.check(
+                    !server.tcp_enabled || *port != server.port,
+                    key,
+                    "must differ from server.port",
+                );
+                for (other_key, other_port) in &ports[..i] {
+                    violations.check(
+                        Some(*port) != *other_port,
+                        key,
+                        format!("must differ from {other_key}"),
+                    );
+                }
+            }
+        }
+
+        for (route, sample_rate) in &self.logging.access_log.sample_rates {
+            violations.check(
+                (0.0..=1.0).contains(sample_rate),
+                &format!("logging.access_log.sample_rates.{route}"),
+                "must be between 0 and 1",
+            );
+        }
+
+        let body_capture = &self.logging.body_capture;
+        for route in &body_capture.routes {
+            violations.check(
+                route.starts_with('/'),
+                "logging.body_capture.routes",
+                format!("{route} does not start with /"),
+            );
+        }
+        violations.check(
+            body_capture.max_size > 0,
+            "logging.body_capture.max_size",
+            "must not be 0",
+        );
+
+        let cors = &self.cors;
+        let any_origin = cors.allowed_origins.iter().any(|origin| origin == ANY);
+        violations.check(
+            !(any_origin && cors.allow_credentials),
+            "cors.allow_credentials",
+            "must not be true if any origin is allowed",
+        );
+        for origin in cors.allowed_origins.iter().filter(|origin| *origin != ANY) {
+            let valid = origin
+                .parse::<Uri>()
+                .map(|uri| uri.scheme().is_some() && uri.host().is_some())
+                .unwrap_or_default();
+            violations.check(
+                valid,
+                "cors.allowed_origins",
+                format!("{origin} is no origin like https://example.com"),
+            );
+        }
+
+        for (id, api_key) in &self.api_keys {
+            violations.check(
+                !api_key.key.expose().is_empty(),
+                &format!("api_keys.{id}.key"),
+                "must not be empty",
+            );
+            if let Some(rate_limit) = &api_key.rate_limit {
+                violations.check_rate_limit(&format!("api_keys.{id}.rate_limit"), rate_limit);
+            }
+        }
+        for (kid, jwt_key) in &self.auth.keys {
+            let key = format!("auth.keys.{kid}");
+            match &jwt_key.secret {
+                Some(secret) => violations.check(
+                    secret.expose().len() >= 32,
+                    &format!("{key}.secret"),
+                    "must have at least 32 bytes",
+                ),
+                None => violations.push(&format!("{key}.secret"), "must be defined"),
+            }
+        }
+        if let Some(rate_limit) = &self.rate_limiting.global {
+            violations.check_rate_limit("rate_limiting.global", rate_limit);
+        }
+        for (route, rate_limit) in &self.rate_limiting.routes {
+            violations.check_rate_limit(&format!("rate_limiting.routes.{route}"), rate_limit);
+        }
+
+        violations.check(
+            !self.events.heartbeat_interval.is_zero(),
+            "events.heartbeat_interval",
+            "must not be 0",
+        );
+
+        for (name, job) in &self.scheduler.jobs {
+            if let Err(e) = job.schedule.parse::<Schedule>() {
+                violations.push(&format!("scheduler.jobs.{name}.schedule"), format!("{e:#}"));
+            }
+        }
+
+        let http_client = &self.http_client;
+        violations.check(
+            !http_client.timeout.is_zero(),
+            "http_client.timeout",
+            "must not be 0",
+        );
+        violations.check(
+            http_client.retry.initial_backoff <= http_client.retry.max_backoff,
+            "http_client.retry.initial_backoff",
+            "must not exceed http_client.retry.max_backoff",
+        );
+        violations.check(
+            http_client.circuit_breaker.failure_threshold != 0,
+            "http_client.circuit_breaker.failure_threshold",
+            "must not be 0",
+        );
+
+        violations.check(
+            !self.idempotency.ttl.is_zero(),
+            "idempotency.ttl",
+            "must not be 0",
+        );
+
+        if self.tenancy.strategy == Some(TenantStrategy::Subdomain) {
+            violations.check(
+                self.tenancy.domain.is_some(),
+                "tenancy.domain",
+                "must be defined for the subdomain strategy",
+            );
+        }
+        if self.tenancy.strategy == Some(TenantStrategy::Header) {
+            violations.check(
+                HeaderName::from_bytes(self.tenancy.header.as_bytes()).is_ok(),
+                "tenancy.header",
+                "must be a valid header name",
+            );
+        }
+        if let Some(TenantStrategy::Claim { name }) = &self.tenancy.strategy {
+            violations.check(
+                !name.is_empty(),
+                "tenancy.strategy.claim.name",
+                "must not be empty",
+            );
+            violations.check(
+                self.auth.keys.values().any(|key| key.secret.is_some()),
+                "tenancy.strategy",
+                "requires auth.keys for the claim strategy",
+            );
+        }
+
+        for (name, flag) in &self.features {
+            violations.check(
+                flag.rollout_percent <= 100,
+                &format!("features.{name}.rollout_percent"),
+                "must not exceed 100",
+            );
+        }
+
+        violations.check(
+            !self.health.refresh_interval.is_zero(),
+            "health.refresh_interval",
+            "must not be 0",
+        );
+        violations.check(
+            !self.health.timeout.is_zero(),
+            "health.timeout",
+            "must not be 0",
+        );
+        for (name, check) in &self.health.http {
+            let valid = check
+                .url
+                .parse::<Uri>()
+                .map(|uri| uri.scheme_str() == Some("http") && uri.host().is_some())
+                .unwrap_or_default();
+            violations.check(
+                valid,
+                &format!("health.http.{name}.url"),
+                "must be an http URL, e.g. http://localhost:8080/healthz",
+            );
+        }
+
+        let uploads = &self.uploads;
+        violations.check(
+            uploads.max_file_size != 0,
+            "uploads.max_file_size",
+            "must not be 0",
+        );
+        violations.check(
+            uploads.max_file_size <= uploads.max_total_size,
+            "uploads.max_file_size",
+            "must not exceed uploads.max_total_size",
+        );
+
+        if let Some(s3) = &self.storage.s3 {
+            violations.check(
+                !s3.bucket.is_empty(),
+                "storage.s3.bucket",
+                "must not be empty",
+            );
+            let endpoint = s3.endpoint.parse::<Uri>();
+            violations.check(
+                endpoint.map_or(false, |endpoint| {
+                    endpoint.scheme_str() == Some("http") && endpoint.host().is_some()
+                }),
+                "storage.s3.endpoint",
+                "must be an absolute http URL",
+            );
+            // S3 requires all parts but the last one to have at least 5 MiB.
+            violations.check(
+                s3.part_size >= 5 * 1024 * 1024,
+                "storage.s3.part_size",
+                "must be at least 5MiB",
+            );
+        }
+
+        violations.check(
+            self.response_cache.max_entries != 0,
+            "response_cache.max_entries",
+            "must not be 0",
+        );
+        for (route, cache) in &self.response_cache.routes {
+            violations.check(
+                !cache.ttl.is_zero(),
+                &format!("response_cache.routes.{route}.ttl"),
+                "must not be 0",
+            );
+            for name in &cache.vary {
+                violations.check(
+                    HeaderName::from_bytes(name.as_bytes()).is_ok(),
+                    &format!("response_cache.routes.{route}.vary"),
+                    format!("{name} is no valid header name"),
+                );
+            }
+        }
+
+        let sessions = &self.sessions;
+        violations.check(
+            is_cookie_name(&sessions.cookie_name),
+            "sessions.cookie_name",
+            "must be a valid cookie name",
+        );
+        if let Some(secret) = &sessions.secret {
+            violations.check(
+                secret.expose().len() >= 32,
+                "sessions.secret",
+                "must have at least 32 bytes",
+            );
+        }
+        violations.check(!sessions.ttl.is_zero(), "sessions.ttl", "must not be 0");
+
+        let csrf = &self.csrf;
+        if !csrf.paths.is_empty() {
+            violations.check(
+                sessions.secret.is_some(),
+                "csrf.paths",
+                "require sessions.secret to be defined",
+            );
+        }
+        for path in &csrf.paths {
+            violations.check(
+                path.starts_with('/'),
+                "csrf.paths",
+                format!("{path} does not start with /"),
+            );
+        }
+        violations.check(
+            is_cookie_name(&csrf.cookie_name) && csrf.cookie_name != sessions.cookie_name,
+            "csrf.cookie_name",
+            "must be a valid cookie name other than sessions.cookie_name",
+        );
+        violations.check(
+            HeaderName::from_bytes(csrf.header.as_bytes()).is_ok(),
+            "csrf.header",
+            "must be a valid header name",
+        );
+
+        for (route, webhook) in &self.webhook_signatures {
+            let key = format!("webhook_signatures.{route}");
+            violations.check(
+                !webhook.secret.expose().is_empty(),
+                &format!("{key}.secret"),
+                "must not be empty",
+            );
+            violations.check(
+                HeaderName::from_bytes(webhook.header.as_bytes()).is_ok(),
+                &format!("{key}.header"),
+                "must be a valid header name",
+            );
+            if let Some(header) = &webhook.timestamp_header {
+                violations.check(
+                    HeaderName::from_bytes(header.as_bytes()).is_ok(),
+                    &format!("{key}.timestamp_header"),
+                    "must be a valid header name",
+                );
+            }
+        }
+
+        violations.check(
+            self.webhooks.max_deliveries != 0,
+            "webhooks.max_deliveries",
+            "must not be 0",
+        );
+        for (name, subscriber) in &self.webhooks.subscribers {
+            let key = format!("webhooks.subscribers.{name}");
+            let url = subscriber.url.parse::<Uri>();
+            violations.check(
+                url.map_or(false, |url| {
+                    url.scheme_str() == Some("http") && url.host().is_some()
+                }),
+                &format!("{key}.url"),
+                "must be an absolute http URL",
+            );
+            violations.check(
+                !subscriber.secret.expose().is_empty(),
+                &format!("{key}.secret"),
+                "must not be empty",
+            );
+        }
+
+        let security_headers = &self.security_headers;
+        let security_headers = [
+            ("hsts", &security_headers.hsts),
+            (
+                "content_type_options",
+                &security_headers.content_type_options,
+            ),
+            ("referrer_policy", &security_headers.referrer_policy),
+            (
+                "content_security_policy",
+                &security_headers.content_security_policy,
+            ),
+            ("permissions_policy", &security_headers.permissions_policy),
+        ];
+        for (key, value) in security_headers {
+            violations.check(
+                HeaderValue::from_str(value).is_ok(),
+                &format!("security_headers.{key}"),
+                "must be a valid header value",
+            );
+        }
+
+        for (prefix, proxy) in &self.proxy {
+            let key = format!("proxy.{prefix}");
+            violations.check(
+                prefix.starts_with('/') && prefix.len() > 1,
+                &key,
+                "must start with / and not be /",
+            );
+            let upstream = proxy.upstream.parse::<Uri>();
+            violations.check(
+                upstream.map_or(false, |upstream| {
+                    upstream.scheme_str() == Some("http") && upstream.host().is_some()
+                }),
+                &format!("{key}.upstream"),
+                "must be an absolute http URL",
+            );
+        }
+
+        let mirror = &self.mirror;
+        if let Some(upstream) = &mirror.upstream {
+            let upstream = upstream.parse::<Uri>();
+            violations.check(
+                upstream.map_or(false, |upstream| {
+                    upstream.scheme_str() == Some("http") && upstream.host().is_some()
+                }),
+                "mirror.upstream",
+                "must be an absolute http URL",
+            );
+        }
+        violations.check(
+            (0.0..=1.0).contains(&mirror.sample_rate),
+            "mirror.sample_rate",
+            "must be between 0 and 1",
+        );
+        violations.check(
+            mirror.max_concurrent != 0,
+            "mirror.max_concurrent",
+            "must not be 0",
+        );
+
+        let canary = &self.canary;
+        violations.check(
+            HeaderName::from_bytes(canary.header.as_bytes()).is_ok(),
+            "canary.header",
+            "must be a valid header name",
+        );
+        violations.check(
+            is_cookie_name(&canary.cookie),
+            "canary.cookie",
+            "must be a valid cookie name",
+        );
+        violations.check(
+            (0.0..=1.0).contains(&canary.fraction),
+            "canary.fraction",
+            "must be between 0 and 1",
+        );
+        if let Some(upstream) = &canary.upstream {
+            let upstream = upstream.parse::<Uri>();
+            violations.check(
+                upstream.map_or(false, |upstream| {
+                    upstream.scheme_str() == Some("http") && upstream.host().is_some()
+                }),
+                "canary.upstream",
+                "must be an absolute http URL",
+            );
+        }
+
+        for (name, filter) in &self.ip_filters {
+            let key = format!("ip_filters.{name}");
+            violations.check(
+                !filter.paths.is_empty(),
+                &format!("{key}.paths"),
+                "must not be empty",
+            );
+            for path in &filter.paths {
+                violations.check(
+                    path.starts_with('/'),
+                    &format!("{key}.paths"),
+                    format!("{path} does not start with /"),
+                );
+            }
+            violations.check(
+                !filter.allow.is_empty() || !filter.deny.is_empty(),
+                &key,
+                "must define allow or deny",
+            );
+        }
+
+        violations.check(
+            self.static_files.path.starts_with('/'),
+            "static_files.path",
+            "must start with /",
+        );
+
+        let runtime = &self.runtime;
+        let values = [
+            ("runtime.worker_threads", runtime.worker_threads),
+            ("runtime.max_blocking_threads", runtime.max_blocking_threads),
+            ("runtime.thread_stack_size", runtime.thread_stack_size),
+            ("compute.threads", self.compute.threads),
+        ];
+        for (key, n) in values {
+            violations.check(n != Some(0), key, "must not be 0");
+        }
+
+        if let Some(addr) = &self.vault.addr {
+            let valid = addr
+                .parse::<Uri>()
+                .map(|uri| uri.scheme_str() == Some("http") && uri.host().is_some())
+                .unwrap_or_default();
+            violations.check(
+                valid,
+                "vault.addr",
+                "must be an http URL, e.g. http://127.0.0.1:8200",
+            );
+        }
+
+        violations.check(
+            self.aws.access_key_id.is_some() == self.aws.secret_access_key.is_some(),
+            "aws.access_key_id",
+            "must be defined together with aws.secret_access_key",
+        );
+        let aws_secrets = &self.aws_secrets;
+        if let Some(source) = aws_secrets.source {
+            // The secrets are received in cleartext, hence must not cross the network.
+            let endpoint = aws_secrets.endpoint.as_deref().map(str::parse::<Uri>);
+            violations.check(
+                matches!(endpoint, Some(Ok(endpoint)) if is_loopback(&endpoint)),
+                "aws_secrets.endpoint",
+                "must be an http URL on the loopback interface, e.g. http://localhost:4566",
+            );
+            violations.check(
+                !aws_secrets.prefix.is_empty(),
+                "aws_secrets.prefix",
+                "must not be empty",
+            );
+            if source == AwsSecretsSource::Ssm {
+                violations.check(
+                    aws_secrets.prefix.starts_with('/'),
+                    "aws_secrets.prefix",
+                    "must start with / for ssm",
+                );
+            }
+        }
+
+        violations.into_result()
+    }
+}
+
+/// Whether the given name is a token as required for cookie names by RFC 6265.
+fn is_cookie_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[derive(Default)]
+struct Violations(Vec<String>);
+
+impl Violations {
+    fn check(&mut self, valid: bool, key: &str, message: impl AsRef<str>) {
+        if !valid {
+            self.push(key, message);
+        }
+    }
+
+    fn check_rate_limit(&mut self, key: &str, rate_limit: &RateLimitSettings) {
+        self.check(
+            rate_limit.per_second != 0,
+            &format!("{key}.per_second"),
+            "must not be 0",
+        );
+        self.check(
+            rate_limit.burst != 0,
+            &format!("{key}.burst"),
+            "must not be 0",
+        );
+    }
+
+    fn push(&mut self, key: &str, message: impl AsRef<str>) {
+        self.0.push(format!("{key}: {}", message.as_ref()));
+    }
+
+    fn into_result(self) -> Result<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            bail!("Invalid settings: {}", self.0.join("; "))
+        }
+    }
+}
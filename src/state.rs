@@ -0,0 +1,184 @@
+//! Application state shared by all handlers, which get it as `Extension<AppState>`.
+
+use crate::audit::{AuditSink, Auditor, FileAuditSink, LogAuditSink};
+use crate::blob_store::s3::S3BlobStore;
+use crate::blob_store::{BlobStore, LocalBlobStore};
+use crate::compute::Compute;
+use crate::domain_events::DomainEvents;
+use crate::events::EventBus;
+use crate::features::Features;
+use crate::health::Startup;
+use crate::http_client::HttpClient;
+use crate::idempotency::{IdempotencyStore, InMemoryIdempotencyStore};
+use crate::maintenance::Maintenance;
+use crate::rate_limit::RouteRateLimits;
+use crate::response_cache::ResponseCache;
+use crate::sessions::{InMemorySessionStore, SessionStore};
+use crate::settings::Settings;
+use crate::telemetry::FilterHandle;
+use crate::webhooks::{DeliveryStore, InMemoryDeliveryStore, Webhooks};
+use anyhow::Result;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Cheap to clone, all components are shared.
+#[derive(Debug, Clone)]
+pub struct AppState {
+    /// Snapshot of the [Settings] at startup.
+    pub settings: Arc<Settings>,
+    pub http_client: HttpClient,
+    pub event_bus: EventBus,
+    pub domain_events: DomainEvents,
+    pub startup: Startup,
+    pub maintenance: Maintenance,
+    pub features: Features,
+    pub rate_limits: RouteRateLimits,
+    pub idempotency_store: Arc<dyn IdempotencyStore>,
+    pub blob_store: Arc<dyn BlobStore>,
+    pub session_store: Arc<dyn SessionStore>,
+    pub response_cache: ResponseCache,
+    pub webhooks: Webhooks,
+    pub auditor: Auditor,
+    pub compute: Compute,
+    /// Handle for changing the logging filter, if logging has been initialized.
+    pub filter_handle: Option<FilterHandle>,
+}
+
+impl AppState {
+    /// Builder creating all components from the given [Settings] unless they are given
+    /// explicitly, e.g. fakes in tests.
+    pub fn builder(settings: Settings) -> AppStateBuilder {
+        AppStateBuilder {
+            settings,
+            http_client: None,
+            event_bus: None,
+            idempotency_store: None,
+            blob_store: None,
+            session_store: None,
+            delivery_store: None,
+            audit_sink: None,
+            filter_handle: None,
+        }
+    }
+}
+
+pub struct AppStateBuilder {
+    settings: Settings,
+    http_client: Option<HttpClient>,
+    event_bus: Option<EventBus>,
+    idempotency_store: Option<Arc<dyn IdempotencyStore>>,
+    blob_store: Option<Arc<dyn BlobStore>>,
+    session_store: Option<Arc<dyn SessionStore>>,
+    delivery_store: Option<Arc<dyn DeliveryStore>>,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    filter_handle: Option<FilterHandle>,
+}
+
+impl AppStateBuilder {
+    pub fn http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    pub fn event_bus(mut self, event_bus: EventBus) -> Self {
+        self.event_bus = Some(event_bus);
+        self
+    }
+
+    pub fn idempotency_store(mut self, idempotency_store: impl IdempotencyStore) -> Self {
+        self.idempotency_store = Some(Arc::new(idempotency_store));
+        self
+    }
+
+    pub fn blob_store(mut self, blob_store: impl BlobStore) -> Self {
+        self.blob_store = Some(Arc::new(blob_store));
+        self
+    }
+
+    pub fn session_store(mut self, session_store: impl SessionStore) -> Self {
+        self.session_store = Some(Arc::new(session_store));
+        self
+    }
+
+    pub fn delivery_store(mut self, delivery_store: impl DeliveryStore) -> Self {
+        self.delivery_store = Some(Arc::new(delivery_store));
+        self
+    }
+
+    pub fn audit_sink(mut self, audit_sink: impl AuditSink) -> Self {
+        self.audit_sink = Some(Arc::new(audit_sink));
+        self
+    }
+
+    pub fn filter_handle(mut self, filter_handle: FilterHandle) -> Self {
+        self.filter_handle = Some(filter_handle);
+        self
+    }
+
+    /// Fails if a component cannot be created, e.g. the [Compute] pool.
+    pub fn build(self) -> Result<AppState> {
+        let settings = self.settings;
+        let http_client = self
+            .http_client
+            .unwrap_or_else(|| HttpClient::new(&settings.http_client));
+        let event_bus = self
+            .event_bus
+            .unwrap_or_else(|| EventBus::new(settings.events.replay_capacity));
+        let domain_events = DomainEvents::new(settings.events.domain_capacity);
+        let maintenance = Maintenance::new(&settings.maintenance);
+        let features = Features::new(&settings.features);
+        let rate_limits = RouteRateLimits::new(&settings.rate_limiting);
+        let response_cache = ResponseCache::new(&settings.response_cache);
+        let idempotency_store = self
+            .idempotency_store
+            .unwrap_or_else(|| Arc::new(InMemoryIdempotencyStore::default()));
+        // Only used for uploads, which are disabled unless `uploads.dir` or `storage.s3` is
+        // defined.
+        let blob_store = self
+            .blob_store
+            .unwrap_or_else(|| match &settings.storage.s3 {
+                Some(s3) => Arc::new(S3BlobStore::new(s3, &settings.aws, http_client.clone())),
+                None => {
+                    let dir = settings
+                        .uploads
+                        .dir
+                        .as_deref()
+                        .unwrap_or_else(|| Path::new("uploads"));
+                    Arc::new(LocalBlobStore::new(dir))
+                }
+            });
+        let session_store = self
+            .session_store
+            .unwrap_or_else(|| Arc::new(InMemorySessionStore::default()));
+        let delivery_store = self.delivery_store.unwrap_or_else(|| {
+            Arc::new(InMemoryDeliveryStore::new(settings.webhooks.max_deliveries))
+        });
+        let webhooks = Webhooks::new(&settings.webhooks, &settings.http_client, delivery_store);
+        let audit_sink = self
+            .audit_sink
+            .unwrap_or_else(|| match &settings.audit.path {
+                Some(path) => Arc::new(FileAuditSink::new(path)),
+                None => Arc::new(LogAuditSink),
+            });
+        let auditor = Auditor::new(audit_sink);
+        let compute = Compute::new(&settings.compute)?;
+        Ok(AppState {
+            settings: Arc::new(settings),
+            http_client,
+            event_bus,
+            domain_events,
+            startup: Startup::default(),
+            maintenance,
+            features,
+            rate_limits,
+            idempotency_store,
+            blob_store,
+            session_store,
+            response_cache,
+            webhooks,
+            auditor,
+            compute,
+            filter_handle: self.filter_handle,
+        })
+    }
+}
@@ -0,0 +1,357 @@
+//! Serving static files, e.g. the bundle of a single page application (SPA), from the directory
+//! configured in [StaticFilesSettings].
+//!
+//! Precompressed variants, i.e. files with an additional `.br` or `.gz` extension, are preferred
+//! if the client accepts the respective encoding. Unknown paths of requests accepting HTML are
+//! answered with the root `index.html` if `spa_fallback` is enabled, such that client side
+//! routing works. `index.html` files must always be revalidated, all others may be cached for
+//! `max_age`. Only `GET` and `HEAD` requests are served, others are answered with 404 like unknown
+//! paths.
+
+use crate::error::Error;
+use crate::settings::StaticFilesSettings;
+use axum::body::{boxed, Body, StreamBody};
+use axum::http::header::{
+    ACCEPT, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE,
+    LAST_MODIFIED, VARY,
+};
+use axum::http::{HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::any;
+use axum::Router;
+use futures_util::stream;
+use percent_encoding::percent_decode_str;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{self, File};
+use tokio::io::AsyncReadExt;
+
+const INDEX_HTML: &str = "index.html";
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Mount the static files under the configured path, unless no directory is configured. Routes
+/// of the given [Router] take precedence if mounted at `/`.
+pub fn mount(app: Router, settings: &StaticFilesSettings) -> Router {
+    if settings.dir.is_none() {
+        return app;
+    }
+
+    let path = settings.path.trim_end_matches('/').to_owned();
+    let settings = Arc::new(settings.clone());
+    let service = any(move |request: Request<Body>| serve(request, settings.clone()));
+    if path.is_empty() {
+        app.fallback(service)
+    } else {
+        app.nest(&path, service)
+    }
+}
+
+async fn serve(request: Request<Body>, settings: Arc<StaticFilesSettings>) -> Response {
+    // Other methods are answered like unknown paths instead of with 405, as if not mounted.
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let dir = settings.dir.as_deref().unwrap_or_else(|| Path::new("."));
+    let mut path = match resolve(dir, request.uri().path()) {
+        Some(path) => path,
+        None => return Error::NotFound("File not found".to_string()).into_response(),
+    };
+    if fs::metadata(&path)
+        .await
+        .map_or(false, |metadata| metadata.is_dir())
+    {
+        path.push(INDEX_HTML);
+    }
+
+    let headers = request.headers();
+    let file = match open(&path, headers).await {
+        Some(file) => Some(file),
+        None if settings.spa_fallback && accepts_html(headers) => {
+            open(&dir.join(INDEX_HTML), headers).await
+        }
+        None => None,
+    };
+    match file {
+        Some(file) => respond(file, request.method(), &settings),
+        None => Error::NotFound("File not found".to_string()).into_response(),
+    }
+}
+
+/// The file for the given request path, `index.html` if it ends with a slash, or `None` if the
+/// path contains hidden or parent segments.
+fn resolve(dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let mut resolved = dir.to_path_buf();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        if segment.starts_with('.') || segment.contains('\\') {
+            return None;
+        }
+        resolved.push(segment);
+    }
+    if path.ends_with('/') {
+        resolved.push(INDEX_HTML);
+    }
+    Some(resolved)
+}
+
+struct StaticFile {
+    file: File,
+    metadata: Metadata,
+    content_type: &'static str,
+    content_encoding: Option<&'static str>,
+    is_index: bool,
+}
+
+/// Opens the precompressed variant of the given file if accepted and existing, else the file
+/// itself.
+async fn open(path: &Path, headers: &HeaderMap) -> Option<StaticFile> {
+    let accept_encoding = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    let variants = [("br", "br"), ("gzip", "gz")]
+        .into_iter()
+        .filter(|(encoding, _)| accepts(&accept_encoding, encoding))
+        .map(|(encoding, extension)| {
+            let mut path = path.as_os_str().to_owned();
+            path.push(".");
+            path.push(extension);
+            (Some(encoding), PathBuf::from(path))
+        })
+        .chain([(None, path.to_path_buf())]);
+
+    for (content_encoding, variant) in variants {
+        if let Ok(file) = File::open(&variant).await {
+            match file.metadata().await {
+                Ok(metadata) if metadata.is_file() => {
+                    return Some(StaticFile {
+                        file,
+                        metadata,
+                        content_type: content_type(path),
+                        content_encoding,
+                        is_index: path.file_name() == Some(INDEX_HTML.as_ref()),
+                    })
+                }
+                _ => continue,
+            }
+        }
+    }
+    None
+}
+
+fn respond(file: StaticFile, method: &Method, settings: &StaticFilesSettings) -> Response {
+    let StaticFile {
+        file,
+        metadata,
+        content_type,
+        content_encoding,
+        is_index,
+    } = file;
+
+    let mut response = if method == Method::HEAD {
+        Response::new(boxed(Body::empty()))
+    } else {
+        let chunks = stream::try_unfold(file, |mut file| async move {
+            let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+            let n = (&mut file)
+                .take(CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)
+                .await?;
+            Ok::<_, std::io::Error>((n > 0).then(|| (chunk, file)))
+        });
+        Response::new(boxed(StreamBody::new(chunks)))
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(metadata.len()));
+    headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+    if let Some(content_encoding) = content_encoding {
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(content_encoding));
+    }
+    let cache_control = if is_index {
+        "no-cache".to_string()
+    } else {
+        format!("public, max-age={}", settings.max_age.as_secs())
+    };
+    if let Ok(cache_control) = HeaderValue::from_str(&cache_control) {
+        headers.insert(CACHE_CONTROL, cache_control);
+    }
+    if let Some(last_modified) = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| HeaderValue::from_str(&httpdate::fmt_http_date(modified)).ok())
+    {
+        headers.insert(LAST_MODIFIED, last_modified);
+    }
+    response
+}
+
+/// Whether the given `Accept-Encoding` contains the given encoding without `q=0`.
+fn accepts(accept_encoding: &str, encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        params.next() == Some(encoding)
+            && !params.any(|param| matches!(param, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"))
+    })
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|accept| accept.contains("text/html"))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tower::ServiceExt;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("bayer-axum-static-{:016x}", rand::random::<u64>()))
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = Path::new("/srv/www");
+        let resolve = |path| resolve(dir, path);
+
+        assert_eq!(resolve("/app.js"), Some(dir.join("app.js")));
+        assert_eq!(
+            resolve("/assets/logo%20dark.svg"),
+            Some(dir.join("assets/logo dark.svg"))
+        );
+        assert_eq!(resolve("/"), Some(dir.join(INDEX_HTML)));
+        assert_eq!(resolve("/docs/"), Some(dir.join("docs").join(INDEX_HTML)));
+
+        // Absolute paths stay within the directory.
+        assert_eq!(resolve("//etc/passwd"), Some(dir.join("etc/passwd")));
+        assert_eq!(resolve("/%2Fetc%2Fpasswd"), Some(dir.join("etc/passwd")));
+
+        // Parent, hidden and backslash segments, also encoded.
+        for path in [
+            "/..",
+            "/../etc/passwd",
+            "/assets/../../etc/passwd",
+            "/%2e%2e/etc/passwd",
+            "/%2E%2E%2Fetc%2Fpasswd",
+            "/assets/..%2f..%2fetc",
+            "/.env",
+            "/.git/config",
+            "/..%5c..%5cetc",
+            "/a\\b",
+            "/%ff",
+        ] {
+            assert_eq!(resolve(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_content_type() {
+        let content_type = |path: &str| content_type(Path::new(path));
+
+        assert_eq!(content_type("index.html"), "text/html; charset=utf-8");
+        assert_eq!(content_type("INDEX.HTM"), "text/html; charset=utf-8");
+        assert_eq!(content_type("app.mjs"), "text/javascript; charset=utf-8");
+        assert_eq!(content_type("app.js.map"), "application/json");
+        assert_eq!(content_type("logo.svg"), "image/svg+xml");
+        assert_eq!(content_type("font.woff2"), "font/woff2");
+        assert_eq!(content_type("archive.tar"), "application/octet-stream");
+        assert_eq!(content_type("LICENSE"), "application/octet-stream");
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(INDEX_HTML), "<html></html>").unwrap();
+        fs::write(dir.join("app.js"), "let x = 42;").unwrap();
+        let settings = StaticFilesSettings {
+            dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let app = mount(Router::new(), &settings);
+        let send = |method: Method, path: &str, accept: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header(ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = send(Method::GET, "/app.js", "*/*").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(response.headers()[CACHE_CONTROL], "public, max-age=3600");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"let x = 42;");
+
+        let response = send(Method::HEAD, "/app.js", "*/*").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "11");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        // Unknown paths fall back to the root index.html for HTML requests only.
+        let response = send(Method::GET, "/users/42", "text/html").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CACHE_CONTROL], "no-cache");
+        let response = send(Method::GET, "/users/42", "*/*").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = send(Method::GET, "/%2e%2e/etc/passwd", "*/*")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Other methods fall through like unknown paths.
+        for path in ["/app.js", "/users/42"] {
+            let response = send(Method::POST, path, "text/html").await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
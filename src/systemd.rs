@@ -0,0 +1,83 @@
+//! Notifications of systemd, see `sd_notify(3)`: `READY=1` once the listeners are bound,
+//! `STOPPING=1` once shutting down and, if the watchdog is enabled via `WatchdogSec=`,
+//! `WATCHDOG=1` every half watchdog interval. Unless running under systemd with `Type=notify`,
+//! i.e. if `NOTIFY_SOCKET` is not defined, nothing is sent.
+
+use crate::log_error_chain;
+use anyhow::{Context, Result};
+use socket2::{Domain, SockAddr, Socket, Type};
+use std::env;
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::process;
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::debug;
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID: &str = "WATCHDOG_PID";
+
+pub fn ready() {
+    notify_or_log("READY=1");
+}
+
+pub fn stopping() {
+    notify_or_log("STOPPING=1");
+}
+
+/// Spawn a task sending `WATCHDOG=1` every half watchdog interval, if the watchdog is enabled for
+/// this process. As the pings are sent from the runtime, they stop if it gets stuck.
+pub fn spawn_watchdog() {
+    if let Some(watchdog_interval) = watchdog_interval() {
+        debug!(?watchdog_interval, "Pinging systemd watchdog");
+        tokio::spawn(async move {
+            let mut ping_interval = interval(watchdog_interval / 2);
+            loop {
+                ping_interval.tick().await;
+                notify_or_log("WATCHDOG=1");
+            }
+        });
+    }
+}
+
+/// Send the given state to systemd, which is a no-op if `NOTIFY_SOCKET` is not defined. Paths
+/// starting with `@` denote abstract sockets.
+pub fn notify(state: &str) -> Result<()> {
+    let path = match env::var_os(NOTIFY_SOCKET) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let mut path = path.into_vec();
+    if path.first() == Some(&b'@') {
+        path[0] = 0;
+    }
+
+    let addr = SockAddr::unix(OsString::from_vec(path)).context("Invalid NOTIFY_SOCKET")?;
+    let socket =
+        Socket::new(Domain::UNIX, Type::DGRAM, None).context("Cannot create notify socket")?;
+    socket
+        .send_to(state.as_bytes(), &addr)
+        .context("Cannot send to NOTIFY_SOCKET")?;
+    Ok(())
+}
+
+fn notify_or_log(state: &str) {
+    if let Err(e) = notify(state) {
+        log_error_chain(&format!("Cannot notify systemd with {state}"), e.as_ref());
+    }
+}
+
+/// The watchdog interval, if `WATCHDOG_USEC` is defined and `WATCHDOG_PID`, if defined, is the ID
+/// of this process.
+fn watchdog_interval() -> Option<Duration> {
+    let for_this_process = env::var(WATCHDOG_PID)
+        .map(|pid| pid.parse() == Ok(process::id()))
+        .unwrap_or(true);
+    env::var(WATCHDOG_USEC)
+        .ok()
+        .filter(|_| for_this_process)
+        .and_then(|usecs| usecs.parse().ok())
+        .filter(|usecs| *usecs > 0)
+        .map(Duration::from_micros)
+}
@@ -0,0 +1,68 @@
+//! Setup of the global tracing subscriber.
+
+use crate::log_error_chain;
+use crate::settings::{LogFormat, LoggingSettings, Settings};
+use anyhow::{Context, Result};
+use tokio::sync::watch;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// Handle to the reloadable filter of the global tracing subscriber.
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Initialize the global tracing subscriber with the configured format and filter or, if the
+/// latter is not defined or invalid, with the one from `RUST_LOG`.
+pub fn init(logging: &LoggingSettings) -> FilterHandle {
+    let (filter, filter_error) = match env_filter(&logging.filter) {
+        Ok(filter) => (filter, None),
+        Err(e) => (EnvFilter::from_default_env(), Some(e)),
+    };
+    let (filter, filter_handle) = reload::Layer::new(filter);
+
+    let registry = tracing_subscriber::registry().with(filter);
+    match logging.format {
+        LogFormat::Json => registry.with(fmt::layer().json()).init(),
+        LogFormat::Pretty => registry.with(fmt::layer().pretty()).init(),
+        LogFormat::Compact => registry.with(fmt::layer().compact()).init(),
+    }
+
+    if let Some(e) = filter_error {
+        log_error_chain("Falling back to RUST_LOG", e.as_ref());
+    }
+
+    filter_handle
+}
+
+/// Apply changes of the configured logging filter, e.g. from [config_watcher](crate::config_watcher).
+pub async fn apply_filter_changes(
+    mut settings_rx: watch::Receiver<Settings>,
+    filter_handle: FilterHandle,
+) {
+    let mut filter = settings_rx.borrow().logging.filter.clone();
+    while settings_rx.changed().await.is_ok() {
+        let new_filter = settings_rx.borrow().logging.filter.clone();
+        if new_filter == filter {
+            continue;
+        }
+        filter = new_filter;
+
+        let result = env_filter(&filter).and_then(|env_filter| {
+            filter_handle
+                .reload(env_filter)
+                .context("Cannot reload filter")
+        });
+        if let Err(e) = result {
+            log_error_chain("Cannot apply changed logging filter", e.as_ref());
+        }
+    }
+}
+
+fn env_filter(filter: &Option<String>) -> Result<EnvFilter> {
+    match filter {
+        Some(filter) => {
+            EnvFilter::try_new(filter).with_context(|| format!("Invalid logging filter {filter}"))
+        }
+        None => Ok(EnvFilter::from_default_env()),
+    }
+}
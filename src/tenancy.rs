@@ -0,0 +1,224 @@
+//! Multi-tenancy: the tenant of each API request is determined according to the configured
+//! strategy, see [TenancySettings], and put into the request extensions as [TenantId] and into
+//! the request span as `tenant_id`. Requests without a valid tenant are rejected with 400.
+
+use crate::auth::Claims;
+use crate::error::Error;
+use crate::settings::{TenancySettings, TenantStrategy};
+use axum::http::header::HOST;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use tracing::Span;
+
+const MAX_LEN: usize = 63;
+
+/// The ID of the tenant of the current request, consisting of at most 63 ASCII alphanumeric
+/// characters, dashes and underscores.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TenantId(String);
+
+impl TenantId {
+    pub fn parse(id: &str) -> Option<Self> {
+        let valid = !id.is_empty()
+            && id.len() <= MAX_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        valid.then(|| Self(id.to_owned()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for TenantId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Middleware determining the [TenantId] of requests according to the given settings.
+pub async fn resolve_tenant<B>(
+    mut request: Request<B>,
+    next: Next<B>,
+    settings: Arc<TenancySettings>,
+) -> Response {
+    let tenant_id = match &settings.strategy {
+        Some(TenantStrategy::Header) => request
+            .headers()
+            .get(&settings.header)
+            .and_then(|value| value.to_str().ok())
+            .and_then(TenantId::parse),
+        Some(TenantStrategy::Subdomain) => {
+            let domain = settings.domain.as_deref().unwrap_or_default();
+            host(&request)
+                .and_then(|host| subdomain(host, domain))
+                .and_then(TenantId::parse)
+        }
+        Some(TenantStrategy::Claim { name }) => request
+            .extensions()
+            .get::<Claims>()
+            .and_then(|claims| claims.other.get(name))
+            .and_then(|value| value.as_str())
+            .and_then(TenantId::parse),
+        None => return next.run(request).await,
+    };
+
+    match tenant_id {
+        Some(tenant_id) => {
+            Span::current().record("tenant_id", &tenant_id.as_str());
+            request.extensions_mut().insert(tenant_id);
+            next.run(request).await
+        }
+        None => Error::Validation("Cannot determine tenant".to_string()).into_response(),
+    }
+}
+
+/// The host without port from the `Host` header or, e.g. for HTTP/2, the URI.
+fn host<B>(request: &Request<B>) -> Option<&str> {
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| request.uri().host())?;
+    Some(host.rsplit_once(':').map(|(host, _)| host).unwrap_or(host))
+}
+
+/// The single label before the given domain, e.g. `acme` for `acme.example.com` and
+/// `example.com`.
+fn subdomain<'a>(host: &'a str, domain: &str) -> Option<&'a str> {
+    host.strip_suffix(domain)?
+        .strip_suffix('.')
+        .filter(|label| !label.contains('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::extract::Extension;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    /// An app responding with the tenant ID, authenticated with the given claims, if any.
+    fn app(strategy: TenantStrategy, claims: Option<Claims>) -> Router {
+        let settings = Arc::new(TenancySettings {
+            strategy: Some(strategy),
+            domain: Some("example.com".to_string()),
+            ..Default::default()
+        });
+        Router::new()
+            .route(
+                "/",
+                get(|Extension(tenant_id): Extension<TenantId>| async move { tenant_id.0 }),
+            )
+            .layer(middleware::from_fn(move |request, next| {
+                resolve_tenant(request, next, settings.clone())
+            }))
+            .layer(middleware::from_fn(
+                move |mut request: Request<Body>, next: Next<Body>| {
+                    if let Some(claims) = claims.clone() {
+                        request.extensions_mut().insert(claims);
+                    }
+                    next.run(request)
+                },
+            ))
+    }
+
+    fn claims(other: serde_json::Value) -> Claims {
+        Claims {
+            sub: Some("alice".to_string()),
+            iss: None,
+            aud: vec![],
+            exp: 0,
+            nbf: None,
+            iat: None,
+            other: serde_json::from_value(other).unwrap(),
+        }
+    }
+
+    /// The status and body of the response to a request with the given header.
+    async fn get_tenant(app: Router, header: (&str, &str)) -> (StatusCode, String) {
+        let request = Request::get("/")
+            .header(header.0, header.1)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_header() {
+        let app = || app(TenantStrategy::Header, None);
+
+        let response = get_tenant(app(), ("x-tenant-id", "acme")).await;
+        assert_eq!(response, (StatusCode::OK, "acme".to_string()));
+
+        let (status, _) = get_tenant(app(), ("x-tenant-id", "acme.corp")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = get_tenant(app(), ("x-other", "acme")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_subdomain() {
+        let app = || app(TenantStrategy::Subdomain, None);
+
+        let response = get_tenant(app(), ("host", "acme.example.com:8080")).await;
+        assert_eq!(response, (StatusCode::OK, "acme".to_string()));
+
+        for host in [
+            "example.com",
+            "a.acme.example.com",
+            "acme.example.org",
+            "acmeexample.com",
+        ] {
+            let (status, _) = get_tenant(app(), ("host", host)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{host}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_claim() {
+        let strategy = || TenantStrategy::Claim {
+            name: "tenant_id".to_string(),
+        };
+
+        let claims_app = app(strategy(), Some(claims(json!({ "tenant_id": "acme" }))));
+        let response = get_tenant(claims_app, ("x-tenant-id", "other")).await;
+        assert_eq!(response, (StatusCode::OK, "acme".to_string()));
+
+        for other in [
+            json!({}),
+            json!({ "tenant_id": 42 }),
+            json!({ "tenant_id": "a/b" }),
+        ] {
+            let claims_app = app(strategy(), Some(claims(other)));
+            let (status, _) = get_tenant(claims_app, ("x-tenant-id", "acme")).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+
+        // Without authentication, there are no claims.
+        let (status, _) = get_tenant(app(strategy(), None), ("x-tenant-id", "acme")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(TenantId::parse("acme-corp_1").is_some());
+        assert!(TenantId::parse(&"a".repeat(63)).is_some());
+        assert!(TenantId::parse(&"a".repeat(64)).is_none());
+        assert!(TenantId::parse("").is_none());
+        assert!(TenantId::parse("acme corp").is_none());
+    }
+}
@@ -0,0 +1,48 @@
+//! Listening on a Unix domain socket, e.g. behind a sidecar proxy.
+
+use anyhow::{bail, Context, Result};
+use hyper::server::accept::{self, Accept};
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::task::Poll;
+use tokio::net::{UnixListener, UnixStream};
+use tracing::warn;
+
+/// Bind a [UnixListener] to the given path, replacing a stale socket, and optionally set the
+/// permissions of the socket, e.g. `0o660`.
+pub fn bind(path: &Path, mode: Option<u32>) -> Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)
+            .with_context(|| format!("Cannot remove stale socket {}", path.display()))?,
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Cannot access {}", path.display()));
+        }
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Cannot bind to socket {}", path.display()))?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))
+            .with_context(|| format!("Cannot set permissions of socket {}", path.display()))?;
+    }
+    Ok(listener)
+}
+
+/// Accept connections from the given listener, to be used with [axum::Server::builder].
+pub fn accept(listener: UnixListener) -> impl Accept<Conn = UnixStream, Error = io::Error> {
+    accept::poll_fn(move |cx| match listener.poll_accept(cx) {
+        Poll::Ready(result) => Poll::Ready(Some(result.map(|(stream, _)| stream))),
+        Poll::Pending => Poll::Pending,
+    })
+}
+
+/// Remove the socket at the given path, logging failures.
+pub fn remove(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        warn!(path = %path.display(), error = %e, "Cannot remove socket");
+    }
+}
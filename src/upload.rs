@@ -0,0 +1,243 @@
+//! Scaffold for file uploads: `POST /api/v2/uploads` streams the files of a `multipart/form-data`
+//! body to the [BlobStore](crate::blob_store::BlobStore) of the [AppState], enforcing the limits
+//! of [UploadSettings](crate::settings::UploadSettings). Other fields are skipped.
+//!
+//! The route is only mounted if `uploads.dir` or `storage.s3` is defined, and it requires the
+//! client to be authenticated with an API key, a bearer token or a session, because uploads take
+//! up storage space.
+
+use crate::api_key::ApiKeyId;
+use crate::auth::Claims;
+use crate::error::{Error, Result};
+use crate::log_error_chain;
+use crate::multipart::Multipart;
+use crate::sessions::Session;
+use crate::state::AppState;
+use axum::extract::{BodyStream, Extension};
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::Serialize;
+
+/// Path of the upload route, which is exempt from `server.max_body_size`.
+pub const PATH: &str = "/api/v2/uploads";
+
+/// A stored file, which can be found in the blob store under its randomly generated key.
+#[derive(Debug, Serialize)]
+pub struct Upload {
+    pub field: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub key: String,
+    pub size: usize,
+}
+
+/// Responds with 201 and the stored files or with 413 if a limit is exceeded, in which case the
+/// files stored so far are deleted.
+pub async fn upload(
+    Extension(state): Extension<AppState>,
+    api_key_id: Option<Extension<ApiKeyId>>,
+    claims: Option<Extension<Claims>>,
+    session: Option<Extension<Session>>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> Result<(StatusCode, Json<Vec<Upload>>)> {
+    let authenticated = api_key_id.is_some()
+        || claims.is_some()
+        || session.map_or(false, |Extension(session)| session.is_established());
+    if !authenticated {
+        return Err(Error::Unauthorized(
+            "Uploads require an API key, a bearer token or a session".to_string(),
+        ));
+    }
+
+    let settings = &state.settings.uploads;
+    let content_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.map_or(false, |length| length > settings.max_total_size) {
+        return Err(total_too_large(settings.max_total_size));
+    }
+
+    let mut multipart = Multipart::new(body, &headers)?;
+    let mut uploads = Vec::new();
+    let mut total_size = 0;
+    let result = async {
+        while let Some(field) = multipart.next_field().await? {
+            if field.filename.is_none() {
+                while let Some(chunk) = multipart.chunk().await? {
+                    total_size += chunk.len();
+                    if total_size > settings.max_total_size {
+                        return Err(total_too_large(settings.max_total_size));
+                    }
+                }
+                continue;
+            }
+
+            let key = format!("{:032x}", rand::random::<u128>());
+            let mut writer = state.blob_store.create(&key).await?;
+            let mut size = 0;
+            let written = async {
+                while let Some(chunk) = multipart.chunk().await? {
+                    size += chunk.len();
+                    total_size += chunk.len();
+                    if size > settings.max_file_size {
+                        return Err(Error::PayloadTooLarge(format!(
+                            "Files must not exceed {} bytes",
+                            settings.max_file_size
+                        )));
+                    }
+                    if total_size > settings.max_total_size {
+                        return Err(total_too_large(settings.max_total_size));
+                    }
+                    writer.write(&chunk).await?;
+                }
+                Ok(())
+            }
+            .await;
+            match written {
+                Ok(()) => writer.complete().await?,
+                Err(e) => {
+                    if let Err(e) = writer.abort().await {
+                        log_error_chain("Cannot abort upload", e.as_ref());
+                    }
+                    return Err(e);
+                }
+            }
+
+            uploads.push(Upload {
+                field: field.name,
+                filename: field.filename,
+                content_type: field.content_type,
+                key,
+                size,
+            });
+        }
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => Ok((StatusCode::CREATED, Json(uploads))),
+        Err(e) => {
+            for upload in &uploads {
+                if let Err(e) = state.blob_store.delete(&upload.key).await {
+                    log_error_chain("Cannot delete upload", e.as_ref());
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+fn total_too_large(max_total_size: usize) -> Error {
+    Error::PayloadTooLarge(format!(
+        "Upload requests must not exceed {max_total_size} bytes"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob_store::LocalBlobStore;
+    use crate::settings::Settings;
+    use axum::body::Body;
+    use axum::http::header::CONTENT_TYPE;
+    use axum::http::Request;
+    use axum::routing::post;
+    use axum::{AddExtensionLayer, Router};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use tower::ServiceExt;
+
+    fn app(dir: &Path) -> Router {
+        let mut settings = Settings::default();
+        settings.uploads.max_file_size = 10;
+        settings.uploads.max_total_size = 25;
+        let state = AppState::builder(settings)
+            .blob_store(LocalBlobStore::new(dir))
+            .build()
+            .unwrap();
+        Router::new()
+            .route(PATH, post(upload))
+            .layer(AddExtensionLayer::new(ApiKeyId("test".to_string())))
+            .layer(AddExtensionLayer::new(state))
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("bayer-axum-uploads-{:016x}", rand::random::<u64>()))
+    }
+
+    /// A request with a file field for each of the given contents and a text field.
+    fn request(files: &[&str]) -> Request<Body> {
+        let mut body =
+            "--XyZ\r\nContent-Disposition: form-data; name=title\r\n\r\nx\r\n".to_string();
+        for (n, file) in files.iter().enumerate() {
+            body.push_str(&format!(
+                "--XyZ\r\nContent-Disposition: form-data; name=file{n}; filename=f{n}.txt\r\n\
+                 \r\n{file}\r\n"
+            ));
+        }
+        body.push_str("--XyZ--\r\n");
+        Request::post(PATH)
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=XyZ")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn test_max_file_size() {
+        let dir = temp_dir();
+
+        let response = app(&dir)
+            .oneshot(request(&["0123456789", ""]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(files(&dir), ["", "0123456789"]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The files stored before the one exceeding the limit are deleted.
+        let response = app(&dir)
+            .oneshot(request(&["a", "0123456789x"]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(files(&dir).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_max_total_size() {
+        let dir = temp_dir();
+
+        // 1 byte of the text field and 24 of files are within the limit, 26 in total are not.
+        let response = app(&dir)
+            .oneshot(request(&["0123456789", "0123456789", "abcd"]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let response = app(&dir)
+            .oneshot(request(&["0123456789", "0123456789", "abcde"]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(files(&dir).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,78 @@
+//! Validation of JSON request bodies: [ValidatedJson] deserializes the body like [Json] and then
+//! validates it via [Validate], rejecting invalid bodies with 422 and the errors per field.
+
+use crate::error::Error;
+use async_trait::async_trait;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, RequestParts};
+use axum::{BoxError, Json};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+
+/// Types which can be validated, adding an error for each invalid field.
+pub trait Validate {
+    fn validate(&self, errors: &mut FieldErrors);
+}
+
+/// Validation errors by field name, e.g. `name` or `address.city`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldErrors(BTreeMap<String, Vec<String>>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.entry(field.into()).or_default().push(message.into());
+    }
+
+    /// Adds the given error for the given field unless `valid` is true.
+    pub fn check(&mut self, valid: bool, field: impl Into<String>, message: impl Into<String>) {
+        if !valid {
+            self.add(field, message);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn into_inner(self) -> BTreeMap<String, Vec<String>> {
+        self.0
+    }
+}
+
+/// Extractor for JSON request bodies which are [Validate]d: bodies which cannot be deserialized
+/// or have no JSON content type are rejected with 400 and invalid ones with 422.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    B: axum::body::HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(request)
+            .await
+            .map_err(from_rejection)?;
+        let mut errors = FieldErrors::default();
+        value.validate(&mut errors);
+        if errors.is_empty() {
+            Ok(Self(value))
+        } else {
+            Err(Error::Unprocessable(errors))
+        }
+    }
+}
+
+fn from_rejection(rejection: JsonRejection) -> Error {
+    match rejection {
+        JsonRejection::MissingJsonContentType(_) | JsonRejection::InvalidJsonBody(_) => {
+            Error::Validation(rejection.to_string())
+        }
+        rejection => Error::Internal(rejection.into()),
+    }
+}
@@ -0,0 +1,88 @@
+//! Resolution of references to secrets in HashiCorp Vault, e.g.
+//! `password = "vault:secret/data/app#db_password"`, in settings values, see [resolve].
+
+use crate::settings::{flatten, VaultSettings};
+use anyhow::{anyhow, bail, Context, Result};
+use hyper::{body, Body, Client, Method, Request};
+use serde_json::Value;
+use std::collections::HashMap;
+use tokio::time::timeout;
+
+const PREFIX: &str = "vault:";
+
+const TOKEN_HEADER: &str = "x-vault-token";
+
+/// Dotted keys, e.g. `admin.password`, and references, i.e. `<path>#<key>` with the `vault:`
+/// prefix stripped, of all string values of the given configuration which reference secrets.
+pub fn references(config: Value) -> Vec<(String, String)> {
+    let mut values = Vec::new();
+    flatten(String::new(), config, &mut values);
+    values
+        .into_iter()
+        .filter_map(|(key, value)| match value {
+            Value::String(value) => value
+                .strip_prefix(PREFIX)
+                .map(|reference| (key, reference.to_owned())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Resolve the given references, see [references], into the dotted keys and the secrets. Each
+/// path is read once via `GET <addr>/v1/<path>` and the key is looked up in `data.data` for the
+/// KV version 2 secrets engine, whose paths contain `data`, or in `data` otherwise.
+pub async fn resolve(
+    references: Vec<(String, String)>,
+    settings: &VaultSettings,
+) -> Result<Vec<(String, String)>> {
+    let addr = settings.addr();
+    let token = settings
+        .token()
+        .ok_or_else(|| anyhow!("Neither vault.token nor VAULT_TOKEN defined"))?;
+    let client = Client::new();
+
+    let mut secrets = HashMap::new();
+    let mut resolved = Vec::with_capacity(references.len());
+    for (key, reference) in references {
+        let (path, secret_key) = reference
+            .split_once('#')
+            .ok_or_else(|| anyhow!("Invalid Vault reference {reference} for {key}"))?;
+
+        if !secrets.contains_key(path) {
+            let request = Request::builder()
+                .method(Method::GET)
+                .uri(format!("{}/v1/{path}", addr.trim_end_matches('/')))
+                .header(TOKEN_HEADER, &token)
+                .body(Body::empty())
+                .context("Cannot create Vault request")?;
+            let secret = timeout(settings.timeout, read(&client, request))
+                .await
+                .map_err(|_| anyhow!("Timeout reading {path} from Vault"))?
+                .with_context(|| format!("Cannot read {path} from Vault"))?;
+            secrets.insert(path.to_owned(), secret);
+        }
+
+        let data = &secrets[path]["data"];
+        let value = data["data"]
+            .get(secret_key)
+            .or_else(|| data.get(secret_key))
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("No string value for {secret_key} in {path} in Vault"))?;
+        resolved.push((key, value.to_owned()));
+    }
+
+    Ok(resolved)
+}
+
+async fn read(
+    client: &Client<hyper::client::HttpConnector>,
+    request: Request<Body>,
+) -> Result<Value> {
+    let response = client.request(request).await?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!("Vault responded with status {status}");
+    }
+    let body = body::to_bytes(response.into_body()).await?;
+    serde_json::from_slice(&body).context("Cannot parse response from Vault")
+}
@@ -0,0 +1,237 @@
+//! Verification of HMAC signatures of inbound webhooks, e.g. from GitHub like providers, for the
+//! routes configured in `webhook_signatures`: the body, or `<timestamp>.<body>` if a
+//! `timestamp_header` is configured, must be signed with the shared secret, otherwise the request
+//! is rejected with 401. Checking the timestamp against the `tolerance` prevents replays of
+//! captured requests.
+//!
+//! The timestamp and signature must be given in separate headers; a single header carrying both,
+//! e.g. `Stripe-Signature: t=<timestamp>,v1=<signature>`, is not supported.
+//!
+//! Verified requests are authenticated by their signature and hence do not need an API key.
+
+use crate::error::Error;
+use crate::hmac::{constant_time_eq, from_hex, hmac_sha256};
+use crate::settings::{SignatureAlgorithm, WebhookSignatureSettings};
+use axum::body::Body;
+use axum::extract::MatchedPath;
+use axum::http::{HeaderMap, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Marks requests with a verified webhook signature in the request extensions.
+#[derive(Debug, Clone, Copy)]
+pub struct VerifiedWebhook;
+
+/// Shared state of the [verify_signature] middleware.
+#[derive(Debug, Clone)]
+pub struct WebhookSignatures {
+    routes: Arc<BTreeMap<String, WebhookSignatureSettings>>,
+}
+
+impl WebhookSignatures {
+    /// The configured signature verification, if any routes are configured.
+    pub fn new(routes: &BTreeMap<String, WebhookSignatureSettings>) -> Option<Self> {
+        (!routes.is_empty()).then(|| Self {
+            routes: Arc::new(routes.clone()),
+        })
+    }
+}
+
+/// Middleware verifying the signatures of requests to the configured routes, see
+/// [crate::webhook_signature].
+pub async fn verify_signature(
+    request: Request<Body>,
+    next: Next<Body>,
+    signatures: WebhookSignatures,
+) -> Response {
+    let settings = match request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|route| signatures.routes.get(route.as_str()))
+    {
+        Some(settings) => settings.clone(),
+        None => return next.run(request).await,
+    };
+
+    let (mut parts, body) = request.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            return Error::Validation(format!("Cannot read request body: {e}")).into_response()
+        }
+    };
+    if let Err(e) = verify(&settings, &parts.headers, &body) {
+        return e.into_response();
+    }
+
+    parts.extensions.insert(VerifiedWebhook);
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+fn verify(
+    settings: &WebhookSignatureSettings,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), Error> {
+    let signature = header(headers, &settings.header)
+        .and_then(|value| value.strip_prefix(settings.prefix.as_str()))
+        .and_then(from_hex)
+        .ok_or_else(|| Error::Unauthorized(format!("Missing or invalid {}", settings.header)))?;
+
+    let mut message = Vec::new();
+    if let Some(timestamp_header) = &settings.timestamp_header {
+        let timestamp = header(headers, timestamp_header).unwrap_or_default();
+        let signed = timestamp
+            .parse()
+            .ok()
+            .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+            .ok_or_else(|| Error::Unauthorized(format!("Missing or invalid {timestamp_header}")))?;
+        let age = SystemTime::now()
+            .duration_since(signed)
+            .unwrap_or_else(|e| e.duration());
+        if age > settings.tolerance {
+            return Err(Error::Unauthorized(
+                "Signature timestamp outside of tolerance".to_string(),
+            ));
+        }
+        message.extend_from_slice(timestamp.as_bytes());
+        message.push(b'.');
+    }
+    message.extend_from_slice(body);
+
+    let expected = match settings.algorithm {
+        SignatureAlgorithm::HmacSha256 => {
+            hmac_sha256(settings.secret.expose().as_bytes(), &message)
+        }
+    };
+    if constant_time_eq(&expected, &signature) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized("Invalid signature".to_string()))
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hmac::to_hex;
+    use crate::secret::Secret;
+
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+
+    fn settings(timestamp_header: Option<&str>) -> WebhookSignatureSettings {
+        WebhookSignatureSettings {
+            secret: Secret::new(SECRET.to_string()),
+            prefix: "sha256=".to_string(),
+            timestamp_header: timestamp_header.map(ToOwned::to_owned),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the request with the given headers and body is verified.
+    fn is_verified(
+        settings: &WebhookSignatureSettings,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> bool {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect();
+        verify(settings, &headers, body).is_ok()
+    }
+
+    fn sign(message: &str) -> String {
+        to_hex(&hmac_sha256(SECRET.as_bytes(), message.as_bytes()))
+    }
+
+    #[test]
+    fn test_verify() {
+        let settings = settings(None);
+        let signature = format!("sha256={}", sign("Hello, World!"));
+        assert!(is_verified(&settings, &[("x-signature", &signature)], BODY));
+
+        // Wrong signatures or bodies.
+        assert!(!is_verified(
+            &settings,
+            &[("x-signature", &signature)],
+            b"Hello, World?"
+        ));
+        let wrong = format!("sha256={}", sign("Hello, World?"));
+        assert!(!is_verified(&settings, &[("x-signature", &wrong)], BODY));
+        assert!(!is_verified(
+            &settings,
+            &[("x-signature", "sha256=xyz")],
+            BODY
+        ));
+        assert!(!is_verified(&settings, &[], BODY));
+    }
+
+    #[test]
+    fn test_verify_prefix() {
+        let settings = settings(None);
+        let signature = sign("Hello, World!");
+
+        assert!(!is_verified(
+            &settings,
+            &[("x-signature", &signature)],
+            BODY
+        ));
+        let wrong_prefix = format!("sha1={signature}");
+        assert!(!is_verified(
+            &settings,
+            &[("x-signature", &wrong_prefix)],
+            BODY
+        ));
+    }
+
+    #[test]
+    fn test_verify_timestamp() {
+        let settings = settings(Some("x-timestamp"));
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let is_verified_at = |timestamp: u64, signed: u64| {
+            let signature = format!("sha256={}", sign(&format!("{signed}.Hello, World!")));
+            let timestamp = timestamp.to_string();
+            let headers = [
+                ("x-signature", signature.as_str()),
+                ("x-timestamp", &timestamp),
+            ];
+            is_verified(&settings, &headers, BODY)
+        };
+
+        assert!(is_verified_at(now, now));
+        assert!(is_verified_at(now - 60, now - 60));
+        assert!(is_verified_at(now + 60, now + 60));
+
+        // Timestamps outside of the tolerance.
+        assert!(!is_verified_at(now - 301, now - 301));
+        assert!(!is_verified_at(now + 301, now + 301));
+
+        // The timestamp is signed.
+        assert!(!is_verified_at(now, now - 1));
+
+        // Missing or invalid timestamps.
+        let signature = format!("sha256={}", sign(&format!("{now}.Hello, World!")));
+        assert!(!is_verified(
+            &settings,
+            &[("x-signature", &signature)],
+            BODY
+        ));
+        let headers = [("x-signature", signature.as_str()), ("x-timestamp", "now")];
+        assert!(!is_verified(&settings, &headers, BODY));
+    }
+}
@@ -0,0 +1,307 @@
+//! Outbound webhooks: [Webhooks::dispatch] delivers events to the configured subscribers
+//! asynchronously, retrying failed deliveries with jittered exponential backoff. Each attempt is
+//! recorded in a [DeliveryStore], which the admin endpoints expose.
+//!
+//! Payloads are POSTed as JSON and signed like verified by [crate::webhook_signature]: the
+//! `x-webhook-signature` header carries `sha256=` and the hex encoded HMAC-SHA256 of
+//! `<timestamp>.<body>` with the subscriber's secret, the `x-webhook-timestamp` header the Unix
+//! time of signing. Deliveries in progress are not resumed after a restart.
+//!
+//! Deliveries use their own [HttpClient], such that failing subscribers do not open circuits
+//! which are part of the readiness of this service.
+
+use crate::hmac::{hmac_sha256, to_hex};
+use crate::http_client::HttpClient;
+use crate::log_error_chain;
+use crate::metrics::increment_counter;
+use crate::settings::{HttpClientSettings, RetrySettings, SubscriberSettings, WebhooksSettings};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::Request;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+use tracing::debug;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub id: String,
+    pub subscriber: String,
+    pub event: String,
+    pub payload: Value,
+    pub status: DeliveryStatus,
+    /// Unix time in seconds.
+    pub created_at: u64,
+    pub attempts: Vec<Attempt>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Attempt {
+    /// Unix time in seconds.
+    pub at: u64,
+    pub duration_millis: u64,
+    /// Status code of the response, if any.
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// Keeps [Delivery]s and their attempts, e.g. in memory or in a database.
+#[async_trait]
+pub trait DeliveryStore: Debug + Send + Sync + 'static {
+    async fn insert(&self, delivery: Delivery) -> Result<()>;
+
+    /// Append the given attempt to the delivery with the given ID and update its status.
+    async fn record_attempt(
+        &self,
+        id: &str,
+        attempt: Attempt,
+        status: DeliveryStatus,
+    ) -> Result<()>;
+
+    async fn get(&self, id: &str) -> Result<Option<Delivery>>;
+
+    /// The most recent deliveries, newest first, optionally only those with the given status.
+    async fn list(&self, status: Option<DeliveryStatus>, limit: usize) -> Result<Vec<Delivery>>;
+}
+
+/// [DeliveryStore] in memory, keeping the given number of most recent deliveries.
+#[derive(Debug)]
+pub struct InMemoryDeliveryStore {
+    deliveries: Mutex<VecDeque<Delivery>>,
+    capacity: usize,
+}
+
+impl InMemoryDeliveryStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            deliveries: Default::default(),
+            capacity,
+        }
+    }
+
+    fn deliveries(&self) -> MutexGuard<'_, VecDeque<Delivery>> {
+        self.deliveries.lock().expect("deliveries can be locked")
+    }
+}
+
+#[async_trait]
+impl DeliveryStore for InMemoryDeliveryStore {
+    async fn insert(&self, delivery: Delivery) -> Result<()> {
+        let mut deliveries = self.deliveries();
+        if deliveries.len() >= self.capacity {
+            deliveries.pop_front();
+        }
+        deliveries.push_back(delivery);
+        Ok(())
+    }
+
+    async fn record_attempt(
+        &self,
+        id: &str,
+        attempt: Attempt,
+        status: DeliveryStatus,
+    ) -> Result<()> {
+        if let Some(delivery) = self
+            .deliveries()
+            .iter_mut()
+            .find(|delivery| delivery.id == id)
+        {
+            delivery.attempts.push(attempt);
+            delivery.status = status;
+        }
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Delivery>> {
+        Ok(self
+            .deliveries()
+            .iter()
+            .find(|delivery| delivery.id == id)
+            .cloned())
+    }
+
+    async fn list(&self, status: Option<DeliveryStatus>, limit: usize) -> Result<Vec<Delivery>> {
+        let deliveries = self
+            .deliveries()
+            .iter()
+            .rev()
+            .filter(|delivery| status.map_or(true, |status| delivery.status == status))
+            .take(limit)
+            .cloned()
+            .collect();
+        Ok(deliveries)
+    }
+}
+
+/// Cheap to clone, all clones share the subscribers and the [DeliveryStore].
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    subscribers: Arc<BTreeMap<String, SubscriberSettings>>,
+    retry: Arc<RetrySettings>,
+    http_client: HttpClient,
+    store: Arc<dyn DeliveryStore>,
+}
+
+impl Webhooks {
+    pub fn new(
+        settings: &WebhooksSettings,
+        http_client: &HttpClientSettings,
+        store: Arc<dyn DeliveryStore>,
+    ) -> Self {
+        Self {
+            subscribers: Arc::new(settings.subscribers.clone()),
+            retry: Arc::new(settings.retry.clone()),
+            http_client: HttpClient::new(http_client),
+            store,
+        }
+    }
+
+    pub fn store(&self) -> &Arc<dyn DeliveryStore> {
+        &self.store
+    }
+
+    /// Deliver the given event to all subscribers of its type in the background, returning the
+    /// IDs of the deliveries.
+    pub async fn dispatch<T>(&self, event: &str, payload: &T) -> Result<Vec<String>>
+    where
+        T: Serialize,
+    {
+        let payload = serde_json::to_value(payload).context("Cannot serialize payload")?;
+        let subscribers = self.subscribers.iter().filter(|(_, subscriber)| {
+            subscriber.events.is_empty() || subscriber.events.iter().any(|e| e == event)
+        });
+
+        let mut ids = Vec::new();
+        for (name, subscriber) in subscribers {
+            let delivery = Delivery {
+                id: to_hex(&rand::random::<[u8; 16]>()),
+                subscriber: name.to_owned(),
+                event: event.to_owned(),
+                payload: payload.clone(),
+                status: DeliveryStatus::Pending,
+                created_at: unix_time(),
+                attempts: vec![],
+            };
+            self.store
+                .insert(delivery.clone())
+                .await
+                .context("Cannot store delivery")?;
+            ids.push(delivery.id.clone());
+
+            let webhooks = self.clone();
+            let subscriber = subscriber.clone();
+            tokio::spawn(async move {
+                if let Err(e) = webhooks.deliver(delivery, subscriber).await {
+                    log_error_chain("Cannot record webhook delivery", e.as_ref());
+                }
+            });
+        }
+        Ok(ids)
+    }
+
+    async fn deliver(&self, delivery: Delivery, subscriber: SubscriberSettings) -> Result<()> {
+        let body = serde_json::to_vec(&delivery.payload).context("Cannot serialize payload")?;
+        let mut retries = 0;
+        loop {
+            let attempt = self.attempt(&delivery, &subscriber, &body).await;
+            let delivered = attempt
+                .status_code
+                .map_or(false, |status_code| (200..300).contains(&status_code));
+            let status = if delivered {
+                DeliveryStatus::Delivered
+            } else if retries == self.retry.max_retries {
+                DeliveryStatus::Failed
+            } else {
+                DeliveryStatus::Pending
+            };
+            debug!(
+                id = delivery.id.as_str(),
+                subscriber = delivery.subscriber.as_str(),
+                ?status,
+                "Webhook delivery attempted"
+            );
+            self.store
+                .record_attempt(&delivery.id, attempt, status)
+                .await?;
+
+            if status != DeliveryStatus::Pending {
+                let outcome = if delivered { "delivered" } else { "failed" };
+                increment_counter(
+                    "webhook_deliveries_total",
+                    &[
+                        ("subscriber", delivery.subscriber.clone()),
+                        ("outcome", outcome.to_string()),
+                    ],
+                );
+                return Ok(());
+            }
+            sleep(self.retry.backoff(retries)).await;
+            retries += 1;
+        }
+    }
+
+    async fn attempt(
+        &self,
+        delivery: &Delivery,
+        subscriber: &SubscriberSettings,
+        body: &[u8],
+    ) -> Attempt {
+        let at = unix_time();
+        let start = Instant::now();
+        let signed = [at.to_string().as_bytes(), b".", body].concat();
+        let signature = hmac_sha256(subscriber.secret.expose().as_bytes(), &signed);
+        let request = Request::post(&subscriber.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header("x-webhook-id", &delivery.id)
+            .header("x-webhook-event", &delivery.event)
+            .header("x-webhook-timestamp", at)
+            .header(
+                "x-webhook-signature",
+                format!("sha256={}", to_hex(&signature)),
+            )
+            .body(Body::from(body.to_vec()))
+            .context("Cannot create request");
+
+        let result = match request {
+            Ok(request) => self.http_client.request(request).await,
+            Err(e) => Err(e),
+        };
+        let (status_code, error) = match result {
+            Ok(response) if response.status().is_success() => (Some(response.status()), None),
+            Ok(response) => {
+                let status = response.status();
+                (
+                    Some(status),
+                    Some(format!("Unexpected status code {status}")),
+                )
+            }
+            Err(e) => (None, Some(format!("{e:#}"))),
+        };
+        Attempt {
+            at,
+            duration_millis: start.elapsed().as_millis() as u64,
+            status_code: status_code.map(|status_code| status_code.as_u16()),
+            error,
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
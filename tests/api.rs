@@ -0,0 +1,71 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use bayer_axum::module::Modules;
+use bayer_axum::{app, AppState, Settings};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn test_root() {
+    let response = app(
+        &AppState::builder(Settings::default()).build().unwrap(),
+        &Modules::default(),
+    )
+    .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"Habe die Ehre!");
+}
+
+#[tokio::test]
+async fn test_readyz() {
+    let response = app(
+        &AppState::builder(Settings::default()).build().unwrap(),
+        &Modules::default(),
+    )
+    .oneshot(
+        Request::builder()
+            .uri("/readyz")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_static_files_in_maintenance() {
+    let dir = std::env::temp_dir().join(format!("bayer-axum-static-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("hello.txt"), "Servus!").unwrap();
+    let mut settings = Settings::default();
+    settings.static_files.dir = Some(dir.clone());
+    settings.static_files.path = "/app".to_string();
+    let state = AppState::builder(settings).build().unwrap();
+    let request = || {
+        Request::builder()
+            .uri("/app/hello.txt")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app(&state, &Modules::default())
+        .oneshot(request())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut maintenance = state.maintenance.get();
+    maintenance.enabled = true;
+    state.maintenance.set(maintenance);
+    let response = app(&state, &Modules::default())
+        .oneshot(request())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,80 @@
+use bayer_axum::Settings;
+use std::env;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_list_from_env() {
+    env::set_var(
+        "APP__CORS__ALLOWED_ORIGINS",
+        "https://a.example.com, https://b.example.com",
+    );
+    env::set_var("APP__CORS__ALLOWED_HEADERS", r"x-a\,b,x-c");
+    env::set_var("APP__SERVER__ADDR", "127.0.0.1,::1");
+
+    let settings = Settings::load(Path::new("config"), &[]).await.unwrap();
+    assert_eq!(
+        settings.cors.allowed_origins,
+        vec!["https://a.example.com", "https://b.example.com"]
+    );
+    assert_eq!(settings.cors.allowed_headers, vec!["x-a,b", "x-c"]);
+    assert_eq!(
+        settings.server.addr,
+        vec![
+            "127.0.0.1".parse::<IpAddr>().unwrap(),
+            "::1".parse::<IpAddr>().unwrap()
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_map_from_env() {
+    env::set_var("APP__API_KEYS__CI_BOT__KEY", "secret");
+    env::set_var("APP__HTTP_CLIENT__TARGETS__BACKEND__TIMEOUT", "5s");
+
+    let settings = Settings::load(Path::new("config"), &[]).await.unwrap();
+    assert_eq!(settings.api_keys["ci_bot"].key.expose(), "secret");
+    assert_eq!(
+        settings.http_client.targets["backend"].timeout,
+        Some(Duration::from_secs(5))
+    );
+}
+
+#[tokio::test]
+async fn test_renamed_keys() {
+    let overrides = [
+        ("cors.max_age_secs", "60".to_string()),
+        ("http_client.targets.legacy.timeout_secs", "5".to_string()),
+    ];
+
+    let error = Settings::load(Path::new("config"), &overrides)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains(
+        "cors.max_age_secs: was renamed to cors.max_age, which takes a duration like 30s"
+    ));
+    assert!(error.contains(
+        "http_client.targets.legacy.timeout_secs: was renamed to \
+         http_client.targets.legacy.timeout"
+    ));
+}
+
+#[tokio::test]
+async fn test_unsupported_keys() {
+    let overrides = [
+        ("auth.jwks_url", "http://idp/jwks".to_string()),
+        (
+            "auth.keys.k1.public_key",
+            "-----BEGIN PUBLIC KEY-----".to_string(),
+        ),
+    ];
+
+    let error = Settings::load(Path::new("config"), &overrides)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("auth.jwks_url: is not supported"));
+    assert!(error.contains("auth.keys.k1.public_key: is not supported, only HS256 keys are"));
+}